@@ -1,9 +1,33 @@
-use std::{io::Write, path::Path};
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use indexer::index_builder::binary_format;
 
 pub struct Config {
     pub index_path: String,
     pub personal_data: String,
     pub app_config_path: String,
+    /// Whether stop words are left out of the index, set `STOP_WORDS='false'`
+    /// to keep them, e.g. for code-heavy notes
+    pub stop_words: bool,
+    /// Whether accents are ignored, so "espanol" finds "español", set
+    /// `FOLD_DIACRITICS='false'` to tell them apart
+    pub fold_diacritics: bool,
+    /// Whether all the notes are split into identifiers like code is, set
+    /// `SPLIT_IDENTIFIERS='true'` when they are mostly code
+    pub split_identifiers: bool,
+    /// The `tokenizer.json` file used to split the notes into subword units,
+    /// set with `TOKENIZER_PATH`, only used with the `subwords` feature
+    pub tokenizer_path: Option<String>,
+    /// The stages of the analyzer, like `ANALYZER='lowercase,stop_words,stem,ascii_fold'`,
+    /// the default pipeline if it isn't set
+    pub analyzer: Option<String>,
+    /// Whether the prefixes of the terms are stored too, so `look for --prefix`
+    /// answers partially typed words without scanning the index, set
+    /// `EDGE_NGRAMS='true'` to enable them at the next `look reindex`
+    pub edge_ngrams: bool,
 }
 
 pub fn load_config() -> Config {
@@ -23,7 +47,7 @@ pub fn load_config() -> Config {
 
     // Load the config file with Config::builder
     let settings_path = config_path.clone() + "/.env";
-    let index_path = config_path.clone() + "/index.json";
+    let index_path = config_path.clone() + "/index.bin";
     let personal_data = config_path.clone() + "/personal_data";
 
     // Create a new config file if it doesn't exist
@@ -47,6 +71,53 @@ pub fn load_config() -> Config {
         index_path: std::env::var("INDEX_PATH").unwrap(),
         personal_data: std::env::var("PERSONAL_DATA").unwrap(),
         app_config_path: config_path,
+        stop_words: env_flag("STOP_WORDS", true),
+        fold_diacritics: env_flag("FOLD_DIACRITICS", true),
+        split_identifiers: env_flag("SPLIT_IDENTIFIERS", false),
+        tokenizer_path: std::env::var("TOKENIZER_PATH").ok(),
+        analyzer: std::env::var("ANALYZER").ok(),
+        edge_ngrams: env_flag("EDGE_NGRAMS", false),
+    }
+}
+
+/// Function to warn about an `INDEX_PATH` still naming the `index.json` file of
+/// older versions once it holds a binary index
+///
+/// Older versions of look saved the index as JSON to `index.json`, a `look
+/// reindex` now saves it in the binary format to the same path, where it
+/// could be mistaken for an export.
+///
+/// # Arguments
+///  * `index_path` - The configured index path
+///  * `app_config_path` - The directory of the `.env` file
+///
+/// # Returns
+///  * The warning to show, `None` if the path is fine
+pub fn index_path_warning(index_path: &str, app_config_path: &str) -> Option<String> {
+    let path = Path::new(index_path);
+    if path.extension()? != "json" {
+        return None;
+    }
+    let mut magic = [0; binary_format::MAGIC.len()];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .ok()?;
+    binary_format::is_binary_index(&magic).then(|| {
+        format!(
+            "Warning: {} holds a binary index, move it to {} and set INDEX_PATH to the new path in {}/.env.",
+            index_path,
+            path.with_extension("bin").display(),
+            app_config_path
+        )
+    })
+}
+
+/// Reads an on/off setting set to 'true' or 'false', `default` if it isn't set
+fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+        Ok(value) if value.eq_ignore_ascii_case("true") => true,
+        Ok(value) if value.eq_ignore_ascii_case("false") => false,
+        _ => default,
     }
 }
 
@@ -69,4 +140,38 @@ mod tests {
             "Users/andrescrucettanieto/Library/Application Support/looker-cli/personal_data"
         );
     }
+
+    #[test]
+    fn test_index_path_warning() {
+        let dir = std::env::temp_dir().join("look-test-index-path-warning");
+        std::fs::create_dir_all(&dir).unwrap();
+        let app_config_path = dir.to_str().unwrap();
+        let json_path = dir.join("index.json");
+        let bin_path = dir.join("index.bin");
+
+        // A missing file or a JSON index is left alone
+        let _ = std::fs::remove_file(&json_path);
+        assert_eq!(
+            index_path_warning(json_path.to_str().unwrap(), app_config_path),
+            None
+        );
+        std::fs::write(&json_path, "{\"format_version\": 4}").unwrap();
+        assert_eq!(
+            index_path_warning(json_path.to_str().unwrap(), app_config_path),
+            None
+        );
+
+        // A binary index saved to the old JSON path is reported
+        std::fs::write(&json_path, binary_format::MAGIC).unwrap();
+        let warning = index_path_warning(json_path.to_str().unwrap(), app_config_path).unwrap();
+        assert!(warning.contains(bin_path.to_str().unwrap()));
+
+        std::fs::write(&bin_path, binary_format::MAGIC).unwrap();
+        assert_eq!(
+            index_path_warning(bin_path.to_str().unwrap(), app_config_path),
+            None
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
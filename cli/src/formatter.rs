@@ -1,6 +1,10 @@
 use ansi_term::Colour::{Blue, Yellow};
 
-use indexer::{index_builder::file_processing::read_file_contents, search_query::SearchResult};
+use indexer::{
+    data_ingestion::text_processing::{fold_diacritics, is_unsegmented},
+    index_builder::file_processing::read_file_contents,
+    search_query::SearchResult,
+};
 use percent_encoding::{percent_encode, AsciiSet, CONTROLS};
 use std::{
     collections::HashMap,
@@ -43,6 +47,22 @@ pub fn format_cli_output(results: Vec<SearchResult>) -> String {
     output
 }
 
+/// Format the message shown when a query has no results
+///
+/// The final format will be:
+///
+/// No results. Did you mean: "kubernetes upgrade", "kubernetes upgrades"?
+pub fn format_no_results(suggestions: &[String]) -> String {
+    if suggestions.is_empty() {
+        return "No results.".to_owned();
+    }
+    let suggestions: Vec<String> = suggestions
+        .iter()
+        .map(|suggestion| format!("\"{}\"", Yellow.bold().paint(suggestion)))
+        .collect();
+    format!("No results. Did you mean: {}?", suggestions.join(", "))
+}
+
 /// Print the lines of code that match the query
 ///
 /// We have a Vec<Search Results> object that includes
@@ -59,11 +79,11 @@ fn get_line_matches(path: &str, queried_tokens: Vec<String>) -> HashMap<usize, S
     for (line_number, line) in file_contents.lines().enumerate() {
         for token in queried_tokens.iter() {
             // We want to check for all cases of the token:
-            // e.g., Test, test, TEST
-            // We will lowercase the line and the token
-            let line = line.to_lowercase();
-            let token = token.to_lowercase();
-            if line.contains(&token) {
+            // e.g., Test, test, TEST, and with or without accents
+            // We will fold the line and the token
+            let folded_line = fold_line(line).text;
+            let token = fold_token(token);
+            if folded_line.contains(&token) {
                 line_matches.insert(line_number, line.to_string());
             }
         }
@@ -76,7 +96,7 @@ fn get_line_matches(path: &str, queried_tokens: Vec<String>) -> HashMap<usize, S
 /// We want to make the line number green "70:"
 /// and the rest of the text white.
 ///
-/// We also want to highlight as dark blue the matches in the words.
+/// We also want to highlight in yellow the matches in the words.
 fn format_line_match(
     line_matches: HashMap<usize, String>,
     queried_tokens: Vec<String>,
@@ -88,16 +108,107 @@ fn format_line_match(
         let line_number = Blue.bold().paint(line_number).to_string();
         formatted_line.push_str(&line_number);
         formatted_line.push(' ');
-        for token in queried_tokens.iter() {
-            let bold_token = Yellow.bold().paint(token).to_string();
-            let line = line.replace(token.as_str(), &format!("{}", bold_token));
-            formatted_line.push_str(&line);
-        }
+        formatted_line.push_str(&highlight_matches(&line, &queried_tokens));
         formatted_line_matches.push(formatted_line);
     }
     formatted_line_matches
 }
 
+/// Highlight every match of the queried tokens in a line
+///
+/// Matches are case insensitive. Overlapping matches are merged, so a
+/// phrase is highlighted as a whole instead of word by word. The query terms
+/// are stemmed, so a match runs to the end of the word it starts. The terms
+/// may have lost their accents, so the matches are found in the folded line,
+/// but the line is shown as it was written.
+fn highlight_matches(line: &str, queried_tokens: &[String]) -> String {
+    let folded_line = fold_line(line);
+
+    let tokens: Vec<String> = queried_tokens
+        .iter()
+        .map(|token| fold_token(token))
+        .filter(|token| !token.is_empty())
+        .collect();
+
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for token in &tokens {
+        for (start, _) in folded_line.text.match_indices(token.as_str()) {
+            let mut end = start + token.len();
+            // Text without spaces is searched by pairs of characters, there
+            // is no word to extend the match to
+            if !is_unsegmented(token) {
+                end = folded_line.text[end..]
+                    .find(|character: char| !character.is_alphanumeric())
+                    .map_or(folded_line.text.len(), |offset| end + offset);
+            }
+            ranges.push(folded_line.original_range(start, end));
+        }
+    }
+
+    // Overlapping matches, like a phrase and its words or the pairs of
+    // characters of a text without spaces, are highlighted as one
+    ranges.sort();
+    let mut merged_ranges: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged_ranges.last_mut() {
+            Some((_, last_end)) if start < *last_end => *last_end = (*last_end).max(end),
+            _ => merged_ranges.push((start, end)),
+        }
+    }
+
+    let mut highlighted_line = String::new();
+    let mut last_end = 0;
+    for (start, end) in merged_ranges {
+        highlighted_line.push_str(&line[last_end..start]);
+        highlighted_line.push_str(&Yellow.bold().paint(&line[start..end]).to_string());
+        last_end = end;
+    }
+    highlighted_line.push_str(&line[last_end..]);
+    highlighted_line
+}
+
+/// A line lowercased and without accents, to find the query terms in it
+struct FoldedLine {
+    text: String,
+    /// The offset in the original line of the character each byte of the
+    /// text comes from, followed by the length of the original line
+    offsets: Vec<usize>,
+}
+
+impl FoldedLine {
+    /// Returns the range of the original line a range of the folded text comes from
+    fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        // The end may fall inside the characters a single character folded
+        // into, the whole original character is kept
+        let last = self.offsets[end - 1];
+        let end = self.offsets[end..]
+            .iter()
+            .find(|&&offset| offset != last)
+            .copied()
+            .unwrap_or(last);
+        (self.offsets[start], end)
+    }
+}
+
+/// Lowercases the line and removes its accents character by character,
+/// remembering where each character comes from
+fn fold_line(line: &str) -> FoldedLine {
+    let mut text = String::new();
+    let mut offsets = Vec::new();
+    for (offset, character) in line.char_indices() {
+        let folded = fold_token(&character.to_string());
+        text.push_str(&folded);
+        offsets.resize(offsets.len() + folded.len(), offset);
+    }
+    offsets.push(line.len());
+    FoldedLine { text, offsets }
+}
+
+/// Lowercases a query token and removes its accents
+fn fold_token(token: &str) -> String {
+    fold_diacritics(&token.to_lowercase())
+}
+
 /// Format the path with respect to the current paths
 fn get_relative_path(path: &str) -> String {
     // We want to print the relative_path with
@@ -150,8 +261,57 @@ mod tests {
         let queried_tokens = vec!["test".to_string()];
         let formatted_line_matches = format_line_match(line_matches, queried_tokens);
         let expected =
-            vec!["\x1B[1;34m3:\x1B[0m This is a \x1B[1;34mtest\x1B[0m sentence.".to_string()];
+            vec!["\x1B[1;34m3:\x1B[0m This is a \x1B[1;33mtest\x1B[0m sentence.".to_string()];
         print!("{:?}", formatted_line_matches);
         assert_eq!(formatted_line_matches, expected);
     }
+
+    #[test]
+    fn test_highlight_phrase() {
+        let queried_tokens = vec!["blue car".to_string(), "car".to_string()];
+        let highlighted = super::highlight_matches("The Blue car and the red car", &queried_tokens);
+        assert_eq!(
+            highlighted,
+            "The \x1B[1;33mBlue car\x1B[0m and the red \x1B[1;33mcar\x1B[0m"
+        );
+    }
+
+    #[test]
+    fn test_highlight_stems() {
+        let queried_tokens = vec!["deploy".to_string()];
+        let highlighted = super::highlight_matches("Deploying, deployed.", &queried_tokens);
+        assert_eq!(
+            highlighted,
+            "\x1B[1;33mDeploying\x1B[0m, \x1B[1;33mdeployed\x1B[0m."
+        );
+    }
+
+    #[test]
+    fn test_highlight_folded_matches() {
+        // The line is shown with its accents and case
+        let queried_tokens = vec!["espanol".to_string(), "cafe".to_string()];
+        let highlighted =
+            super::highlight_matches("Un Café en Español, cafe\u{301}", &queried_tokens);
+        assert_eq!(
+            highlighted,
+            "Un \x1B[1;33mCafé\x1B[0m en \x1B[1;33mEspañol\x1B[0m, \x1B[1;33mcafe\u{301}\x1B[0m"
+        );
+    }
+
+    #[test]
+    fn test_highlight_text_without_spaces() {
+        let queried_tokens = vec!["数据".to_string(), "据中".to_string()];
+        let highlighted = super::highlight_matches("上海数据中心", &queried_tokens);
+        assert_eq!(highlighted, "上海\x1B[1;33m数据中\x1B[0m心");
+    }
+
+    #[test]
+    fn test_format_no_results() {
+        assert_eq!(super::format_no_results(&[]), "No results.");
+        let suggestions = vec!["kubernetes".to_string(), "kubernetes~1".to_string()];
+        assert_eq!(
+            super::format_no_results(&suggestions),
+            "No results. Did you mean: \"\x1B[1;33mkubernetes\x1B[0m\", \"\x1B[1;33mkubernetes~1\x1B[0m\"?"
+        );
+    }
 }
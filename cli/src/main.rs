@@ -1,7 +1,15 @@
-use clap::{arg, command, Command};
+use clap::{arg, command, ArgAction, Command};
 use config::Config;
-use indexer::index_builder::file_processing::process_directory;
-use indexer::index_builder::Index;
+#[cfg(feature = "subwords")]
+use indexer::data_ingestion::subwords::SubwordTokenizer;
+use indexer::data_ingestion::{load_stop_words, parse_pipeline, Analyzer, Lang, Stage};
+use indexer::index_builder::edge_ngrams::{
+    DEFAULT_EDGE_NGRAM_MAX_LENGTH, DEFAULT_EDGE_NGRAM_MIN_LENGTH,
+};
+use indexer::index_builder::file_processing::{
+    default_jobs, update_directory_with_options, WalkOptions,
+};
+use indexer::index_builder::{Index, IndexLock, IndexReader, SearchIndex};
 use indexer::search_query;
 
 use std::env;
@@ -19,28 +27,169 @@ fn main() {
             Command::new("for")
                 .about("Find a document with a query")
                 .arg(arg!([QUERY]))
+                .arg(
+                    arg!(--scorer <SCORER> "The ranking function used to sort the results")
+                        .value_parser(["bm25", "tfidf"])
+                        .default_value("bm25"),
+                )
+                .arg(
+                    arg!(--k1 <K1> "How quickly repeating a term stops raising the BM25 score, 1.2 by default")
+                        .value_parser(parse_k1),
+                )
+                .arg(
+                    arg!(--b <B> "How much long notes are penalized by BM25, from 0 to 1, 0.75 by default")
+                        .value_parser(parse_b),
+                )
+                .arg(arg!(--proximity "Rank higher the documents where the terms appear close together"))
+                .arg(arg!(--fuzzy "Also match the terms a few typos away from the query terms"))
+                .arg(arg!(--autocorrect "Search for the top suggestion when there are no results"))
+                .arg(arg!(--"no-synonyms" "Only search for the terms of the query, not their synonyms"))
+                .arg(arg!(--prefix "Also match the words starting with the last word of the query, as it is being typed"))
+                .arg(
+                    arg!(--"max-expansions" <COUNT> "The maximum number of terms a wildcard or fuzzy term expands to")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("50"),
+                )
+                .arg(
+                    arg!(--lang <LANG> "The language of the query, like \"spa\" or \"Spanish\", detected if not set")
+                        .value_parser(parse_language),
+                )
                 .arg_required_else_help(true),
         )
-        .subcommand(Command::new("reindex").about("Re-index a directory"))
+        .subcommand(
+            Command::new("reindex")
+                .about("Re-index a directory")
+                .arg(
+                    arg!(--include <GLOB> "Only index the files matching this glob, like \"*.md\"")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    arg!(--exclude <GLOB> "Skip the files and folders matching this glob")
+                        .action(ArgAction::Append),
+                )
+                .arg(arg!(--"no-ignore" "Also index the files listed in .gitignore and .lookignore, and the hidden ones"))
+                .arg(
+                    arg!(-j --jobs <N> "The number of threads reading and analyzing the files, one per CPU core by default")
+                        .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+                ),
+        )
+        .subcommand(Command::new("stats").about("Show the size of the index"))
+        .subcommand(
+            Command::new("export")
+                .about("Export the index as JSON, to inspect it")
+                .arg(arg!(<PATH> "The JSON file to write")),
+        )
         .get_matches();
 
+    // `look reindex` warns once the index is saved
+    if matches.subcommand_name() != Some("reindex") {
+        warn_about_index_path(&config);
+    }
+
     match matches.subcommand() {
         Some(("for", matches)) => {
-            let index = Index::load_index_from_json_file(Path::new(&config.index_path))
-                .expect("Failed to load index");
+            let analyzer = match build_analyzer(&config) {
+                Ok(analyzer) => analyzer,
+                Err(e) => {
+                    println!("Error occurred: {}", e);
+                    return;
+                }
+            };
+            let index = match open_index(Path::new(&config.index_path), analyzer) {
+                Ok(index) => index,
+                Err(e) => {
+                    println!("Error occurred: {}", e);
+                    return;
+                }
+            };
             let query = matches.get_one::<String>("QUERY").unwrap();
-            match search(query, index) {
+            let scorer = match build_scorer(
+                matches.get_one::<String>("scorer").unwrap(),
+                matches.get_one::<f64>("k1").copied(),
+                matches.get_one::<f64>("b").copied(),
+            ) {
+                Ok(scorer) => scorer,
+                Err(e) => {
+                    println!("Error occurred: {}", e);
+                    return;
+                }
+            };
+            let proximity = matches.get_flag("proximity");
+            let options = search_query::SearchOptions {
+                max_expansions: *matches.get_one::<usize>("max-expansions").unwrap(),
+                fuzzy: matches.get_flag("fuzzy"),
+                language: matches.get_one::<Lang>("lang").copied(),
+                synonyms: !matches.get_flag("no-synonyms"),
+                prefix: matches.get_flag("prefix"),
+            };
+            let autocorrect = matches.get_flag("autocorrect");
+            match search(
+                query,
+                index.as_ref(),
+                scorer,
+                proximity,
+                &options,
+                autocorrect,
+            ) {
                 Ok(_) => {}
                 Err(e) => println!("Error occurred: {}", e),
             }
         }
-        Some(("reindex", _matches)) => {
-            reindex(config);
+        Some(("reindex", matches)) => {
+            let globs = |name: &str| -> Vec<String> {
+                matches
+                    .get_many::<String>(name)
+                    .map(|globs| globs.cloned().collect())
+                    .unwrap_or_default()
+            };
+            let options = WalkOptions {
+                ignore_files: !matches.get_flag("no-ignore"),
+                include: globs("include"),
+                exclude: globs("exclude"),
+            };
+            let jobs = matches
+                .get_one::<usize>("jobs")
+                .copied()
+                .unwrap_or_else(default_jobs);
+            reindex(config, &options, jobs);
+        }
+        Some(("stats", _matches)) => {
+            match Index::load_index_from_file(Path::new(&config.index_path)) {
+                Ok(index) => println!("{}", index.stats()),
+                Err(e) => println!("Error occurred: {}", e),
+            }
+        }
+        Some(("export", matches)) => {
+            let export_path = matches.get_one::<String>("PATH").unwrap();
+            let exported = Index::load_index_from_file(Path::new(&config.index_path))
+                .and_then(|index| index.save_index_to_json_file(Path::new(export_path)));
+            match exported {
+                Ok(_) => println!("Index exported to {}.", export_path),
+                Err(e) => println!("Error occurred: {}", e),
+            }
         }
         _ => unreachable!(),
     }
 }
 
+/// Opens the index file for a search
+///
+/// The index file is memory-mapped and only the terms of the query are read,
+/// the JSON exports are loaded whole.
+fn open_index(path: &Path, analyzer: Analyzer) -> std::io::Result<Box<dyn SearchIndex>> {
+    match IndexReader::open(path) {
+        Ok(mut reader) => {
+            reader.analyzer = analyzer;
+            Ok(Box::new(reader))
+        }
+        Err(_) => {
+            let mut index = Index::load_index_from_file(path)?;
+            index.analyzer = analyzer;
+            Ok(Box::new(index))
+        }
+    }
+}
+
 /// Search for a query in an index
 ///
 /// This function will search for a query in an index
@@ -48,49 +197,245 @@ fn main() {
 /// # Arguments
 ///  * `query` - The query to search for
 ///  * `index` - The index to search in
+///  * `scorer` - The ranking function
+///  * `proximity` - Whether to boost the documents where the terms appear close together
+///  * `options` - The search options, like fuzzy matching or the maximum number of expansions
+///  * `autocorrect` - Whether to search for the top spelling suggestion when nothing matches
 ///
 /// # Returns
 ///  * `Vec<String>` - The results of the search
-fn search(query: &String, index: Index) -> Result<(), Box<dyn std::error::Error>> {
-    let search_results = search_query::search(query, &index);
-    match search_results {
-        Ok(results) => {
-            // Only print the top 10 results
-            let cli_output = formatter::format_cli_output(results);
-            println!("{}", cli_output);
-            Ok(())
+fn search(
+    query: &String,
+    index: &dyn SearchIndex,
+    mut scorer: Box<dyn search_query::Scorer>,
+    proximity: bool,
+    options: &search_query::SearchOptions,
+    autocorrect: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if proximity {
+        scorer = Box::new(search_query::ProximityScorer::new(scorer));
+    }
+    let mut results = search_query::search_with_options(query, index, scorer.as_ref(), options)?;
+
+    // Suggest spelling corrections from the words of the index
+    if results.is_empty() {
+        let suggestions = search_query::suggest_queries(query, index, 3);
+        match suggestions.first() {
+            Some(suggestion) if autocorrect => {
+                println!(
+                    "No results for \"{}\", showing results for \"{}\".",
+                    query, suggestion
+                );
+                results =
+                    search_query::search_with_options(suggestion, index, scorer.as_ref(), options)?;
+            }
+            _ => {
+                println!("{}", formatter::format_no_results(&suggestions));
+                return Ok(());
+            }
+        }
+    }
+
+    // Only print the top 10 results
+    let cli_output = formatter::format_cli_output(results);
+    println!("{}", cli_output);
+    Ok(())
+}
+
+/// Builds the analyzer described by the config, the same one is used to
+/// index the notes and to analyze the queries
+///
+/// The stages come from `ANALYZER`, the synonym files they name are relative
+/// to the config directory, and so is the `synonyms.txt` file. The custom stop words are read from the
+/// `stop_words` folder of the config directory, one file per language like
+/// `eng.txt`. The subword tokenizer is only loaded when look is built with
+/// the `subwords` feature.
+fn build_analyzer(config: &Config) -> Result<Analyzer, Box<dyn std::error::Error>> {
+    let mut stages = match &config.analyzer {
+        Some(pipeline) => {
+            parse_pipeline(pipeline).map_err(|e| format!("Invalid ANALYZER ({})", e))?
+        }
+        None => Analyzer::new().stages,
+    };
+    if !config.stop_words {
+        stages.retain(|stage| !matches!(stage, Stage::StopWords(_)));
+    }
+    if !config.fold_diacritics {
+        stages.retain(|stage| *stage != Stage::AsciiFold);
+    }
+    for stage in stages.iter_mut() {
+        if let Stage::Synonym(path) = stage {
+            *path = Path::new(&config.app_config_path).join(path.as_path());
+        }
+    }
+    let mut analyzer = Analyzer::from_stages(stages)?;
+    analyzer.split_identifiers = config.split_identifiers;
+
+    let synonyms_path = Path::new(&config.app_config_path).join("synonyms.txt");
+    if synonyms_path.exists() {
+        if let Err(e) = analyzer.add_synonyms(&synonyms_path) {
+            println!("Could not load the synonyms ({}).", e);
+        }
+    }
+
+    let stop_words_path = Path::new(&config.app_config_path).join("stop_words");
+    if stop_words_path.exists() {
+        match load_stop_words(&stop_words_path) {
+            Ok(custom_stop_words) => analyzer.custom_stop_words = custom_stop_words,
+            Err(e) => println!("Could not load the custom stop words ({}).", e),
+        }
+    }
+
+    if let Some(tokenizer_path) = &config.tokenizer_path {
+        #[cfg(feature = "subwords")]
+        match SubwordTokenizer::from_file(tokenizer_path) {
+            Ok(tokenizer) => analyzer.subword_tokenizer = Some(tokenizer),
+            Err(e) => println!("Could not load the subword tokenizer ({}).", e),
+        }
+        #[cfg(not(feature = "subwords"))]
+        println!(
+            "Ignoring TOKENIZER_PATH={}, look was built without the subwords feature.",
+            tokenizer_path
+        );
+    }
+    Ok(analyzer)
+}
+
+/// Function to build the ranking function of a search
+///
+/// # Arguments
+///  * `name` - The name of the ranking function, "bm25" or "tfidf"
+///  * `k1` - The term frequency saturation of BM25, its default if not set
+///  * `b` - The length normalization of BM25, its default if not set
+///
+/// # Returns
+///  * The scorer, or an error if the parameters don't apply to it
+fn build_scorer(
+    name: &str,
+    k1: Option<f64>,
+    b: Option<f64>,
+) -> Result<Box<dyn search_query::Scorer>, Box<dyn std::error::Error>> {
+    match name {
+        "bm25" => {
+            let default = search_query::Bm25Scorer::default();
+            Ok(Box::new(search_query::Bm25Scorer::new(
+                k1.unwrap_or(default.k1),
+                b.unwrap_or(default.b),
+            )))
+        }
+        _ if k1.is_some() || b.is_some() => {
+            Err(format!("--k1 and --b only apply to the bm25 scorer, not {}", name).into())
         }
-        Err(e) => Err(e),
+        _ => search_query::get_scorer(name),
     }
 }
 
+/// Parses the `--k1` argument, a number from 0
+fn parse_k1(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(k1) if k1.is_finite() && k1 >= 0.0 => Ok(k1),
+        _ => Err(format!("\"{}\" isn't a number from 0, like 1.2", value)),
+    }
+}
+
+/// Parses the `--b` argument, a number from 0 to 1
+fn parse_b(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(b) if (0.0..=1.0).contains(&b) => Ok(b),
+        _ => Err(format!(
+            "\"{}\" isn't a number from 0 to 1, like 0.75",
+            value
+        )),
+    }
+}
+
+/// Parses the `--lang` argument into a language
+fn parse_language(name: &str) -> Result<Lang, String> {
+    indexer::data_ingestion::parse_language(name)
+        .ok_or_else(|| format!("unknown language \"{}\"", name))
+}
+
 /// This function will re-index a directory and save the index to the INDEX_PATH
-fn reindex(config: Config) {
+///
+/// Only the files that changed since the last reindex are processed again;
+/// if there is no usable index yet or the analyzer changed, every file is indexed.
+/// The files are picked following the `options`, the ignore files and the
+/// `--include` and `--exclude` globs, and are read and analyzed on `jobs` threads.
+/// The index is locked until it is saved, another `look reindex` waits for it.
+fn reindex(config: Config, options: &WalkOptions, jobs: usize) {
+    let index_path = Path::new(&config.index_path);
+    let _lock = match IndexLock::try_acquire(index_path) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            println!("Waiting for another `look reindex` to finish...");
+            match IndexLock::acquire(index_path) {
+                Ok(lock) => lock,
+                Err(e) => {
+                    println!("Error occurred: {}", e);
+                    return;
+                }
+            }
+        }
+        Err(e) => {
+            println!("Error occurred: {}", e);
+            return;
+        }
+    };
+
     println!("Indexing all the files in {}...", config.personal_data);
-    let mut index = Index::new();
+    let mut index = if index_path.exists() {
+        Index::load_index_from_file(index_path).unwrap_or_else(|e| {
+            println!("Could not load the existing index ({}), rebuilding it.", e);
+            Index::new()
+        })
+    } else {
+        Index::new()
+    };
+    index.analyzer = match build_analyzer(&config) {
+        Ok(analyzer) => analyzer,
+        Err(e) => {
+            println!("Error occurred: {}", e);
+            return;
+        }
+    };
+    if index.reset_if_analyzer_changed() && index_path.exists() {
+        println!("The analyzer changed, indexing every file again.");
+    }
+    if config.edge_ngrams {
+        if index.enable_edge_ngrams(DEFAULT_EDGE_NGRAM_MIN_LENGTH, DEFAULT_EDGE_NGRAM_MAX_LENGTH) {
+            println!("The edge n-grams changed, indexing every file again.");
+        }
+    } else {
+        index.disable_edge_ngrams();
+    }
 
-    // Update your `process_file` function to accept a `&mut HashMap<String, Vec<String>>` argument and pass it to `store_processed_text_in_index`
-    match process_directory(config.personal_data, &mut index) {
-        Ok(_) => println!("Processing completed."),
+    // The IDF is recalculated once the index is up to date
+    match update_directory_with_options(config.personal_data.clone(), &mut index, options, jobs) {
+        Ok(summary) => println!("Processing completed: {}.", summary),
         Err(e) => println!("Error occurred: {}", e),
     }
 
-    // Calculate the IDF for each term
-    index.calculate_idf();
-
-    // Save the in-memory index to a JSON file
-    match index.save_index_to_json_file(Path::new(&config.index_path)) {
-        Ok(_) => println!("Index saved to {}.", config.index_path.as_str()),
+    // Save the in-memory index to the index file
+    match index.save_index_to_file(index_path) {
+        Ok(_) => {
+            println!("Index saved to {}.", config.index_path.as_str());
+            warn_about_index_path(&config);
+        }
         Err(e) => println!("Error occurred: {}", e),
     }
 }
 
+/// Warns when the index path still names the JSON file of older versions
+fn warn_about_index_path(config: &Config) {
+    if let Some(warning) = config::index_path_warning(&config.index_path, &config.app_config_path) {
+        println!("{}", warning);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::Path;
 
-    use indexer::Index;
-
     use crate::config;
 
     fn build_index_with_3_docs() -> super::Index {
@@ -115,10 +460,10 @@ mod tests {
         let document = Document::new(path.to_str().unwrap().to_owned());
         index.store_processed_text_in_index(&document, text);
 
-        // Save the index to a JSON file
+        // Save the index to the index file
         index.calculate_idf();
         index
-            .save_index_to_json_file(Path::new(&config.index_path))
+            .save_index_to_file(Path::new(&config.index_path))
             .unwrap();
         index
     }
@@ -128,15 +473,50 @@ mod tests {
         let index = build_index_with_3_docs();
 
         let query = "test";
-        super::search(&query.to_owned(), index).unwrap();
+        super::search(
+            &query.to_owned(),
+            &index,
+            super::build_scorer("bm25", None, None).unwrap(),
+            false,
+            &Default::default(),
+            false,
+        )
+        .unwrap();
     }
 
     #[test]
     fn test_search_with_all_indexed_terms() {
         let config = config::load_config();
-        let index = Index::load_index_from_json_file(Path::new(&config.index_path)).unwrap();
+        let index = super::open_index(Path::new(&config.index_path), Default::default()).unwrap();
         let query = "more";
-        super::search(&query.to_owned(), index).unwrap();
+        super::search(
+            &query.to_owned(),
+            index.as_ref(),
+            super::build_scorer("bm25", None, None).unwrap(),
+            false,
+            &Default::default(),
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_bm25_parameters() {
+        assert_eq!(super::parse_k1("2"), Ok(2.0));
+        assert_eq!(super::parse_k1("0"), Ok(0.0));
+        assert!(super::parse_k1("-1").is_err());
+        assert!(super::parse_k1("inf").is_err());
+        assert_eq!(super::parse_b("0.5"), Ok(0.5));
+        assert!(super::parse_b("1.5").is_err());
+        assert!(super::parse_b("NaN").is_err());
+
+        assert!(super::build_scorer("bm25", Some(2.0), Some(0.5)).is_ok());
+        assert!(super::build_scorer("tfidf", None, None).is_ok());
+        let error = super::build_scorer("tfidf", Some(2.0), None).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "--k1 and --b only apply to the bm25 scorer, not tfidf"
+        );
     }
 
     #[test]
@@ -151,7 +531,7 @@ mod tests {
         }
 
         // Re-index the directory
-        super::reindex(super::config::load_config());
+        super::reindex(super::config::load_config(), &Default::default(), 1);
 
         // Check that the index file was created
         assert!(index_path.exists());
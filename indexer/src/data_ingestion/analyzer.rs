@@ -0,0 +1,940 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+pub use whatlang::Lang;
+
+use super::file_handler::DocumentKind;
+use super::pipeline::{format_pipeline, ngrams, parse_pipeline, Stage, DEFAULT_PIPELINE};
+#[cfg(feature = "subwords")]
+use super::subwords::SubwordTokenizer;
+use super::text_processing::{
+    detect_language, fold_diacritics, normalize_pattern, split_fenced_code_blocks,
+    split_words_with_positions, stem_tokens, stop_words, tokenize_identifiers_with_positions,
+    tokenize_with_positions,
+};
+use crate::index_builder::document::hash_content;
+
+/// The analyzer turns raw text into the terms stored in the index.
+///
+/// Both the indexing path (`Index::store_processed_text_in_index`) and the
+/// query path (`Query::new`) go through the same analyzer, so any
+/// normalization applied to documents is also applied to queries.
+///
+/// The text is first split into tokens, then the tokens go through the
+/// stages of the pipeline in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analyzer {
+    /// The stages applied to the tokens, by default `DEFAULT_PIPELINE`
+    pub stages: Vec<Stage>,
+    /// The synonyms of the terms, or of the terms of a phrase separated by
+    /// spaces. A synonym may have several terms, with their relative positions
+    pub synonyms: HashMap<String, Vec<Vec<(String, u32)>>>,
+    /// The stop words removed on top of the built-in ones, by language
+    pub custom_stop_words: HashMap<Lang, HashSet<String>>,
+    /// Whether every text is split into identifiers and their sub-words, like
+    /// code is, so "readFileContents" also finds "read file contents"
+    pub split_identifiers: bool,
+    /// Splits the texts into the subword units of a tokenizer instead of
+    /// words, the units aren't stemmed
+    #[cfg(feature = "subwords")]
+    pub subword_tokenizer: Option<SubwordTokenizer>,
+    /// The language of every text, instead of detecting it
+    pub language: Option<Lang>,
+    /// The language of the texts whose language can't be detected reliably
+    pub default_language: Lang,
+}
+
+/// What an index records about the analyzer it was built with
+///
+/// Queries must be analyzed the same way as the documents, so an index is
+/// only searched by an analyzer with the same header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalyzerHeader {
+    pub tokenizer: String,
+    pub pipeline: String,
+}
+
+/// The term of a whole identifier, with its position and the number of
+/// positions its sub-words take up
+pub type Identifier = (String, u32, u32);
+
+impl fmt::Display for AnalyzerHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tokenizer: {}, pipeline: {}",
+            self.tokenizer, self.pipeline
+        )
+    }
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Analyzer {
+            stages: parse_pipeline(DEFAULT_PIPELINE).unwrap(),
+            synonyms: HashMap::new(),
+            custom_stop_words: HashMap::new(),
+            split_identifiers: false,
+            #[cfg(feature = "subwords")]
+            subword_tokenizer: None,
+            language: None,
+            default_language: Lang::Eng,
+        }
+    }
+}
+
+impl Analyzer {
+    pub fn new() -> Analyzer {
+        Analyzer::default()
+    }
+
+    /// Creates an analyzer that applies the stages of a pipeline
+    ///
+    /// # Arguments
+    ///  * `pipeline` - The stages separated by commas, like `lowercase,stem,min_length(2)`
+    ///
+    /// # Returns
+    ///  * The analyzer, or an error if a stage can't be parsed or a synonym file can't be read
+    pub fn from_pipeline(pipeline: &str) -> Result<Analyzer, Box<dyn Error>> {
+        Analyzer::from_stages(parse_pipeline(pipeline)?)
+    }
+
+    /// Creates an analyzer that applies `stages`, loading the synonym files they name
+    pub fn from_stages(stages: Vec<Stage>) -> Result<Analyzer, Box<dyn Error>> {
+        let mut analyzer = Analyzer {
+            stages,
+            ..Analyzer::default()
+        };
+        analyzer.load_synonyms()?;
+        Ok(analyzer)
+    }
+
+    /// Reads the files of the synonym stages, replacing the synonyms loaded before
+    pub fn load_synonyms(&mut self) -> Result<(), Box<dyn Error>> {
+        self.synonyms.clear();
+        let paths: Vec<PathBuf> = self
+            .stages
+            .iter()
+            .filter_map(|stage| match stage {
+                Stage::Synonym(path) => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+        for path in paths {
+            self.add_synonyms(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Function to add the synonyms of a file to the analyzer
+    ///
+    /// Each line of the file is a group of words or phrases that mean the
+    /// same, separated by commas, like `pr, pull request`. Empty lines and
+    /// lines starting with `#` are ignored. The synonyms are analyzed like
+    /// the terms they stand for.
+    ///
+    /// # Arguments
+    ///  * `path` - The path of the synonyms file
+    ///
+    /// # Returns
+    ///  * An error if the file can't be read
+    pub fn add_synonyms(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read the synonyms {}: {}", path.display(), e))?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let language = self.detect_language(line);
+            let group: Vec<Vec<(String, u32)>> = line
+                .split(',')
+                .map(|entry| {
+                    let mut terms = self.analyze_with_language(entry, language);
+                    // Stop words like "pr" still stand for their synonyms
+                    if terms.is_empty() {
+                        terms = self
+                            .without_stop_words()
+                            .analyze_with_language(entry, language);
+                    }
+                    let first_position = terms.first().map_or(0, |(_, position)| *position);
+                    for (_, position) in terms.iter_mut() {
+                        *position -= first_position;
+                    }
+                    terms
+                })
+                .filter(|terms| !terms.is_empty())
+                .collect();
+            for entry in &group {
+                let synonyms = self.synonyms.entry(synonym_key(entry)).or_default();
+                for synonym in &group {
+                    if synonym != entry && !synonyms.contains(synonym) {
+                        synonyms.push(synonym.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The stages that change the terms of the index, written like in the config
+    ///
+    /// An index can only be searched with an analyzer that has the same pipeline.
+    pub fn pipeline(&self) -> String {
+        let stages: Vec<Stage> = self
+            .stages
+            .iter()
+            .filter(|stage| stage.affects_index())
+            .cloned()
+            .collect();
+        format_pipeline(&stages)
+    }
+
+    /// How the texts are split into tokens, before the stages
+    pub fn tokenizer(&self) -> String {
+        #[cfg(feature = "subwords")]
+        if let Some(subword_tokenizer) = &self.subword_tokenizer {
+            return format!("subwords({})", subword_tokenizer.path().display());
+        }
+        if self.split_identifiers {
+            "identifiers".to_owned()
+        } else {
+            "words".to_owned()
+        }
+    }
+
+    /// The header stored in the indexes built with this analyzer
+    ///
+    /// The custom stop words change the terms of the index too, so a
+    /// fingerprint of them follows the pipeline, and editing them asks for
+    /// a reindex.
+    pub fn header(&self) -> AnalyzerHeader {
+        let mut pipeline = self.pipeline();
+        if let Some(fingerprint) = self.stop_words_fingerprint() {
+            pipeline.push_str(&format!(", custom stop words {:016x}", fingerprint));
+        }
+        AnalyzerHeader {
+            tokenizer: self.tokenizer(),
+            pipeline,
+        }
+    }
+
+    /// Function to hash the custom stop words removed by the pipeline
+    ///
+    /// # Returns
+    ///  * A hash of the sorted stop words of each language, `None` if there
+    ///    are none or the pipeline doesn't remove stop words
+    fn stop_words_fingerprint(&self) -> Option<u64> {
+        if !self.has_stage(|stage| matches!(stage, Stage::StopWords(_))) {
+            return None;
+        }
+        let mut lists: Vec<String> = self
+            .custom_stop_words
+            .iter()
+            .filter(|(_, words)| !words.is_empty())
+            .map(|(language, words)| {
+                let mut words: Vec<&str> = words.iter().map(String::as_str).collect();
+                words.sort_unstable();
+                format!("{}:{}", language.code(), words.join(","))
+            })
+            .collect();
+        if lists.is_empty() {
+            return None;
+        }
+        lists.sort_unstable();
+        Some(hash_content(&lists.join("\n")))
+    }
+
+    /// Whether the pipeline has a stage matching `predicate`
+    pub fn has_stage(&self, predicate: impl Fn(&Stage) -> bool) -> bool {
+        self.stages.iter().any(predicate)
+    }
+
+    /// Returns the synonyms of analyzed terms, a single term or the terms of a phrase
+    ///
+    /// Each synonym is a list of terms with their positions relative to the first one.
+    pub fn synonyms(&self, terms: &[(String, u32)]) -> &[Vec<(String, u32)>] {
+        self.synonyms
+            .get(&synonym_key(terms))
+            .map_or(&[], |synonyms| synonyms.as_slice())
+    }
+
+    /// Returns a copy of the analyzer that keeps the stop words
+    pub fn without_stop_words(&self) -> Analyzer {
+        let mut analyzer = self.clone();
+        analyzer
+            .stages
+            .retain(|stage| !matches!(stage, Stage::StopWords(_)));
+        analyzer
+    }
+
+    /// Returns a copy of the analyzer that treats every text as `language`
+    ///
+    /// Queries are analyzed word by word, which is too little text to detect
+    /// a language from, so the language of the whole query is fixed first.
+    pub fn with_language(&self, language: Lang) -> Analyzer {
+        Analyzer {
+            language: Some(language),
+            ..self.clone()
+        }
+    }
+
+    /// Detects the language of the text
+    ///
+    /// # Returns
+    /// * The language set on the analyzer if any, else the detected language
+    ///   or the default language when the detection isn't reliable
+    pub fn detect_language(&self, text: &str) -> Lang {
+        match self.language {
+            Some(language) => language,
+            None => detect_language(text, self.default_language),
+        }
+    }
+
+    /// Returns the stop words removed from the texts in `language`
+    pub fn stop_words(&self, language: Lang) -> HashSet<String> {
+        let mut stops = stop_words(language);
+        if let Some(custom_stop_words) = self.custom_stop_words.get(&language) {
+            stops.extend(custom_stop_words.iter().cloned());
+        }
+        stops
+    }
+
+    /// Analyzes the text into a list of terms
+    ///
+    /// # Arguments
+    /// * `text` - The text to analyze
+    ///
+    /// # Returns
+    /// * A vector of terms, in the order they appear in the text
+    pub fn analyze(&self, text: &str) -> Vec<String> {
+        self.analyze_with_positions(text)
+            .into_iter()
+            .map(|(term, _)| term)
+            .collect()
+    }
+
+    /// Analyzes the text into a list of terms with their positions in the text
+    ///
+    /// Positions are used for phrase queries, removed words still take up a position.
+    pub fn analyze_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        self.analyze_with_language(text, self.detect_language(text))
+    }
+
+    /// Same as `analyze_with_positions`, for a text whose language is already known
+    pub fn analyze_with_language(&self, text: &str, language: Lang) -> Vec<(String, u32)> {
+        without_words(self.analyze_words(text, language))
+    }
+
+    /// Same as `analyze_with_language`, each term comes with the word it was
+    /// made from, normalized like the term but not stemmed
+    pub fn analyze_words(&self, text: &str, language: Lang) -> Vec<(String, u32, String)> {
+        if self.split_identifiers {
+            return self.analyze_code_words(text, language);
+        }
+        #[cfg(feature = "subwords")]
+        if let Some(subword_tokenizer) = &self.subword_tokenizer {
+            let tokens = subword_tokenizer.tokenize_with_positions(text);
+            return self.filter_tokens(tokens, language, false);
+        }
+        self.filter_tokens(split_words_with_positions(text), language, true)
+    }
+
+    /// Analyzes code, its identifiers are kept whole and split into their sub-words
+    pub fn analyze_code(&self, text: &str, language: Lang) -> Vec<(String, u32)> {
+        without_words(self.analyze_code_words(text, language))
+    }
+
+    fn analyze_code_words(&self, text: &str, language: Lang) -> Vec<(String, u32, String)> {
+        self.filter_tokens(tokenize_identifiers_with_positions(text), language, true)
+    }
+
+    /// Same as `analyze_with_positions`, the whole identifiers are told apart
+    /// from their sub-words
+    ///
+    /// A query can match an identifier either whole or as the phrase of its
+    /// sub-words, the identifiers are only split when `split_identifiers` is set.
+    ///
+    /// # Returns
+    /// * The terms of the whole identifiers, with their position and the
+    ///   number of positions their sub-words take up
+    /// * The terms of the sub-words and of the other words, with their position
+    pub fn analyze_identifiers(&self, text: &str) -> (Vec<Identifier>, Vec<(String, u32)>) {
+        if !self.split_identifiers {
+            return (Vec::new(), self.analyze_with_positions(text));
+        }
+        let language = self.detect_language(text);
+        let tokens = tokenize_identifiers_with_positions(text);
+
+        // A whole identifier comes right before its first sub-word, at the
+        // same position, and is made of the sub-words that follow it
+        let mut identifiers = Vec::new();
+        let mut widths = HashMap::new();
+        let mut words = Vec::new();
+        let mut index = 0;
+        while index < tokens.len() {
+            let (token, position) = &tokens[index];
+            if tokens.get(index + 1).map(|(_, next)| next) != Some(position) {
+                words.push(tokens[index].clone());
+                index += 1;
+                continue;
+            }
+            let mut concatenated = String::new();
+            let mut width = 0;
+            while concatenated.len() < token.len() && index + 1 + width < tokens.len() {
+                concatenated.push_str(&tokens[index + 1 + width].0);
+                width += 1;
+            }
+            identifiers.push((token.clone(), *position));
+            widths.insert(*position, width as u32);
+            words.extend(tokens[index + 1..index + 1 + width].iter().cloned());
+            index += 1 + width;
+        }
+
+        let identifiers = without_words(self.filter_tokens(identifiers, language, true))
+            .into_iter()
+            .map(|(term, position)| (term, position, widths[&position]))
+            .collect();
+        let words = without_words(self.filter_tokens(words, language, true));
+        (identifiers, words)
+    }
+
+    /// Analyzes the contents of a file, following the kind of document it is
+    ///
+    /// The fenced code blocks of a markdown document are analyzed as code,
+    /// their positions follow the positions of the prose before them.
+    ///
+    /// # Arguments
+    /// * `text` - The contents of the file
+    /// * `kind` - The kind of document, from the file handler
+    /// * `language` - The language of the document
+    ///
+    /// # Returns
+    /// * A vector of (term, position) pairs
+    pub fn analyze_document(
+        &self,
+        text: &str,
+        kind: DocumentKind,
+        language: Lang,
+    ) -> Vec<(String, u32)> {
+        without_words(self.analyze_document_words(text, kind, language))
+    }
+
+    /// Same as `analyze_document`, each term comes with the word it was made
+    /// from, normalized like the term but not stemmed
+    ///
+    /// # Returns
+    /// * A vector of (term, position, word) triples
+    pub fn analyze_document_words(
+        &self,
+        text: &str,
+        kind: DocumentKind,
+        language: Lang,
+    ) -> Vec<(String, u32, String)> {
+        match kind {
+            DocumentKind::Text => self.analyze_words(text, language),
+            DocumentKind::Code => self.analyze_code_words(text, language),
+            DocumentKind::Markdown => {
+                let mut tokens = Vec::new();
+                let mut next_position = 0;
+                for (section, code) in split_fenced_code_blocks(text) {
+                    let section_tokens = if code {
+                        self.analyze_code_words(section, language)
+                    } else {
+                        self.analyze_words(section, language)
+                    };
+                    let first_position = next_position;
+                    if let Some((_, last_position, _)) = section_tokens.last() {
+                        next_position = first_position + last_position + 1;
+                    }
+                    tokens.extend(
+                        section_tokens.into_iter().map(|(token, position, word)| {
+                            (token, first_position + position, word)
+                        }),
+                    );
+                }
+                tokens
+            }
+        }
+    }
+
+    /// Runs the tokens of a text through the stages, the stem stages are
+    /// skipped unless `stemming`
+    ///
+    /// # Returns
+    /// * The terms with their position and the word they were made from,
+    ///   which goes through every stage but the stem ones
+    fn filter_tokens(
+        &self,
+        tokens: Vec<(String, u32)>,
+        language: Lang,
+        stemming: bool,
+    ) -> Vec<(String, u32, String)> {
+        let mut tokens: Vec<(String, u32, String)> = tokens
+            .into_iter()
+            .map(|(token, position)| (token.clone(), position, token))
+            .collect();
+        for stage in &self.stages {
+            tokens = match stage {
+                Stage::Lowercase => tokens
+                    .into_iter()
+                    .map(|(token, position, word)| {
+                        (token.to_lowercase(), position, word.to_lowercase())
+                    })
+                    .collect(),
+                Stage::AsciiFold => tokens
+                    .into_iter()
+                    .map(|(token, position, word)| {
+                        (fold_diacritics(&token), position, fold_diacritics(&word))
+                    })
+                    .collect(),
+                Stage::StopWords(stage_language) => {
+                    let stops = self.stop_words(stage_language.unwrap_or(language));
+                    tokens
+                        .into_iter()
+                        .filter(|(token, _, _)| !stops.contains(token))
+                        .collect()
+                }
+                Stage::Stem(stage_language) if stemming => {
+                    let (terms, words): (Vec<(String, u32)>, Vec<String>) = tokens
+                        .into_iter()
+                        .map(|(token, position, word)| ((token, position), word))
+                        .unzip();
+                    stem_tokens(terms, stage_language.unwrap_or(language))
+                        .into_iter()
+                        .zip(words)
+                        .map(|((token, position), word)| (token, position, word))
+                        .collect()
+                }
+                // An n-gram is its own word
+                Stage::NGram(min, max) => tokens
+                    .into_iter()
+                    .flat_map(|(token, position, _)| {
+                        ngrams(&token, *min, *max)
+                            .into_iter()
+                            .map(move |ngram| (ngram.clone(), position, ngram))
+                    })
+                    .collect(),
+                Stage::MinLength(length) => tokens
+                    .into_iter()
+                    .filter(|(token, _, _)| token.chars().count() >= *length)
+                    .collect(),
+                Stage::MaxLength(length) => tokens
+                    .into_iter()
+                    .filter(|(token, _, _)| token.chars().count() <= *length)
+                    .collect(),
+                Stage::Stem(_) | Stage::Synonym(_) => tokens,
+            };
+        }
+        tokens
+    }
+
+    /// Normalizes a wildcard pattern like the terms of the index, keeping the
+    /// `*` and `?` wildcards
+    ///
+    /// # Returns
+    /// * The pattern, or `None` if it has nothing but wildcards
+    pub fn analyze_pattern(&self, pattern: &str) -> Option<String> {
+        let mut pattern = normalize_pattern(pattern);
+        if self.has_stage(|stage| *stage == Stage::AsciiFold) {
+            pattern = fold_diacritics(&pattern);
+        }
+        if pattern.chars().any(char::is_alphanumeric) {
+            Some(pattern)
+        } else {
+            None
+        }
+    }
+}
+
+/// Drops the words the terms were made from
+fn without_words(terms: Vec<(String, u32, String)>) -> Vec<(String, u32)> {
+    terms
+        .into_iter()
+        .map(|(term, position, _)| (term, position))
+        .collect()
+}
+
+/// The key of the synonyms of some terms, the terms separated by spaces
+fn synonym_key(terms: &[(String, u32)]) -> String {
+    terms
+        .iter()
+        .map(|(term, _)| term.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Function to load the custom stop words from a directory
+///
+/// Each file is named after a language, like `eng.txt` or `spanish.txt`, and
+/// holds one stop word per line. Empty lines and lines starting with `#` are
+/// ignored.
+///
+/// # Arguments
+///  * `directory` - The directory with the stop-word files
+///
+/// # Returns
+///  * The stop words of each language, or an error if a file can't be read
+///    or isn't named after a language
+pub fn load_stop_words(directory: &Path) -> Result<HashMap<Lang, HashSet<String>>, Box<dyn Error>> {
+    let mut custom_stop_words: HashMap<Lang, HashSet<String>> = HashMap::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some("txt") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let language = parse_language(name).ok_or(format!(
+            "{} isn't named after a language, like eng.txt",
+            path.display()
+        ))?;
+
+        let contents = fs::read_to_string(&path)?;
+        let words = custom_stop_words.entry(language).or_default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The words are normalized like the tokens they are compared to
+            words.extend(
+                tokenize_with_positions(line)
+                    .into_iter()
+                    .map(|(word, _)| word),
+            );
+        }
+    }
+    Ok(custom_stop_words)
+}
+
+/// Finds a language from its ISO 639-3 code ("eng") or its English name ("English")
+pub fn parse_language(name: &str) -> Option<Lang> {
+    Lang::from_code(name.to_lowercase()).or_else(|| {
+        Lang::all()
+            .iter()
+            .find(|language| language.eng_name().eq_ignore_ascii_case(name))
+            .copied()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_pattern() {
+        let analyzer = Analyzer::new();
+        assert_eq!(
+            analyzer.analyze_pattern("Deploy*"),
+            Some("deploy*".to_owned())
+        );
+        assert_eq!(analyzer.analyze_pattern("Te?t!"), Some("te?t".to_owned()));
+        assert_eq!(analyzer.analyze_pattern("*?"), None);
+    }
+
+    #[test]
+    fn test_analyze() {
+        let analyzer = Analyzer::new();
+        let terms = analyzer.analyze("A blue car!");
+        assert_eq!(terms, vec!["blue", "car"]);
+    }
+
+    #[test]
+    fn test_query_and_document_terms_match() {
+        let analyzer = Analyzer::new();
+        let document_terms = analyzer.analyze("This is a sample. The blue car, again!");
+
+        // Case, punctuation and stop words should not change the terms
+        for query in ["Sample", "SAMPLE", "car!", "the car", "Blue, car?"] {
+            for term in analyzer.analyze(query) {
+                assert!(
+                    document_terms.contains(&term),
+                    "query term {:?} from {:?} not found in {:?}",
+                    term,
+                    query,
+                    document_terms
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_analyze_with_positions() {
+        let analyzer = Analyzer::new();
+        let terms = analyzer.analyze_with_positions("The blue car, and the red bike");
+        assert_eq!(
+            terms,
+            vec![
+                ("blue".to_owned(), 1),
+                ("car".to_owned(), 2),
+                ("red".to_owned(), 5),
+                ("bike".to_owned(), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stemming() {
+        let analyzer = Analyzer::new();
+        assert_eq!(
+            analyzer.analyze("Deploying the clusters"),
+            analyzer.analyze("deployed cluster")
+        );
+
+        let analyzer = Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap();
+        assert_eq!(
+            analyzer.analyze("Deploying clusters"),
+            vec!["deploying", "clusters"]
+        );
+    }
+
+    #[test]
+    fn test_analyze_document_words() {
+        let analyzer = Analyzer::new();
+        assert_eq!(
+            analyzer.analyze_document_words("Rolling the Clusters", DocumentKind::Text, Lang::Eng),
+            vec![
+                ("roll".to_owned(), 0, "rolling".to_owned()),
+                ("cluster".to_owned(), 2, "clusters".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let analyzer = Analyzer::new();
+        let portuguese = "Olá mundo, este é um texto em português sobre o idioma";
+        assert_eq!(analyzer.detect_language(portuguese), Lang::Por);
+        // Too short to be detected reliably
+        assert_eq!(analyzer.detect_language("deploying"), Lang::Eng);
+        assert_eq!(
+            analyzer
+                .with_language(Lang::Spa)
+                .detect_language(portuguese),
+            Lang::Spa
+        );
+    }
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(parse_language("spa"), Some(Lang::Spa));
+        assert_eq!(parse_language("Portuguese"), Some(Lang::Por));
+        assert_eq!(parse_language("klingon"), None);
+    }
+
+    #[test]
+    fn test_stop_words_follow_the_language() {
+        let analyzer = Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap();
+        let spanish = "Hola mundo, este idioma es el español que hablamos en casa";
+        assert_eq!(
+            analyzer.analyze(spanish),
+            vec!["hola", "mundo", "idioma", "espanol", "hablamos", "casa"]
+        );
+
+        let analyzer = Analyzer::from_pipeline("lowercase,ascii_fold").unwrap();
+        assert_eq!(analyzer.analyze("the cluster"), vec!["the", "cluster"]);
+    }
+
+    #[test]
+    fn test_fold_diacritics() {
+        let analyzer = Analyzer::new();
+        assert_eq!(analyzer.analyze("Español"), analyzer.analyze("espanol"));
+        assert_eq!(analyzer.analyze_pattern("Espa*"), Some("espa*".to_owned()));
+        // The Spanish stop words are removed before the accents
+        let spanish = "Él está en la casa, donde están los niños y también su familia";
+        assert_eq!(analyzer.analyze(spanish), vec!["cas", "nin", "famili"]);
+
+        let analyzer = Analyzer::from_pipeline("lowercase,stop_words,stem").unwrap();
+        assert_eq!(analyzer.analyze("español"), vec!["español"]);
+    }
+
+    #[test]
+    fn test_analyze_document() {
+        let analyzer = Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap();
+        let markdown = "Read a file\n```\nreadFileContents(path)\n```\nthen parse_it";
+        assert_eq!(
+            analyzer.analyze_document(markdown, DocumentKind::Markdown, Lang::Eng),
+            vec![
+                ("read".to_owned(), 0),
+                ("file".to_owned(), 2),
+                ("readfilecontents".to_owned(), 3),
+                ("read".to_owned(), 3),
+                ("file".to_owned(), 4),
+                ("contents".to_owned(), 5),
+                ("path".to_owned(), 6),
+                ("parseit".to_owned(), 8),
+            ]
+        );
+        assert_eq!(
+            analyzer.analyze_document("parse_it", DocumentKind::Code, Lang::Eng),
+            // "it" is a stop word
+            vec![("parseit".to_owned(), 0), ("parse".to_owned(), 0)]
+        );
+
+        let analyzer = Analyzer {
+            split_identifiers: true,
+            ..analyzer
+        };
+        assert_eq!(analyzer.analyze("then parse_it"), vec!["parseit", "parse"]);
+    }
+
+    #[test]
+    fn test_pipeline_stages() {
+        let analyzer =
+            Analyzer::from_pipeline("stop_words(eng),stem(spa),min_length(3),max_length(8)")
+                .unwrap();
+        // Without the lowercase stage "The" isn't a stop word, "application"
+        // is too long once stemmed
+        assert_eq!(
+            analyzer.analyze("The despliegues of an application"),
+            vec!["The", "desplieg"]
+        );
+        assert_eq!(
+            analyzer.pipeline(),
+            "stop_words(eng),stem(spa),min_length(3),max_length(8)"
+        );
+        assert_eq!(analyzer.tokenizer(), "words");
+
+        let analyzer = Analyzer::from_pipeline("lowercase,ngram(3,4)").unwrap();
+        assert_eq!(
+            analyzer.analyze_with_positions("Kube go"),
+            vec![
+                ("kub".to_owned(), 0),
+                ("ube".to_owned(), 0),
+                ("kube".to_owned(), 0),
+                ("go".to_owned(), 1),
+            ]
+        );
+
+        assert!(Analyzer::from_pipeline("lowercase,stemming").is_err());
+        assert_eq!(Analyzer::new().pipeline(), DEFAULT_PIPELINE);
+    }
+
+    #[test]
+    fn test_load_synonyms() {
+        let path = std::env::temp_dir().join("look_test_analyzer_synonyms.txt");
+        fs::write(
+            &path,
+            "# Words that mean the same\nk8s, Kubernetes\n\npr, pull request\npoc, proof of concept\n",
+        )
+        .unwrap();
+        let pipeline = format!("lowercase,stop_words,stem,synonym({})", path.display());
+        let analyzer = Analyzer::from_pipeline(&pipeline).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let terms = |terms: &[(&str, u32)]| -> Vec<(String, u32)> {
+            terms
+                .iter()
+                .map(|(term, position)| (term.to_string(), *position))
+                .collect()
+        };
+        assert_eq!(
+            analyzer.synonyms(&terms(&[("k8s", 0)])),
+            [terms(&[("kubernet", 0)])]
+        );
+        // "pr" is a stop word, but it's kept to stand for its synonyms
+        assert_eq!(
+            analyzer.synonyms(&terms(&[("pr", 0)])),
+            [terms(&[("pull", 0), ("request", 1)])]
+        );
+        assert_eq!(
+            analyzer.synonyms(&terms(&[("pull", 0), ("request", 1)])),
+            [terms(&[("pr", 0)])]
+        );
+        // The stop words keep their place in the phrases
+        assert_eq!(
+            analyzer.synonyms(&terms(&[("poc", 0)])),
+            [terms(&[("proof", 0), ("concept", 2)])]
+        );
+        assert!(analyzer.synonyms(&terms(&[("pull", 0)])).is_empty());
+        // Synonyms don't change the terms of the documents
+        assert_eq!(analyzer.analyze("my k8s"), vec!["k8s"]);
+        assert_eq!(analyzer.pipeline(), "lowercase,stop_words,stem");
+
+        let missing = Analyzer::from_pipeline("synonym(missing_synonyms.txt)").unwrap_err();
+        assert!(missing
+            .to_string()
+            .starts_with("Could not read the synonyms"));
+    }
+
+    #[cfg(feature = "subwords")]
+    #[test]
+    fn test_subword_tokenizer() {
+        use crate::data_ingestion::subwords::tests::write_tokenizer;
+
+        let path = write_tokenizer("look_test_analyzer_tokenizer.json");
+        let analyzer = Analyzer {
+            subword_tokenizer: Some(SubwordTokenizer::from_file(&path).unwrap()),
+            ..Analyzer::new()
+        };
+        // The stop words are still removed, the units aren't stemmed
+        assert_eq!(
+            analyzer.analyze("Upgrade the cluster with kubeadm"),
+            vec!["upgrade", "cluster", "kube", "adm"]
+        );
+        // "kubectl" and "kubeadm" share their first unit
+        assert_eq!(analyzer.analyze("kubectl"), vec!["kube", "ctl"]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_stop_words() {
+        let directory = std::env::temp_dir().join("look_test_load_stop_words");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("eng.txt"),
+            "# Words of my notes\nTODO\n\nfixme\n",
+        )
+        .unwrap();
+        fs::write(directory.join("README.md"), "Not a list").unwrap();
+
+        let custom_stop_words = load_stop_words(&directory).unwrap();
+        let expected: HashSet<String> = ["todo", "fixme"].map(str::to_owned).into();
+        assert_eq!(custom_stop_words[&Lang::Eng], expected);
+
+        let analyzer = Analyzer {
+            custom_stop_words,
+            ..Analyzer::new()
+        };
+        assert_eq!(
+            analyzer.analyze("TODO: upgrade the cluster"),
+            vec!["upgrad", "cluster"]
+        );
+
+        // Editing the stop words changes the header, the index is rebuilt
+        assert_ne!(analyzer.header(), Analyzer::new().header());
+        fs::write(directory.join("eng.txt"), "TODO\nfixme\nwip\n").unwrap();
+        let edited = Analyzer {
+            custom_stop_words: load_stop_words(&directory).unwrap(),
+            ..Analyzer::new()
+        };
+        assert_ne!(edited.header(), analyzer.header());
+        let reloaded = Analyzer {
+            custom_stop_words: load_stop_words(&directory).unwrap(),
+            ..Analyzer::new()
+        };
+        assert_eq!(edited.header(), reloaded.header());
+        assert_eq!(
+            edited.without_stop_words().header(),
+            Analyzer::new().without_stop_words().header()
+        );
+
+        fs::write(directory.join("klingon.txt"), "qapla").unwrap();
+        assert!(load_stop_words(&directory).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_stop_word_only_query_is_empty() {
+        let analyzer = Analyzer::new();
+        assert!(analyzer.analyze("the a is").is_empty());
+    }
+}
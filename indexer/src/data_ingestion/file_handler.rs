@@ -1,4 +1,20 @@
+/// How the contents of a file are split into terms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentKind {
+    /// Prose, split into words
+    Text,
+    /// Prose whose fenced code blocks are split into identifiers
+    Markdown,
+    /// Source code, split into identifiers and their sub-words
+    Code,
+}
+
 pub trait FileHandler {
     fn can_handle(&self, file_extension: &str) -> bool;
     fn read_contents(&self, file_path: &str) -> Result<String, Box<dyn std::error::Error>>;
+
+    /// How the contents of the files of this handler are analyzed
+    fn document_kind(&self) -> DocumentKind {
+        DocumentKind::Text
+    }
 }
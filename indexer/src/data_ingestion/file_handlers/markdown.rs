@@ -1,4 +1,4 @@
-use crate::data_ingestion::file_handler::FileHandler;
+use crate::data_ingestion::file_handler::{DocumentKind, FileHandler};
 use std::fs;
 
 pub struct MarkdownHandler;
@@ -12,6 +12,10 @@ impl FileHandler for MarkdownHandler {
         let content = fs::read_to_string(file_path)?;
         Ok(content)
     }
+
+    fn document_kind(&self) -> DocumentKind {
+        DocumentKind::Markdown
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,7 @@
 pub mod markdown;
 pub mod plain_text;
+pub mod source_code;
 
 pub use markdown::MarkdownHandler;
 pub use plain_text::PlainTextHandler;
+pub use source_code::SourceCodeHandler;
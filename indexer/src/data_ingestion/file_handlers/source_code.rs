@@ -0,0 +1,25 @@
+use crate::data_ingestion::file_handler::{DocumentKind, FileHandler};
+use std::fs;
+
+/// The extensions of the source files that are indexed
+const SOURCE_CODE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "c", "h", "cpp", "hpp", "cs", "rb",
+    "php", "swift", "scala", "lua", "sh", "sql",
+];
+
+pub struct SourceCodeHandler;
+
+impl FileHandler for SourceCodeHandler {
+    fn can_handle(&self, file_extension: &str) -> bool {
+        SOURCE_CODE_EXTENSIONS.contains(&file_extension)
+    }
+
+    fn read_contents(&self, file_path: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(file_path)?;
+        Ok(content)
+    }
+
+    fn document_kind(&self) -> DocumentKind {
+        DocumentKind::Code
+    }
+}
@@ -1,6 +1,12 @@
+pub mod analyzer;
 pub mod file_handler;
 pub mod file_handlers;
+pub mod pipeline;
+#[cfg(feature = "subwords")]
+pub mod subwords;
 pub mod text_processing;
 
-pub use file_handler::FileHandler;
+pub use analyzer::{load_stop_words, parse_language, Analyzer, AnalyzerHeader, Identifier, Lang};
+pub use file_handler::{DocumentKind, FileHandler};
 pub use file_handlers::*;
+pub use pipeline::{parse_pipeline, Stage, DEFAULT_PIPELINE};
@@ -0,0 +1,313 @@
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use whatlang::Lang;
+
+use super::analyzer::parse_language;
+
+/// The stages of the analyzer when none are configured
+pub const DEFAULT_PIPELINE: &str = "lowercase,stop_words,stem,ascii_fold";
+
+/// A step the analyzer applies to the tokens of a text, in the order of the pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stage {
+    /// Converts the tokens to lowercase
+    Lowercase,
+    /// Removes the accents, so "español" becomes "espanol"
+    AsciiFold,
+    /// Removes the stop words of a language, or of the language of the text
+    StopWords(Option<Lang>),
+    /// Reduces the tokens to their stem with the rules of a language, or of
+    /// the language of the text
+    Stem(Option<Lang>),
+    /// Replaces each token by its character n-grams between these lengths
+    NGram(usize, usize),
+    /// Expands the query terms to their synonyms, listed in this file
+    Synonym(PathBuf),
+    /// Drops the tokens shorter than this many characters
+    MinLength(usize),
+    /// Drops the tokens longer than this many characters
+    MaxLength(usize),
+}
+
+impl Stage {
+    /// Whether the stage changes the terms stored in the index
+    ///
+    /// Synonyms only expand the queries, the index can be searched with or without them.
+    pub fn affects_index(&self) -> bool {
+        !matches!(self, Stage::Synonym(_))
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Lowercase => write!(f, "lowercase"),
+            Stage::AsciiFold => write!(f, "ascii_fold"),
+            Stage::StopWords(None) => write!(f, "stop_words"),
+            Stage::StopWords(Some(language)) => write!(f, "stop_words({})", language.code()),
+            Stage::Stem(None) => write!(f, "stem"),
+            Stage::Stem(Some(language)) => write!(f, "stem({})", language.code()),
+            Stage::NGram(min, max) => write!(f, "ngram({},{})", min, max),
+            Stage::Synonym(path) => match path.to_str() {
+                Some(path) if path_needs_quotes(path) => write!(f, "synonym(\"{}\")", path),
+                _ => write!(f, "synonym({})", path.display()),
+            },
+            Stage::MinLength(length) => write!(f, "min_length({})", length),
+            Stage::MaxLength(length) => write!(f, "max_length({})", length),
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = Box<dyn Error>;
+
+    /// Parses a stage written like `stem`, `stem(spa)`, `ngram(2,3)` or
+    /// `synonym("synonyms (work).txt")`
+    fn from_str(stage: &str) -> Result<Stage, Self::Err> {
+        let stage = stage.trim();
+        let (name, arguments) = match stage.split_once('(') {
+            Some((name, rest)) => match rest.strip_suffix(')') {
+                Some(arguments) => (name.trim(), Some(arguments.trim())),
+                None => return Err(format!("missing closing ')' in \"{}\"", stage).into()),
+            },
+            None => (stage, None),
+        };
+
+        let stage = match (name, arguments) {
+            ("lowercase", None) => Stage::Lowercase,
+            ("ascii_fold", None) => Stage::AsciiFold,
+            ("stop_words", None) => Stage::StopWords(None),
+            ("stop_words", Some(language)) => Stage::StopWords(Some(language_argument(language)?)),
+            ("stem", None) => Stage::Stem(None),
+            ("stem", Some(language)) => Stage::Stem(Some(language_argument(language)?)),
+            ("ngram", Some(lengths)) => {
+                let (min, max) = lengths
+                    .split_once(',')
+                    .ok_or("ngram takes a minimum and a maximum length, like ngram(2,3)")?;
+                let (min, max) = (length_argument(min)?, length_argument(max)?);
+                if min > max {
+                    return Err(format!(
+                        "the minimum length of ngram({},{}) is above its maximum",
+                        min, max
+                    )
+                    .into());
+                }
+                Stage::NGram(min, max)
+            }
+            ("synonym", Some(path)) if !path.is_empty() => Stage::Synonym(path_argument(path)?),
+            ("min_length", Some(length)) => Stage::MinLength(length_argument(length)?),
+            ("max_length", Some(length)) => Stage::MaxLength(length_argument(length)?),
+            ("lowercase" | "ascii_fold", Some(_)) => {
+                return Err(format!("{} doesn't take arguments", name).into())
+            }
+            ("ngram" | "synonym" | "min_length" | "max_length", _) => {
+                return Err(
+                    format!("{} is missing its arguments, like {}", name, example(name)).into(),
+                )
+            }
+            _ => return Err(format!("unknown analyzer stage \"{}\"", name).into()),
+        };
+        Ok(stage)
+    }
+}
+
+/// Function to parse a pipeline, a list of stages separated by commas
+///
+/// # Arguments
+///  * `pipeline` - The stages, like `lowercase,stop_words,stem(spa),min_length(2)`
+///
+/// # Returns
+///  * The stages in order, or an error naming the stage that can't be parsed
+pub fn parse_pipeline(pipeline: &str) -> Result<Vec<Stage>, Box<dyn Error>> {
+    let mut stages = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut quoted = false;
+    for (offset, character) in pipeline.char_indices() {
+        match character {
+            // The commas and parentheses of a quoted path are part of it
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            // The commas between the arguments of a stage don't separate stages
+            ',' if depth == 0 => {
+                stages.push(&pipeline[start..offset]);
+                start = offset + 1;
+            }
+            _ => {}
+        }
+    }
+    stages.push(&pipeline[start..]);
+
+    stages
+        .into_iter()
+        .filter(|stage| !stage.trim().is_empty())
+        .map(Stage::from_str)
+        .collect()
+}
+
+/// Writes the stages back as a pipeline, the opposite of `parse_pipeline`
+pub fn format_pipeline(stages: &[Stage]) -> String {
+    stages
+        .iter()
+        .map(|stage| stage.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Returns the character n-grams of a token, from the shortest to the longest
+///
+/// A token shorter than `min` is kept whole, so short words stay searchable.
+pub fn ngrams(token: &str, min: usize, max: usize) -> Vec<String> {
+    let characters: Vec<char> = token.chars().collect();
+    if characters.len() < min {
+        return vec![token.to_owned()];
+    }
+    let mut ngrams = Vec::new();
+    for length in min..=max.min(characters.len()) {
+        for window in characters.windows(length) {
+            ngrams.push(window.iter().collect());
+        }
+    }
+    ngrams
+}
+
+fn language_argument(language: &str) -> Result<Lang, Box<dyn Error>> {
+    parse_language(language).ok_or_else(|| format!("unknown language \"{}\"", language).into())
+}
+
+/// Reads the path of a file, which is put in double quotes when it contains
+/// commas, parentheses or spaces at its ends
+fn path_argument(path: &str) -> Result<PathBuf, Box<dyn Error>> {
+    match path.strip_prefix('"') {
+        Some(rest) => match rest.strip_suffix('"') {
+            Some(path) if !path.is_empty() && !path.contains('"') => Ok(PathBuf::from(path)),
+            _ => Err(format!("{} isn't a quoted path, like \"synonyms.txt\"", path).into()),
+        },
+        None if path_needs_quotes(path) => Err(format!(
+            "the path \"{}\" must be put in double quotes, like synonym(\"synonyms (work).txt\")",
+            path
+        )
+        .into()),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+/// Whether a path can only be written in a pipeline between double quotes
+fn path_needs_quotes(path: &str) -> bool {
+    path.contains([',', '(', ')', '"']) || path.trim() != path
+}
+
+fn length_argument(length: &str) -> Result<usize, Box<dyn Error>> {
+    match length.trim().parse() {
+        Ok(length) if length > 0 => Ok(length),
+        _ => Err(format!("\"{}\" isn't a length, like 2", length.trim()).into()),
+    }
+}
+
+fn example(name: &str) -> &'static str {
+    match name {
+        "ngram" => "ngram(2,3)",
+        "synonym" => "synonym(synonyms.txt)",
+        "min_length" => "min_length(2)",
+        _ => "max_length(40)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pipeline() {
+        let stages =
+            parse_pipeline("lowercase, stop_words(Spanish), stem, ngram(2, 3), min_length(2)")
+                .unwrap();
+        assert_eq!(
+            stages,
+            vec![
+                Stage::Lowercase,
+                Stage::StopWords(Some(Lang::Spa)),
+                Stage::Stem(None),
+                Stage::NGram(2, 3),
+                Stage::MinLength(2),
+            ]
+        );
+        assert_eq!(
+            format_pipeline(&stages),
+            "lowercase,stop_words(spa),stem,ngram(2,3),min_length(2)"
+        );
+        assert_eq!(
+            format_pipeline(&parse_pipeline(DEFAULT_PIPELINE).unwrap()),
+            DEFAULT_PIPELINE
+        );
+        assert_eq!(parse_pipeline("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_quoted_paths() {
+        let stages =
+            parse_pipeline("lowercase,synonym(\"notes/synonyms, (work).txt\"),stem").unwrap();
+        assert_eq!(
+            stages,
+            vec![
+                Stage::Lowercase,
+                Stage::Synonym(PathBuf::from("notes/synonyms, (work).txt")),
+                Stage::Stem(None),
+            ]
+        );
+        assert_eq!(
+            format_pipeline(&stages),
+            "lowercase,synonym(\"notes/synonyms, (work).txt\"),stem"
+        );
+        assert_eq!(
+            parse_pipeline("synonym(notes/synonyms.txt)").unwrap(),
+            vec![Stage::Synonym(PathBuf::from("notes/synonyms.txt"))]
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_pipeline() {
+        for (pipeline, message) in [
+            ("lowercase,stemming", "unknown analyzer stage \"stemming\""),
+            ("stem(klingon)", "unknown language \"klingon\""),
+            (
+                "ngram(3)",
+                "ngram takes a minimum and a maximum length, like ngram(2,3)",
+            ),
+            (
+                "ngram(3,2)",
+                "the minimum length of ngram(3,2) is above its maximum",
+            ),
+            (
+                "min_length",
+                "min_length is missing its arguments, like min_length(2)",
+            ),
+            ("max_length(0)", "\"0\" isn't a length, like 2"),
+            ("lowercase(eng)", "lowercase doesn't take arguments"),
+            ("stem(eng", "missing closing ')' in \"stem(eng\""),
+            (
+                "synonym(synonyms (work).txt)",
+                "the path \"synonyms (work).txt\" must be put in double quotes, like synonym(\"synonyms (work).txt\")",
+            ),
+            (
+                "synonym(\"synonyms.txt)",
+                "\"synonyms.txt isn't a quoted path, like \"synonyms.txt\"",
+            ),
+        ] {
+            let error = parse_pipeline(pipeline).unwrap_err();
+            assert_eq!(error.to_string(), message, "{}", pipeline);
+        }
+    }
+
+    #[test]
+    fn test_ngrams() {
+        assert_eq!(ngrams("kube", 2, 3), vec!["ku", "ub", "be", "kub", "ube"]);
+        assert_eq!(ngrams("k8s", 2, 5), vec!["k8", "8s", "k8s"]);
+        assert_eq!(ngrams("a", 2, 3), vec!["a"]);
+    }
+}
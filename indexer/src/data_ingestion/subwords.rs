@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokenizers::models::ModelWrapper;
+use tokenizers::Tokenizer;
+
+use super::text_processing::{normalize_unicode, tokenize_with_positions};
+
+/// Splits text into the subword units of a WordPiece or BPE tokenizer
+///
+/// The tokenizer is loaded from a `tokenizer.json` file, like the ones of the
+/// Hugging Face models. A compound word or a rare term is split into pieces
+/// the tokenizer knows, so "kubeadm" and "kubectl" share the "kube" unit.
+#[derive(Clone)]
+pub struct SubwordTokenizer {
+    path: PathBuf,
+    tokenizer: Arc<Tokenizer>,
+    /// The id of the unit standing for the words the tokenizer doesn't know
+    unknown_id: Option<u32>,
+}
+
+impl SubwordTokenizer {
+    /// Function to load a tokenizer from a `tokenizer.json` file
+    ///
+    /// # Arguments
+    ///  * `path` - The path of the `tokenizer.json` file
+    ///
+    /// # Returns
+    ///  * The tokenizer, or an error if the file can't be read or parsed
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SubwordTokenizer, Box<dyn Error>> {
+        let path = path.as_ref();
+        let tokenizer = Tokenizer::from_file(path)
+            .map_err(|e| format!("Could not load the tokenizer {}: {}", path.display(), e))?;
+        Ok(SubwordTokenizer {
+            path: path.to_owned(),
+            unknown_id: unknown_id(&tokenizer),
+            tokenizer: Arc::new(tokenizer),
+        })
+    }
+
+    /// The path the tokenizer was loaded from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Splits the text into lowercased subword units, with their position in the text
+    ///
+    /// The markers of the tokenizers, like the "##" of a WordPiece continuation
+    /// or the "Ġ" of a byte-level BPE space, are removed, and so are the
+    /// units without any letter or digit. The unknown and special units, like
+    /// "[UNK]", are dropped, they would match every word the tokenizer doesn't know.
+    ///
+    /// # Arguments
+    /// * `text` - The text to split
+    ///
+    /// # Returns
+    /// * A vector of (unit, position) pairs
+    pub fn tokenize_with_positions(&self, text: &str) -> Vec<(String, u32)> {
+        let text = normalize_unicode(text);
+        let encoding = match self.tokenizer.encode(text.as_str(), false) {
+            Ok(encoding) => encoding,
+            // The words are still searchable if the tokenizer fails
+            Err(_) => return tokenize_with_positions(&text),
+        };
+
+        encoding
+            .get_tokens()
+            .iter()
+            .zip(encoding.get_ids())
+            .zip(encoding.get_special_tokens_mask())
+            .filter(|((_, id), special)| **special == 0 && Some(**id) != self.unknown_id)
+            .map(|((token, _), _)| {
+                token
+                    .trim_start_matches("##")
+                    .trim_start_matches(['Ġ', '▁'])
+                    .to_lowercase()
+                    .chars()
+                    .filter(|character| character.is_alphanumeric())
+                    .collect::<String>()
+            })
+            .filter(|token| !token.is_empty())
+            .enumerate()
+            .map(|(position, token)| (token, position as u32))
+            .collect()
+    }
+}
+
+/// Returns the id of the unknown unit of the tokenizer's model, if it has one
+fn unknown_id(tokenizer: &Tokenizer) -> Option<u32> {
+    let unknown_token = match tokenizer.get_model() {
+        ModelWrapper::WordPiece(model) => model.unk_token.clone(),
+        ModelWrapper::WordLevel(model) => model.unk_token.clone(),
+        ModelWrapper::BPE(model) => model.unk_token.clone()?,
+        // The unigram model only exposes the id in its serialized form
+        ModelWrapper::Unigram(model) => {
+            let unknown_id = serde_json::to_value(model).ok()?["unk_id"].as_u64()?;
+            return u32::try_from(unknown_id).ok();
+        }
+    };
+    tokenizer.token_to_id(&unknown_token)
+}
+
+impl fmt::Debug for SubwordTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SubwordTokenizer")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Two tokenizers are the same if they come from the same file
+impl PartialEq for SubwordTokenizer {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes a small WordPiece tokenizer that knows a few Kubernetes words
+    pub(crate) fn write_tokenizer(name: &str) -> PathBuf {
+        let tokenizer = r###"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": {"type": "Lowercase"},
+            "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordPiece",
+                "unk_token": "[UNK]",
+                "continuing_subword_prefix": "##",
+                "max_input_chars_per_word": 100,
+                "vocab": {
+                    "[UNK]": 0, "kube": 1, "##adm": 2, "##ctl": 3, "##let": 4,
+                    "the": 5, "cluster": 6, "upgrade": 7, "with": 8, "!": 9
+                }
+            }
+        }"###;
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, tokenizer).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        let path = write_tokenizer("look_test_subwords_tokenizer.json");
+        let tokenizer = SubwordTokenizer::from_file(&path).unwrap();
+        assert_eq!(
+            tokenizer.tokenize_with_positions("Upgrade the cluster with Kubeadm!"),
+            vec![
+                ("upgrade".to_owned(), 0),
+                ("the".to_owned(), 1),
+                ("cluster".to_owned(), 2),
+                ("with".to_owned(), 3),
+                ("kube".to_owned(), 4),
+                ("adm".to_owned(), 5),
+            ]
+        );
+        // The words missing from the vocabulary aren't indexed as "[UNK]"
+        assert_eq!(
+            tokenizer.tokenize_with_positions("Upgrade etcd"),
+            vec![("upgrade".to_owned(), 0)]
+        );
+        assert_eq!(tokenizer.path(), path);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(SubwordTokenizer::from_file("missing_tokenizer.json").is_err());
+    }
+}
@@ -1,56 +1,363 @@
+use std::collections::HashSet;
+
 use rust_stemmers::{Algorithm, Stemmer};
+use stop_words::LANGUAGE;
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
+use whatlang::{Lang, Script};
 
 pub fn process_text(text: &str) -> String {
-    // Get the language of the text
-    // let language = whatlang::detect(text).unwrap().lang();
+    // Join the tokens back into a single string
+    analyze_text(text).join(" ")
+}
+
+/// Runs the text through the processing steps and returns the resulting tokens
+///
+/// # Arguments
+/// * `text` - The text to process
+///
+/// # Returns
+/// * A vector of lowercased tokens without punctuation or stop words
+pub fn analyze_text(text: &str) -> Vec<String> {
+    analyze_text_with_positions(text)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect()
+}
+
+/// Same as `analyze_text`, but each token comes with its position in the text
+///
+/// Positions count the removed stop words too, so the distance between two
+/// tokens is the same in a document and in a phrase query.
+///
+/// # Arguments
+/// * `text` - The text to process
+///
+/// # Returns
+/// * A vector of (token, position) pairs
+pub fn analyze_text_with_positions(text: &str) -> Vec<(String, u32)> {
+    let tokens = tokenize_with_positions(text);
+
+    // Step 4: Remove the stop words of the language of the text
+    // Stemming depends on the language of the text, so it's left to the `Analyzer`
+    let language = detect_language(text, Lang::Eng);
+    remove_stop_words(tokens, &stop_words(language))
+}
+
+/// Splits the text into lowercased tokens without punctuation, with their
+/// position in the text
+///
+/// # Arguments
+/// * `text` - The text to process
+///
+/// # Returns
+/// * A vector of (token, position) pairs
+pub fn tokenize_with_positions(text: &str) -> Vec<(String, u32)> {
+    // Some characters only have a lowercase form once normalized, like "Ⅻ"
+    split_words_with_positions(&normalize_unicode(text).to_lowercase())
+}
 
-    // Step 1: Convert text to lowercase
-    let lowercased_text = text.to_lowercase();
+/// Same as `tokenize_with_positions`, but the tokens keep their case
+pub fn split_words_with_positions(text: &str) -> Vec<(String, u32)> {
+    // Step 1: Normalize the text, so the same word is always encoded the same way
+    let normalized_text = normalize_unicode(text);
 
     // Step 2: Remove any unwanted characters, such as punctuation or special characters
-    let cleaned_text = remove_unwanted_characters(&lowercased_text);
+    let cleaned_text = remove_unwanted_characters(&normalized_text);
 
     // Step 3: Tokenize the text into words (e.g., using an NLP library or custom function)
-    let tokens = tokenize(&cleaned_text);
+    tokenize(&cleaned_text)
+        .into_iter()
+        .enumerate()
+        .map(|(position, token)| (token, position as u32))
+        .collect()
+}
+
+/// Splits code into identifiers, with their position in the text
+///
+/// `snake_case`, `camelCase`, `kebab-case` and dotted identifiers like
+/// `std::fs::read_dir` are kept whole, without their separators, and split
+/// into their lowercased sub-words. The whole identifier shares the position
+/// of its first sub-word, so `readFileContents` matches both the query
+/// `readfilecontents` and the phrase "read file contents".
+///
+/// # Arguments
+/// * `text` - The code to process
+///
+/// # Returns
+/// * A vector of (token, position) pairs
+pub fn tokenize_identifiers_with_positions(text: &str) -> Vec<(String, u32)> {
+    let text = normalize_unicode(text);
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    let identifiers = text.split(|character: char| {
+        !character.is_alphanumeric() && !is_identifier_separator(character)
+    });
+    for identifier in identifiers {
+        // Text without spaces within the sub-words is still split into pairs of characters
+        let words: Vec<String> = split_identifier(identifier)
+            .iter()
+            .flat_map(|word| tokenize(word))
+            .collect();
+        if words.len() > 1 {
+            tokens.push((words.concat(), position));
+        }
+        for word in words {
+            tokens.push((word, position));
+            position += 1;
+        }
+    }
+    tokens
+}
+
+/// Whether the character separates the words of an identifier
+fn is_identifier_separator(character: char) -> bool {
+    matches!(character, '_' | '-' | '.' | ':')
+}
+
+/// Splits an identifier into its lowercased words,
+/// e.g. "parseHTTPResponse_v2" -> ["parse", "http", "response", "v2"]
+fn split_identifier(identifier: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for part in identifier.split(is_identifier_separator) {
+        let characters: Vec<char> = part.chars().collect();
+        let mut start = 0;
+        for i in 1..characters.len() {
+            let previous = characters[i - 1];
+            let current = characters[i];
+            let next = characters.get(i + 1);
+            // "readFile", "utf8Decode", and the end of an acronym in "HTTPResponse"
+            let boundary = ((previous.is_lowercase() || previous.is_numeric())
+                && current.is_uppercase())
+                || (previous.is_uppercase()
+                    && current.is_uppercase()
+                    && next.is_some_and(|next| next.is_lowercase()));
+            if boundary {
+                words.push(
+                    characters[start..i]
+                        .iter()
+                        .collect::<String>()
+                        .to_lowercase(),
+                );
+                start = i;
+            }
+        }
+        if start < characters.len() {
+            words.push(
+                characters[start..]
+                    .iter()
+                    .collect::<String>()
+                    .to_lowercase(),
+            );
+        }
+    }
+    words
+}
+
+/// Splits markdown into its prose and its fenced code blocks
+///
+/// The fence lines themselves are left out. A code block that isn't closed
+/// runs to the end of the text.
+///
+/// # Arguments
+/// * `markdown` - The markdown text
+///
+/// # Returns
+/// * The sections of the text, with whether they are code
+pub fn split_fenced_code_blocks(markdown: &str) -> Vec<(&str, bool)> {
+    let mut sections = Vec::new();
+    let mut section_start = 0;
+    let mut offset = 0;
+    // The character and length of the fence of the current code block
+    let mut fence: Option<(char, usize)> = None;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(' ');
+        let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~');
+        if let Some(marker) = marker {
+            let length = trimmed.chars().take_while(|&c| c == marker).count();
+            match fence {
+                None if length >= 3 => {
+                    sections.push((&markdown[section_start..offset], false));
+                    section_start = offset + line.len();
+                    fence = Some((marker, length));
+                }
+                Some((fence_marker, fence_length))
+                    if marker == fence_marker
+                        && length >= fence_length
+                        && trimmed[length..].trim().is_empty() =>
+                {
+                    sections.push((&markdown[section_start..offset], true));
+                    section_start = offset + line.len();
+                    fence = None;
+                }
+                _ => {}
+            }
+        }
+        offset += line.len();
+    }
+    sections.push((&markdown[section_start..], fence.is_some()));
+    sections.retain(|(section, _)| !section.trim().is_empty());
+    sections
+}
 
-    // Step 4: Remove stop words
-    let tokens_without_stop_words = remove_stop_words(tokens);
+/// Applies the Unicode NFKC normalization to the text
+///
+/// The composed and decomposed forms of an accented letter ("é" and "e" followed
+/// by a combining accent) become the same character, and compatibility
+/// characters like the "ﬁ" ligature or full-width letters become their usual form.
+pub fn normalize_unicode(text: &str) -> String {
+    text.nfkc().collect()
+}
 
-    // Step 4: Stemming
-    // Stemming is the process of reducing a word to its word stem
-    // let stemmed_tokens = stem_tokens(tokens, language);
+/// Removes the diacritics from the text, e.g. "español" becomes "espanol"
+///
+/// # Arguments
+/// * `text` - The text to fold
+///
+/// # Returns
+/// * The text without accents, the marks that are part of a letter, like the
+///   Japanese voicing marks or the Thai vowels, are kept
+pub fn fold_diacritics(text: &str) -> String {
+    text.nfd()
+        .filter(|character| !is_diacritic(*character))
+        .nfc()
+        .collect()
+}
 
-    // Step 5: Join the tokens back into a single string
-    tokens_without_stop_words.join(" ")
+/// Whether the character is one of the combining diacritical marks
+fn is_diacritic(character: char) -> bool {
+    matches!(
+        character,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
 }
 
-fn remove_stop_words(tokens: Vec<String>) -> Vec<String> {
-    let stops = stop_words::get(stop_words::LANGUAGE::English);
-    let mut tokens_without_stop_words: Vec<String> = Vec::new();
-    for token in tokens {
+/// Detects the language of the text
+///
+/// # Arguments
+/// * `text` - The text to detect the language of
+/// * `default_language` - The language returned when the detection isn't reliable,
+///   short texts rarely are
+pub fn detect_language(text: &str, default_language: Lang) -> Lang {
+    match whatlang::detect(text) {
+        Some(info) if info.is_reliable() => info.lang(),
+        _ => default_language,
+    }
+}
+
+/// Lowercases a wildcard pattern and removes the characters the terms can't
+/// contain, the `*` and `?` wildcards are kept
+pub fn normalize_pattern(pattern: &str) -> String {
+    normalize_unicode(pattern)
+        .to_lowercase()
+        .chars()
+        .filter(|character| character.is_alphanumeric() || matches!(character, '*' | '?'))
+        .collect()
+}
+
+/// Removes the stop words from the tokens, the positions of the other tokens don't change
+///
+/// # Arguments
+/// * `tokens` - The tokens with their positions
+/// * `stops` - The words to remove
+pub fn remove_stop_words(
+    tokens: Vec<(String, u32)>,
+    stops: &HashSet<String>,
+) -> Vec<(String, u32)> {
+    let mut tokens_without_stop_words: Vec<(String, u32)> = Vec::new();
+    for (token, position) in tokens {
         if !stops.contains(&token) {
-            tokens_without_stop_words.push(token);
+            tokens_without_stop_words.push((token, position));
         }
     }
     tokens_without_stop_words
 }
 
-fn stem_tokens(tokens: Vec<String>, language: whatlang::Lang) -> Vec<String> {
-    let lang_stemmer = match language {
-        whatlang::Lang::Eng => Stemmer::create(Algorithm::English),
-        whatlang::Lang::Spa => Stemmer::create(Algorithm::Spanish),
-        _ => Stemmer::create(Algorithm::English),
+/// Returns the built-in stop words of a language, empty if there is no list for it
+pub fn stop_words(language: Lang) -> HashSet<String> {
+    match stop_words_list(language) {
+        Some(list) => stop_words::get(list).into_iter().collect(),
+        None => HashSet::new(),
+    }
+}
+
+/// Returns the stop-words list for a language, if the stop-words crate has one
+fn stop_words_list(language: Lang) -> Option<LANGUAGE> {
+    match language {
+        Lang::Ara => Some(LANGUAGE::Arabic),
+        Lang::Dan => Some(LANGUAGE::Danish),
+        Lang::Nld => Some(LANGUAGE::Dutch),
+        Lang::Eng => Some(LANGUAGE::English),
+        Lang::Fin => Some(LANGUAGE::Finnish),
+        Lang::Fra => Some(LANGUAGE::French),
+        Lang::Deu => Some(LANGUAGE::German),
+        Lang::Ell => Some(LANGUAGE::Greek),
+        Lang::Hun => Some(LANGUAGE::Hungarian),
+        Lang::Ind => Some(LANGUAGE::Indonesian),
+        Lang::Ita => Some(LANGUAGE::Italian),
+        Lang::Nob => Some(LANGUAGE::Norwegian),
+        Lang::Por => Some(LANGUAGE::Portuguese),
+        Lang::Ron => Some(LANGUAGE::Romanian),
+        Lang::Rus => Some(LANGUAGE::Russian),
+        Lang::Slv => Some(LANGUAGE::Slovenian),
+        Lang::Spa => Some(LANGUAGE::Spanish),
+        Lang::Swe => Some(LANGUAGE::Swedish),
+        Lang::Tur => Some(LANGUAGE::Turkish),
+        _ => None,
+    }
+}
+
+/// Reduces each token to its stem, e.g. "deploying" and "deployed" become "deploy"
+///
+/// # Arguments
+/// * `tokens` - The tokens with their positions
+/// * `language` - The language of the tokens
+///
+/// # Returns
+/// * The stemmed tokens, or the same tokens if there is no stemmer for the language
+pub fn stem_tokens(tokens: Vec<(String, u32)>, language: Lang) -> Vec<(String, u32)> {
+    let lang_stemmer = match stemming_algorithm(language) {
+        Some(algorithm) => Stemmer::create(algorithm),
+        None => return tokens,
     };
 
     let mut stemmed_tokens = Vec::new();
-    for token in tokens {
+    for (token, position) in tokens {
         let stemmed_token = lang_stemmer.stem(&token).into_owned();
-        stemmed_tokens.push(stemmed_token);
+        stemmed_tokens.push((stemmed_token, position));
     }
     stemmed_tokens
 }
 
+/// Returns the stemming algorithm for a language, if rust-stemmers has one
+pub fn stemming_algorithm(language: Lang) -> Option<Algorithm> {
+    match language {
+        Lang::Ara => Some(Algorithm::Arabic),
+        Lang::Dan => Some(Algorithm::Danish),
+        Lang::Nld => Some(Algorithm::Dutch),
+        Lang::Eng => Some(Algorithm::English),
+        Lang::Fin => Some(Algorithm::Finnish),
+        Lang::Fra => Some(Algorithm::French),
+        Lang::Deu => Some(Algorithm::German),
+        Lang::Ell => Some(Algorithm::Greek),
+        Lang::Hun => Some(Algorithm::Hungarian),
+        Lang::Ita => Some(Algorithm::Italian),
+        Lang::Nob => Some(Algorithm::Norwegian),
+        Lang::Por => Some(Algorithm::Portuguese),
+        Lang::Ron => Some(Algorithm::Romanian),
+        Lang::Rus => Some(Algorithm::Russian),
+        Lang::Spa => Some(Algorithm::Spanish),
+        Lang::Swe => Some(Algorithm::Swedish),
+        Lang::Tam => Some(Algorithm::Tamil),
+        Lang::Tur => Some(Algorithm::Turkish),
+        _ => None,
+    }
+}
+
 /// Tokenizes the text into words
 ///
 /// # Arguments
@@ -63,11 +370,49 @@ fn tokenize(cleaned_text: &str) -> Vec<String> {
     // e.g., "Hello, world!" -> ["Hello", ",", "world", "!"]
     // We want to keep punctuation so that we can use it for phrase queries
     let tokens = cleaned_text.split_word_bounds();
-    let mut vector_tokens: Vec<String> = tokens.map(|token| token.to_owned()).collect();
+    let mut vector_tokens: Vec<String> = Vec::new();
+
+    // Chinese, Japanese and Thai don't put spaces between words, the word
+    // bounds split them into single characters or whole sentences, so each
+    // run of them is indexed as overlapping pairs of characters instead
+    let mut unsegmented_run = String::new();
+    for token in tokens {
+        if is_unsegmented(token) {
+            unsegmented_run.push_str(token);
+            continue;
+        }
+        vector_tokens.extend(bigrams(&unsegmented_run));
+        unsegmented_run.clear();
+        vector_tokens.push(token.to_owned());
+    }
+    vector_tokens.extend(bigrams(&unsegmented_run));
+
     vector_tokens.retain(|token| !token.contains(" "));
     vector_tokens
 }
 
+/// Whether the text is written in a script without spaces between words,
+/// like Chinese, Japanese or Thai
+pub fn is_unsegmented(text: &str) -> bool {
+    matches!(
+        whatlang::detect_script(text),
+        Some(Script::Mandarin | Script::Hiragana | Script::Katakana | Script::Thai)
+    )
+}
+
+/// Splits a run of text into overlapping pairs of characters,
+/// e.g. "東京都" -> ["東京", "京都"]
+///
+/// A single character is kept as it is. Characters are grapheme clusters, so
+/// a letter is never split from its marks.
+fn bigrams(run: &str) -> Vec<String> {
+    let characters: Vec<&str> = run.graphemes(true).collect();
+    if characters.len() < 2 {
+        return characters.into_iter().map(str::to_owned).collect();
+    }
+    characters.windows(2).map(|pair| pair.concat()).collect()
+}
+
 /// Removes any characters that are not alphanumeric from the text
 /// it keeps spaces and punctuation
 ///
@@ -152,10 +497,160 @@ mod tests {
         assert_eq!(processed_text, "blue car");
     }
 
+    #[test]
+    fn test_stem_tokens() {
+        let tokens = vec![("deploying".to_owned(), 0), ("clusters".to_owned(), 2)];
+        assert_eq!(
+            stem_tokens(tokens.clone(), Lang::Eng),
+            vec![("deploy".to_owned(), 0), ("cluster".to_owned(), 2)]
+        );
+        let tokens = vec![("despliegues".to_owned(), 0)];
+        assert_eq!(
+            stem_tokens(tokens, Lang::Spa),
+            vec![("desplieg".to_owned(), 0)]
+        );
+        // There is no stemmer for Esperanto
+        let tokens = vec![("deploying".to_owned(), 0)];
+        assert_eq!(stem_tokens(tokens.clone(), Lang::Epo), tokens);
+    }
+
+    #[test]
+    fn test_normalize_unicode() {
+        // "é" composed and decomposed
+        assert_eq!(
+            normalize_unicode("caf\u{e9}"),
+            normalize_unicode("cafe\u{301}")
+        );
+        assert_eq!(normalize_unicode("\u{fb01}le"), "file");
+        assert_eq!(
+            analyze_text("cafe\u{301} espan\u{303}ol"),
+            vec!["caf\u{e9}", "espa\u{f1}ol"]
+        );
+    }
+
+    #[test]
+    fn test_fold_diacritics() {
+        assert_eq!(fold_diacritics("español"), "espanol");
+        assert_eq!(fold_diacritics("Ça déménage"), "Ca demenage");
+        assert_eq!(fold_diacritics("cafe\u{301}"), "cafe");
+        // The voicing mark is part of the Japanese letter
+        assert_eq!(fold_diacritics("が"), "が");
+        // So are the Thai and Devanagari vowel signs
+        assert_eq!(fold_diacritics("สวัสดี"), "สวัสดี");
+        assert_eq!(fold_diacritics("हिंदी"), "हिंदी");
+    }
+
+    #[test]
+    fn test_bigrams() {
+        assert_eq!(tokenize("東京都"), vec!["東京", "京都"]);
+        assert_eq!(tokenize("東"), vec!["東"]);
+        // Japanese mixes kanji, hiragana and katakana without spaces
+        assert_eq!(
+            tokenize("東京でコンピュータ"),
+            vec!["東京", "京で", "でコ", "コン", "ンピ", "ピュ", "ュー", "ータ"]
+        );
+        // Thai vowels and tone marks stay with their letter
+        assert_eq!(tokenize("สวัสดี"), vec!["สวั", "วัส", "สดี"]);
+    }
+
+    #[test]
+    fn test_mixed_language_text() {
+        let tokens = analyze_text_with_positions("Deploy 北京 servers, 上海数据中心");
+        assert_eq!(
+            tokens,
+            vec![
+                ("deploy".to_owned(), 0),
+                ("北京".to_owned(), 1),
+                ("servers".to_owned(), 2),
+                ("上海".to_owned(), 3),
+                ("海数".to_owned(), 4),
+                ("数据".to_owned(), 5),
+                ("据中".to_owned(), 6),
+                ("中心".to_owned(), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_identifier() {
+        assert_eq!(
+            split_identifier("readFileContents"),
+            vec!["read", "file", "contents"]
+        );
+        assert_eq!(split_identifier("read_dir"), vec!["read", "dir"]);
+        assert_eq!(split_identifier("kebab-case"), vec!["kebab", "case"]);
+        assert_eq!(
+            split_identifier("std::fs::read_dir"),
+            vec!["std", "fs", "read", "dir"]
+        );
+        assert_eq!(
+            split_identifier("parseHTTPResponse_v2"),
+            vec!["parse", "http", "response", "v2"]
+        );
+        assert_eq!(split_identifier("cluster"), vec!["cluster"]);
+    }
+
+    #[test]
+    fn test_tokenize_identifiers() {
+        assert_eq!(
+            tokenize_identifiers_with_positions("let contents = readFileContents(path);"),
+            vec![
+                ("let".to_owned(), 0),
+                ("contents".to_owned(), 1),
+                ("readfilecontents".to_owned(), 2),
+                ("read".to_owned(), 2),
+                ("file".to_owned(), 3),
+                ("contents".to_owned(), 4),
+                ("path".to_owned(), 5),
+            ]
+        );
+        // The whole identifier is the term a query for it is analyzed into
+        assert_eq!(
+            tokenize_identifiers_with_positions("std::fs::read_dir")[0].0,
+            analyze_text("std::fs::read_dir")[0]
+        );
+    }
+
+    #[test]
+    fn test_split_fenced_code_blocks() {
+        let markdown = "# Notes\nRead the files:\n```rust\nfs::read_dir(path)\n```\nDone.\n~~~\nunclosed_block";
+        assert_eq!(
+            split_fenced_code_blocks(markdown),
+            vec![
+                ("# Notes\nRead the files:\n", false),
+                ("fs::read_dir(path)\n", true),
+                ("Done.\n", false),
+                ("unclosed_block", true),
+            ]
+        );
+        // A shorter fence doesn't close the block
+        assert_eq!(
+            split_fenced_code_blocks("````\ncode\n```\nstill code\n````\n"),
+            vec![("code\n```\nstill code\n", true)]
+        );
+    }
+
+    #[test]
+    fn test_stop_words() {
+        assert!(stop_words(Lang::Eng).contains("the"));
+        assert!(stop_words(Lang::Spa).contains("este"));
+        assert!(!stop_words(Lang::Spa).contains("the"));
+        // There is no list for Esperanto
+        assert!(stop_words(Lang::Epo).is_empty());
+
+        let tokens = vec![("the".to_owned(), 0), ("cluster".to_owned(), 1)];
+        let stops = ["the".to_owned()].into_iter().collect();
+        assert_eq!(
+            remove_stop_words(tokens, &stops),
+            vec![("cluster".to_owned(), 1)]
+        );
+    }
+
     #[test]
     fn test_process_spanish_text() {
-        let input = "Hola, mundo este idioma es español!";
-        let expected_output = "hola mundo este idioma es español";
+        // The Spanish stop words are removed, not the English ones
+        let input = "Hola, mundo! Este idioma es el español";
+        let expected_output = "hola mundo idioma español";
         let output = process_text(input);
         assert_eq!(output, expected_output);
     }
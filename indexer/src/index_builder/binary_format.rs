@@ -0,0 +1,1314 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Error, ErrorKind, Result};
+use std::ops::{Bound, Range};
+
+use crate::data_ingestion::{Analyzer, AnalyzerHeader};
+
+use super::{Document, DocumentMetadata, EdgeNgrams, Index, Postings, Term};
+
+/// The first bytes of an index file
+pub const MAGIC: &[u8; 8] = b"LOOKIDX\0";
+
+/// The version of the format written by `encode_index`
+///
+///  * 1: the first binary format
+///  * 2: sorted tables and a document table, an entry is read without
+///    decoding the others
+///  * 3: the time the index was built
+///  * 4: the edge n-grams are the prefixes of the words before stemming
+///  * 5: the words of each document the edge n-grams were made from
+pub const FORMAT_VERSION: u32 = 5;
+
+/// The oldest version still read, a version 2 file is read as an index
+/// without a build time, and the edge n-grams of the files before version 4
+/// are dropped so that `look reindex` builds them again. The prefixes of the
+/// words of the documents saved before version 5 are only removed with their
+/// term. The files saved in an older version are rebuilt with `look reindex`.
+pub const OLDEST_READABLE_VERSION: u32 = 2;
+
+/// The sections of an index file, in the order they are written
+///
+/// The header holds the offset where each section ends, so a section can be
+/// read without decoding the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// The analyzer the index was built with, the number of documents,
+    /// their total length and when the index was built
+    Analyzer,
+    /// The path, length, language, norm and file metadata of each document,
+    /// sorted by path with the offset of each one
+    Documents,
+    /// The terms in a sorted table, with their IDF and where their postings are
+    Terms,
+    /// The documents and positions of each term, delta and varint encoded
+    Postings,
+    /// The words of each document that aren't their own term, and the terms
+    /// starting with each prefix in a sorted table, when edge n-grams are enabled
+    EdgeNgrams,
+}
+
+const SECTIONS: [Section; 5] = [
+    Section::Analyzer,
+    Section::Documents,
+    Section::Terms,
+    Section::Postings,
+    Section::EdgeNgrams,
+];
+
+/// The length of the header: magic bytes, version and the end of each section
+pub const HEADER_LENGTH: usize = MAGIC.len() + 4 + SECTIONS.len() * 8;
+
+/// Whether the bytes start like an index file in the binary format
+pub fn is_binary_index(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Function to encode an index in the binary format
+///
+/// # Arguments
+///  * `index` - The index to encode
+///
+/// # Returns
+///  * The bytes of the index file, the same for the same index
+pub fn encode_index(index: &Index) -> Vec<u8> {
+    let documents = document_table(index);
+    let document_ids: HashMap<&Document, u64> = documents
+        .iter()
+        .enumerate()
+        .map(|(id, document)| (*document, id as u64))
+        .collect();
+    let (terms, postings) = encode_terms(index, &document_ids);
+    let sections = [
+        encode_analyzer(index),
+        encode_documents(index, &documents),
+        terms,
+        postings,
+        encode_edge_ngrams(index, &document_ids),
+    ];
+
+    let mut bytes = Vec::with_capacity(
+        HEADER_LENGTH + sections.iter().map(|section| section.len()).sum::<usize>(),
+    );
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    let mut end = HEADER_LENGTH;
+    for section in &sections {
+        end += section.len();
+        bytes.extend_from_slice(&(end as u64).to_le_bytes());
+    }
+    for section in &sections {
+        bytes.extend_from_slice(section);
+    }
+    bytes
+}
+
+/// Function to decode an index saved in the binary format
+///
+/// # Arguments
+///  * `bytes` - The contents of the index file
+///
+/// # Returns
+///  * The index, with the default analyzer, or an `InvalidData` error if the
+///    bytes aren't a valid index
+pub fn decode_index(bytes: &[u8]) -> Result<Index> {
+    let header = read_header(bytes)?;
+    let section = |section: Section| &bytes[header.sections[section as usize].clone()];
+
+    let analyzer_section = decode_analyzer(
+        &mut Decoder::new(section(Section::Analyzer)),
+        header.version,
+    )?;
+    let mut index = Index::with_analyzer(Analyzer::new());
+    index.built_with = analyzer_section.built_with;
+    index.built_at = analyzer_section.built_at;
+    index.num_docs = analyzer_section.num_docs;
+
+    let document_table = DocumentTable::new(section(Section::Documents))?;
+    let mut documents = Vec::with_capacity(document_table.len());
+    for id in 0..document_table.len() {
+        let record = document_table.record(id)?;
+        let document = record.document;
+        if let Some(length) = record.length {
+            index.document_lengths.insert(document.clone(), length);
+        }
+        if let Some(language) = record.language {
+            index.document_languages.insert(document.clone(), language);
+        }
+        if let Some(norm) = record.norm {
+            index.document_norms.insert(document.clone(), norm);
+        }
+        if let Some(metadata) = record.metadata {
+            index.document_metadata.insert(document.clone(), metadata);
+        }
+        documents.push(document);
+    }
+
+    let terms = SortedTable::new(section(Section::Terms))?;
+    let postings = section(Section::Postings);
+    let mut term_ids = Vec::with_capacity(terms.len());
+    for entry in terms.iter_from(0) {
+        let (term, value) = entry?;
+        let entry = decode_term_entry(term, value)?;
+        let docs = decode_postings(postings, &entry, |id| {
+            documents
+                .get(id)
+                .cloned()
+                .ok_or_else(|| invalid_data("The postings refer to an unknown document"))
+        })?;
+        if let Some(idf) = entry.idf {
+            index.idf.insert(Term(entry.term.clone()), idf);
+        }
+        index.inverted_index.insert(Term(entry.term.clone()), docs);
+        term_ids.push(entry.term);
+    }
+
+    // The edge n-grams before version 4 are the prefixes of the stemmed terms
+    let edge_ngrams = match header.version {
+        2 | 3 => None,
+        _ => read_edge_ngrams(section(Section::EdgeNgrams), header.version)?,
+    };
+    if let Some(section) = edge_ngrams {
+        let term = |id: usize| {
+            term_ids
+                .get(id)
+                .ok_or_else(|| invalid_data("The edge n-grams refer to an unknown term"))
+        };
+        let mut edge_ngrams = EdgeNgrams::new(section.min_length, section.max_length);
+        for entry in section.prefixes.iter_from(0) {
+            let (prefix, value) = entry?;
+            let mut prefix_terms = BTreeSet::new();
+            for id in decode_term_ids(value)? {
+                prefix_terms.insert(term(id)?.clone());
+            }
+            edge_ngrams.prefixes.insert(prefix, prefix_terms);
+        }
+        if let Some(document_words) = section.document_words {
+            for (id, words) in decode_document_words(document_words)? {
+                let document = documents
+                    .get(id)
+                    .ok_or_else(|| invalid_data("The edge n-grams refer to an unknown document"))?;
+                let mut document_terms = BTreeMap::new();
+                for (word, term_id) in words {
+                    document_terms.insert(word, term(term_id)?.clone());
+                }
+                edge_ngrams
+                    .document_words
+                    .insert(document.path.clone(), document_terms);
+            }
+        }
+        index.edge_ngrams = Some(edge_ngrams);
+    }
+    Ok(index)
+}
+
+/// The header of an index file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileHeader {
+    /// The version of the format the file was saved in
+    pub version: u32,
+    /// The range of bytes of each section, in the order of `Section`
+    pub sections: Vec<Range<usize>>,
+}
+
+/// Function to check the magic bytes and version of an index file
+///
+/// # Returns
+///  * The header, or an error asking to reindex if the version can't be read
+pub fn read_header(bytes: &[u8]) -> Result<FileHeader> {
+    if !is_binary_index(bytes) {
+        return Err(invalid_data("Not a look index file"));
+    }
+    let mut decoder = Decoder::new(bytes);
+    decoder.take(MAGIC.len())?;
+    let version = u32::from_le_bytes(decoder.take(4)?.try_into().unwrap());
+    if version < OLDEST_READABLE_VERSION {
+        return Err(older_version_error(version));
+    }
+    if version > FORMAT_VERSION {
+        return Err(newer_version_error(version));
+    }
+
+    let mut sections = Vec::with_capacity(SECTIONS.len());
+    let mut start = HEADER_LENGTH;
+    for _ in SECTIONS {
+        let end = decoder.u64()? as usize;
+        if end < start || end > bytes.len() {
+            return Err(invalid_data("The index file is truncated"));
+        }
+        sections.push(start..end);
+        start = end;
+    }
+    Ok(FileHeader { version, sections })
+}
+
+/// The error for an index saved in a format version too old to be read
+pub fn older_version_error(version: u32) -> Error {
+    invalid_data(&format!(
+        "The index was built by an older version of look (format version {}), run `look reindex`.",
+        version
+    ))
+}
+
+/// The error for an index saved in a format version this version of look doesn't know
+pub fn newer_version_error(version: u32) -> Error {
+    invalid_data(&format!(
+        "The index was built by a newer version of look (format version {}), upgrade look or run `look reindex`.",
+        version
+    ))
+}
+
+/// The documents of the index sorted by path, a document's id is its position
+fn document_table(index: &Index) -> Vec<&Document> {
+    let documents: BTreeSet<&Document> = index
+        .document_lengths
+        .keys()
+        .chain(index.document_languages.keys())
+        .chain(index.document_metadata.keys())
+        .chain(index.document_norms.keys())
+        .chain(index.inverted_index.values().flat_map(|docs| docs.keys()))
+        .collect();
+    documents.into_iter().collect()
+}
+
+/// What the analyzer section holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzerSection {
+    pub built_with: Option<AnalyzerHeader>,
+    pub num_docs: usize,
+    /// The sum of the lengths of the documents
+    pub total_length: u64,
+    /// The number of documents with a length
+    pub length_count: usize,
+    /// When the index was built, in seconds since the UNIX epoch
+    pub built_at: Option<u64>,
+}
+
+impl AnalyzerSection {
+    /// Returns the average number of terms per document
+    pub fn average_document_length(&self) -> f64 {
+        if self.length_count == 0 {
+            return 0.0;
+        }
+        self.total_length as f64 / self.length_count as f64
+    }
+}
+
+fn encode_analyzer(index: &Index) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match &index.built_with {
+        Some(header) => {
+            bytes.push(1);
+            write_string(&mut bytes, &header.tokenizer);
+            write_string(&mut bytes, &header.pipeline);
+        }
+        None => bytes.push(0),
+    }
+    write_varint(&mut bytes, index.num_docs as u64);
+    // The average document length is known without reading every document
+    let total_length: u64 = index.document_lengths.values().map(|l| *l as u64).sum();
+    write_varint(&mut bytes, total_length);
+    write_varint(&mut bytes, index.document_lengths.len() as u64);
+    match index.built_at {
+        Some(built_at) => {
+            bytes.push(1);
+            write_varint(&mut bytes, built_at);
+        }
+        None => bytes.push(0),
+    }
+    bytes
+}
+
+/// Function to decode the analyzer section
+///
+/// # Arguments
+///  * `decoder` - The analyzer section
+///  * `version` - The format version of the index file
+pub fn decode_analyzer(decoder: &mut Decoder, version: u32) -> Result<AnalyzerSection> {
+    let built_with = match decoder.byte()? {
+        0 => None,
+        _ => Some(AnalyzerHeader {
+            tokenizer: decoder.string()?.to_owned(),
+            pipeline: decoder.string()?.to_owned(),
+        }),
+    };
+    let num_docs = decoder.varint()? as usize;
+    let total_length = decoder.varint()?;
+    let length_count = decoder.varint()? as usize;
+    // The build time was added in version 3
+    let built_at = match version {
+        2 => None,
+        _ => match decoder.byte()? {
+            0 => None,
+            _ => Some(decoder.varint()?),
+        },
+    };
+    Ok(AnalyzerSection {
+        built_with,
+        num_docs,
+        total_length,
+        length_count,
+        built_at,
+    })
+}
+
+// Which of the optional values of a document are stored
+const HAS_LENGTH: u8 = 1;
+const HAS_LANGUAGE: u8 = 1 << 1;
+const HAS_NORM: u8 = 1 << 2;
+const HAS_METADATA: u8 = 1 << 3;
+
+/// Writes the number of documents, where each document starts and the documents
+fn encode_documents(index: &Index, documents: &[&Document]) -> Vec<u8> {
+    let mut records = Vec::new();
+    let mut offsets = Vec::with_capacity(documents.len());
+    for document in documents {
+        offsets.push(records.len() as u64);
+        let length = index.document_lengths.get(*document);
+        let language = index.document_languages.get(*document);
+        let norm = index.document_norms.get(*document);
+        let metadata = index.document_metadata.get(*document);
+        let flags = [
+            (length.is_some(), HAS_LENGTH),
+            (language.is_some(), HAS_LANGUAGE),
+            (norm.is_some(), HAS_NORM),
+            (metadata.is_some(), HAS_METADATA),
+        ]
+        .iter()
+        .filter(|(present, _)| *present)
+        .fold(0, |flags, (_, flag)| flags | flag);
+
+        write_string(&mut records, &document.path);
+        records.push(flags);
+        if let Some(length) = length {
+            write_varint(&mut records, *length as u64);
+        }
+        if let Some(language) = language {
+            write_string(&mut records, language);
+        }
+        if let Some(norm) = norm {
+            records.extend_from_slice(&norm.to_le_bytes());
+        }
+        if let Some(metadata) = metadata {
+            write_varint(&mut records, metadata.modified);
+            write_varint(&mut records, metadata.size);
+            records.extend_from_slice(&metadata.content_hash.to_le_bytes());
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(8 + offsets.len() * 8 + records.len());
+    bytes.extend_from_slice(&(documents.len() as u64).to_le_bytes());
+    for offset in offsets {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+    }
+    bytes.extend_from_slice(&records);
+    bytes
+}
+
+/// A document of the document table and what's stored about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentRecord {
+    pub document: Document,
+    pub length: Option<u32>,
+    pub language: Option<String>,
+    pub norm: Option<f64>,
+    pub metadata: Option<DocumentMetadata>,
+}
+
+/// The documents section, a document is read from its id or found from its
+/// path without decoding the others
+pub struct DocumentTable<'b> {
+    len: usize,
+    offsets: &'b [u8],
+    records: &'b [u8],
+}
+
+impl<'b> DocumentTable<'b> {
+    pub fn new(bytes: &'b [u8]) -> Result<DocumentTable<'b>> {
+        let mut decoder = Decoder::new(bytes);
+        let len = decoder.u64()? as usize;
+        let offsets_length = len
+            .checked_mul(8)
+            .ok_or_else(|| invalid_data("The index file is truncated"))?;
+        let offsets = decoder.take(offsets_length)?;
+        Ok(DocumentTable {
+            len,
+            offsets,
+            records: decoder.rest(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn decoder(&self, id: usize) -> Result<Decoder<'b>> {
+        let offset = self
+            .offsets
+            .get(id * 8..id * 8 + 8)
+            .ok_or_else(|| invalid_data("The postings refer to an unknown document"))?;
+        let offset = u64::from_le_bytes(offset.try_into().unwrap()) as usize;
+        let record = self
+            .records
+            .get(offset..)
+            .ok_or_else(|| invalid_data("The index file is truncated"))?;
+        Ok(Decoder::new(record))
+    }
+
+    /// Function to read the path of a document
+    pub fn path(&self, id: usize) -> Result<&'b str> {
+        self.decoder(id)?.string()
+    }
+
+    /// Function to read a document and what's stored about it
+    pub fn record(&self, id: usize) -> Result<DocumentRecord> {
+        let mut decoder = self.decoder(id)?;
+        let document = Document::new(decoder.string()?.to_owned());
+        let flags = decoder.byte()?;
+        let length = match flags & HAS_LENGTH {
+            0 => None,
+            _ => Some(decoder.varint()? as u32),
+        };
+        let language = match flags & HAS_LANGUAGE {
+            0 => None,
+            _ => Some(decoder.string()?.to_owned()),
+        };
+        let norm = match flags & HAS_NORM {
+            0 => None,
+            _ => Some(decoder.f64()?),
+        };
+        let metadata = match flags & HAS_METADATA {
+            0 => None,
+            _ => Some(DocumentMetadata {
+                modified: decoder.varint()?,
+                size: decoder.varint()?,
+                content_hash: decoder.u64()?,
+            }),
+        };
+        Ok(DocumentRecord {
+            document,
+            length,
+            language,
+            norm,
+            metadata,
+        })
+    }
+
+    /// Function to find a document by binary search on the paths
+    pub fn find(&self, document: &Document) -> Result<Option<DocumentRecord>> {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match self.path(middle)?.cmp(document.path.as_str()) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return self.record(middle).map(Some),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The number of entries of a block of a sorted table
+///
+/// The keys are front-coded within a block, the first key of each block is
+/// stored whole so a key is found by a binary search on the blocks.
+pub const BLOCK_LENGTH: usize = 16;
+
+/// Writes string keys in order with their values as a sorted table
+fn encode_sorted_table(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut blocks = Vec::new();
+    let mut block_offsets = Vec::with_capacity(entries.len().div_ceil(BLOCK_LENGTH));
+    for (i, (key, value)) in entries.iter().enumerate() {
+        let previous_key = if i % BLOCK_LENGTH == 0 {
+            block_offsets.push(blocks.len() as u64);
+            ""
+        } else {
+            entries[i - 1].0
+        };
+        write_front_coded(&mut blocks, previous_key, key);
+        write_varint(&mut blocks, value.len() as u64);
+        blocks.extend_from_slice(value);
+    }
+
+    let mut bytes = Vec::with_capacity(8 + block_offsets.len() * 8 + blocks.len());
+    bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for offset in block_offsets {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+    }
+    bytes.extend_from_slice(&blocks);
+    bytes
+}
+
+/// String keys in order with their values, read one block at a time
+///
+/// An entry's id is its position in the table.
+#[derive(Clone, Copy)]
+pub struct SortedTable<'b> {
+    len: usize,
+    block_offsets: &'b [u8],
+    blocks: &'b [u8],
+}
+
+impl<'b> SortedTable<'b> {
+    pub fn new(bytes: &'b [u8]) -> Result<SortedTable<'b>> {
+        let mut decoder = Decoder::new(bytes);
+        let len = decoder.u64()? as usize;
+        let block_offsets_length = len
+            .div_ceil(BLOCK_LENGTH)
+            .checked_mul(8)
+            .ok_or_else(|| invalid_data("The index file is truncated"))?;
+        let block_offsets = decoder.take(block_offsets_length)?;
+        Ok(SortedTable {
+            len,
+            block_offsets,
+            blocks: decoder.rest(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.len.div_ceil(BLOCK_LENGTH)
+    }
+
+    fn block_decoder(&self, block: usize) -> Result<Decoder<'b>> {
+        let offset = self
+            .block_offsets
+            .get(block * 8..block * 8 + 8)
+            .ok_or_else(|| invalid_data("Unknown block in the index file"))?;
+        let offset = u64::from_le_bytes(offset.try_into().unwrap()) as usize;
+        let bytes = self
+            .blocks
+            .get(offset..)
+            .ok_or_else(|| invalid_data("The index file is truncated"))?;
+        Ok(Decoder::new(bytes))
+    }
+
+    /// Function to decode the entries of a block
+    ///
+    /// # Returns
+    ///  * The keys and values of the block, the ids of the entries start at
+    ///    `block * BLOCK_LENGTH`
+    pub fn block(&self, block: usize) -> Result<Vec<(String, &'b [u8])>> {
+        let mut decoder = self.block_decoder(block)?;
+        let count = BLOCK_LENGTH.min(self.len.saturating_sub(block * BLOCK_LENGTH));
+        let mut entries = Vec::with_capacity(count);
+        let mut key = String::new();
+        for _ in 0..count {
+            key = decoder.front_coded(&key)?;
+            let value_length = decoder.varint()? as usize;
+            entries.push((key.clone(), decoder.take(value_length)?));
+        }
+        Ok(entries)
+    }
+
+    /// Function to read an entry from its id
+    pub fn entry(&self, id: usize) -> Result<(String, &'b [u8])> {
+        if id >= self.len {
+            return Err(invalid_data("Unknown entry in the index file"));
+        }
+        let mut entries = self.block(id / BLOCK_LENGTH)?;
+        Ok(entries.swap_remove(id % BLOCK_LENGTH))
+    }
+
+    /// Function to read the entries in order, starting from an id
+    pub fn iter_from(self, id: usize) -> impl Iterator<Item = Result<(String, &'b [u8])>> {
+        (id / BLOCK_LENGTH..self.block_count())
+            .flat_map(move |block| match self.block(block) {
+                Ok(entries) => entries.into_iter().map(Ok).collect(),
+                Err(error) => vec![Err(error)],
+            })
+            .skip(id % BLOCK_LENGTH)
+    }
+
+    /// Function to find the first entry whose key is within a lower bound
+    ///
+    /// # Returns
+    ///  * The id, key and value of the entry, `None` if every key is below the bound
+    pub fn seek(&self, lower_bound: Bound<&str>) -> Result<Option<(usize, String, &'b [u8])>> {
+        let first_block = match lower_bound {
+            Bound::Unbounded => 0,
+            Bound::Included(key) | Bound::Excluded(key) => {
+                // The first block whose first key is after the bound, the
+                // entry is in the block before it or is its first one
+                let (mut low, mut high) = (0, self.block_count());
+                while low < high {
+                    let middle = low + (high - low) / 2;
+                    let mut decoder = self.block_decoder(middle)?;
+                    if decoder.front_coded("")?.as_str() <= key {
+                        low = middle + 1;
+                    } else {
+                        high = middle;
+                    }
+                }
+                low.saturating_sub(1)
+            }
+        };
+        let first_id = first_block * BLOCK_LENGTH;
+        for (i, entry) in self.iter_from(first_id).enumerate() {
+            let (key, value) = entry?;
+            let within = match lower_bound {
+                Bound::Included(bound) => key.as_str() >= bound,
+                Bound::Excluded(bound) => key.as_str() > bound,
+                Bound::Unbounded => true,
+            };
+            if within {
+                return Ok(Some((first_id + i, key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Function to find the entry of a key
+    ///
+    /// # Returns
+    ///  * The id and value of the entry, `None` if the key isn't in the table
+    pub fn get(&self, key: &str) -> Result<Option<(usize, &'b [u8])>> {
+        Ok(self
+            .seek(Bound::Included(key))?
+            .filter(|(_, found, _)| found == key)
+            .map(|(id, _, value)| (id, value)))
+    }
+}
+
+/// A term of the term dictionary, and where its postings are
+#[derive(Debug, Clone, PartialEq)]
+pub struct TermEntry {
+    pub term: String,
+    pub idf: Option<f64>,
+    /// The number of documents containing the term
+    pub document_frequency: usize,
+    /// The bytes of its postings in the postings section
+    pub postings: Range<usize>,
+}
+
+/// Encodes the term dictionary and the postings, the terms are in order
+fn encode_terms(index: &Index, document_ids: &HashMap<&Document, u64>) -> (Vec<u8>, Vec<u8>) {
+    let mut entries = Vec::with_capacity(index.inverted_index.len());
+    let mut postings = Vec::new();
+    for (term, docs) in &index.inverted_index {
+        let postings_start = postings.len();
+        encode_postings(&mut postings, docs, document_ids);
+
+        let mut value = Vec::new();
+        match index.idf.get(term) {
+            Some(idf) => {
+                value.push(1);
+                value.extend_from_slice(&idf.to_le_bytes());
+            }
+            None => value.push(0),
+        }
+        write_varint(&mut value, docs.len() as u64);
+        write_varint(&mut value, postings_start as u64);
+        write_varint(&mut value, (postings.len() - postings_start) as u64);
+        entries.push((term.0.as_str(), value));
+    }
+    (encode_sorted_table(&entries), postings)
+}
+
+/// Function to decode the value of a term in the term dictionary
+pub fn decode_term_entry(term: String, value: &[u8]) -> Result<TermEntry> {
+    let mut decoder = Decoder::new(value);
+    let idf = match decoder.byte()? {
+        0 => None,
+        _ => Some(decoder.f64()?),
+    };
+    let document_frequency = decoder.varint()? as usize;
+    let postings_start = decoder.varint()? as usize;
+    let postings_end = postings_start
+        .checked_add(decoder.varint()? as usize)
+        .ok_or_else(|| invalid_data("The index file is truncated"))?;
+    Ok(TermEntry {
+        term,
+        idf,
+        document_frequency,
+        postings: postings_start..postings_end,
+    })
+}
+
+/// Writes the documents of a term by increasing id, each id as the
+/// difference with the previous one, followed by its positions
+fn encode_postings(bytes: &mut Vec<u8>, docs: &Postings, document_ids: &HashMap<&Document, u64>) {
+    let mut docs: Vec<(u64, &Vec<u32>)> = docs
+        .iter()
+        .map(|(document, positions)| (document_ids[document], positions))
+        .collect();
+    docs.sort_unstable_by_key(|(id, _)| *id);
+
+    write_varint(bytes, docs.len() as u64);
+    let mut previous_id = 0;
+    for (id, positions) in docs {
+        write_varint(bytes, id - previous_id);
+        previous_id = id;
+        write_varint(bytes, positions.len() as u64);
+        // The positions are usually increasing, but their order is kept as is
+        let mut previous_position = 0i64;
+        for position in positions {
+            write_varint(bytes, zigzag(*position as i64 - previous_position));
+            previous_position = *position as i64;
+        }
+    }
+}
+
+/// Function to decode the postings of a term
+///
+/// # Arguments
+///  * `postings` - The postings section
+///  * `entry` - The term, from the term dictionary
+///  * `document` - Returns the document of an id
+pub fn decode_postings(
+    postings: &[u8],
+    entry: &TermEntry,
+    mut document: impl FnMut(usize) -> Result<Document>,
+) -> Result<Postings> {
+    let bytes = postings
+        .get(entry.postings.clone())
+        .ok_or_else(|| invalid_data("The index file is truncated"))?;
+    let mut decoder = Decoder::new(bytes);
+    let count = decoder.varint()? as usize;
+    // The counts come from the file, every value takes at least a byte
+    let mut docs = Postings::with_capacity(count.min(bytes.len()));
+    let mut id = 0usize;
+    for _ in 0..count {
+        id = id
+            .checked_add(decoder.varint()? as usize)
+            .ok_or_else(|| invalid_data("Unknown document in the index file"))?;
+        let position_count = decoder.varint()? as usize;
+        let mut positions = Vec::with_capacity(position_count.min(bytes.len()));
+        let mut position = 0i64;
+        for _ in 0..position_count {
+            position = position.wrapping_add(unzigzag(decoder.varint()?));
+            positions.push(position as u32);
+        }
+        docs.insert(document(id)?, positions);
+    }
+    Ok(docs)
+}
+
+/// Writes the words of each document and the terms of each prefix, the terms
+/// as their ids in the dictionary
+fn encode_edge_ngrams(index: &Index, document_ids: &HashMap<&Document, u64>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let Some(edge_ngrams) = &index.edge_ngrams else {
+        bytes.push(0);
+        return bytes;
+    };
+    bytes.push(1);
+    write_varint(&mut bytes, edge_ngrams.min_length as u64);
+    write_varint(&mut bytes, edge_ngrams.max_length as u64);
+
+    let term_ids: HashMap<&str, u64> = index
+        .inverted_index
+        .keys()
+        .enumerate()
+        .map(|(id, term)| (term.0.as_str(), id as u64))
+        .collect();
+
+    let document_words = encode_document_words(edge_ngrams, document_ids, &term_ids);
+    write_varint(&mut bytes, document_words.len() as u64);
+    bytes.extend_from_slice(&document_words);
+
+    let mut prefixes: Vec<(&str, Vec<u8>)> = edge_ngrams
+        .prefixes
+        .iter()
+        .map(|(prefix, terms)| {
+            let mut ids: Vec<u64> = terms
+                .iter()
+                .filter_map(|term| term_ids.get(term.as_str()).copied())
+                .collect();
+            ids.sort_unstable();
+            let mut value = Vec::new();
+            write_varint(&mut value, ids.len() as u64);
+            let mut previous_id = 0;
+            for id in ids {
+                write_varint(&mut value, id - previous_id);
+                previous_id = id;
+            }
+            (prefix.as_str(), value)
+        })
+        .collect();
+    prefixes.sort_unstable();
+
+    bytes.extend_from_slice(&encode_sorted_table(&prefixes));
+    bytes
+}
+
+/// Writes the documents by id, each with its words sorted and the ids of their terms
+fn encode_document_words(
+    edge_ngrams: &EdgeNgrams,
+    document_ids: &HashMap<&Document, u64>,
+    term_ids: &HashMap<&str, u64>,
+) -> Vec<u8> {
+    let path_ids: HashMap<&str, u64> = document_ids
+        .iter()
+        .map(|(document, id)| (document.path.as_str(), *id))
+        .collect();
+    let mut documents: Vec<(u64, &BTreeMap<String, String>)> = edge_ngrams
+        .document_words
+        .iter()
+        .filter_map(|(path, words)| Some((*path_ids.get(path.as_str())?, words)))
+        .collect();
+    documents.sort_unstable_by_key(|(id, _)| *id);
+
+    let mut bytes = Vec::new();
+    write_varint(&mut bytes, documents.len() as u64);
+    let mut previous_id = 0;
+    for (id, words) in documents {
+        write_varint(&mut bytes, id - previous_id);
+        previous_id = id;
+        let words: Vec<(&str, u64)> = words
+            .iter()
+            .filter_map(|(word, term)| Some((word.as_str(), *term_ids.get(term.as_str())?)))
+            .collect();
+        write_varint(&mut bytes, words.len() as u64);
+        let mut previous_word = "";
+        for (word, term_id) in words {
+            write_front_coded(&mut bytes, previous_word, word);
+            write_varint(&mut bytes, term_id);
+            previous_word = word;
+        }
+    }
+    bytes
+}
+
+/// What the edge n-grams section holds
+pub struct EdgeNgramsSection<'b> {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// The words of each document, read with `decode_document_words`, `None`
+    /// before version 5
+    pub document_words: Option<&'b [u8]>,
+    /// The prefixes with the ids of their terms
+    pub prefixes: SortedTable<'b>,
+}
+
+/// Function to read the edge n-grams section
+///
+/// # Arguments
+///  * `bytes` - The edge n-grams section
+///  * `version` - The format version of the index file
+///
+/// # Returns
+///  * The section, `None` if the edge n-grams are disabled
+pub fn read_edge_ngrams(bytes: &[u8], version: u32) -> Result<Option<EdgeNgramsSection<'_>>> {
+    let mut decoder = Decoder::new(bytes);
+    if decoder.byte()? == 0 {
+        return Ok(None);
+    }
+    let min_length = decoder.varint()? as usize;
+    let max_length = decoder.varint()? as usize;
+    // The words of the documents were added in version 5
+    let document_words = match version {
+        2..=4 => None,
+        _ => {
+            let length = decoder.varint()? as usize;
+            Some(decoder.take(length)?)
+        }
+    };
+    let prefixes = SortedTable::new(decoder.rest())?;
+    Ok(Some(EdgeNgramsSection {
+        min_length,
+        max_length,
+        document_words,
+        prefixes,
+    }))
+}
+
+/// The id of a document, with its words and the ids of their terms
+pub type DocumentWords = (usize, Vec<(String, usize)>);
+
+/// Function to decode the words of each document
+///
+/// # Returns
+///  * The documents by id, each with its words and the ids of their terms
+pub fn decode_document_words(bytes: &[u8]) -> Result<Vec<DocumentWords>> {
+    let mut decoder = Decoder::new(bytes);
+    let count = decoder.varint()? as usize;
+    let mut documents = Vec::with_capacity(count.min(bytes.len()));
+    let mut id = 0usize;
+    for _ in 0..count {
+        id = id
+            .checked_add(decoder.varint()? as usize)
+            .ok_or_else(|| invalid_data("Unknown document in the index file"))?;
+        let word_count = decoder.varint()? as usize;
+        let mut words = Vec::with_capacity(word_count.min(bytes.len()));
+        let mut word = String::new();
+        for _ in 0..word_count {
+            word = decoder.front_coded(&word)?;
+            words.push((word.clone(), decoder.varint()? as usize));
+        }
+        documents.push((id, words));
+    }
+    Ok(documents)
+}
+
+/// Function to decode the ids of the terms of a prefix
+pub fn decode_term_ids(value: &[u8]) -> Result<Vec<usize>> {
+    let mut decoder = Decoder::new(value);
+    let count = decoder.varint()? as usize;
+    let mut ids = Vec::with_capacity(count.min(value.len()));
+    let mut id = 0usize;
+    for _ in 0..count {
+        id = id
+            .checked_add(decoder.varint()? as usize)
+            .ok_or_else(|| invalid_data("Unknown term in the index file"))?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+/// Reads the values of an index file one after the other
+pub struct Decoder<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Decoder<'b> {
+    pub fn new(bytes: &'b [u8]) -> Decoder<'b> {
+        Decoder { bytes, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'b [u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("The index file is truncated"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// The bytes not read yet
+    fn rest(&mut self) -> &'b [u8] {
+        let rest = &self.bytes[self.position..];
+        self.position = self.bytes.len();
+        rest
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads an unsigned LEB128 varint, 7 bits per byte
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("Invalid varint in the index file"))
+    }
+
+    fn string(&mut self) -> Result<&'b str> {
+        let length = self.varint()? as usize;
+        std::str::from_utf8(self.take(length)?)
+            .map_err(|_| invalid_data("Invalid UTF-8 in the index file"))
+    }
+
+    /// Reads a string stored as the length it shares with the previous one and the rest
+    fn front_coded(&mut self, previous: &str) -> Result<String> {
+        let shared = self.varint()? as usize;
+        let shared_prefix = previous
+            .get(..shared)
+            .ok_or_else(|| invalid_data("Invalid term in the index file"))?;
+        Ok(shared_prefix.to_owned() + self.string()?)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    write_varint(bytes, string.len() as u64);
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Writes a string as the length it shares with the previous one and the rest,
+/// sorted strings like the terms of the index share long prefixes
+fn write_front_coded(bytes: &mut Vec<u8>, previous: &str, string: &str) {
+    let mut shared = previous
+        .bytes()
+        .zip(string.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !string.is_char_boundary(shared) {
+        shared -= 1;
+    }
+    write_varint(bytes, shared as u64);
+    write_string(bytes, &string[shared..]);
+}
+
+/// Maps signed integers to unsigned ones, small magnitudes to small values
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            assert_eq!(Decoder::new(&bytes).varint().unwrap(), value);
+        }
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 300);
+        assert_eq!(bytes, vec![0xac, 0x02]);
+        for value in [0, 1, -1, 1000, -1000] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_front_coding() {
+        let mut bytes = Vec::new();
+        write_front_coded(&mut bytes, "deploy", "deployment");
+        write_front_coded(&mut bytes, "añil", "año");
+        assert_eq!(bytes[0], 6);
+        let mut decoder = Decoder::new(&bytes);
+        assert_eq!(decoder.front_coded("deploy").unwrap(), "deployment");
+        assert_eq!(decoder.front_coded("añil").unwrap(), "año");
+    }
+
+    #[test]
+    fn test_sorted_table() {
+        let keys: Vec<String> = (0..40).map(|i| format!("term{:02}", i * 2)).collect();
+        let entries: Vec<(&str, Vec<u8>)> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (key.as_str(), vec![i as u8]))
+            .collect();
+        let bytes = encode_sorted_table(&entries);
+        let table = SortedTable::new(&bytes).unwrap();
+        assert_eq!(table.len(), 40);
+        assert_eq!(table.block_count(), 3);
+
+        assert_eq!(table.entry(17).unwrap(), ("term34".to_owned(), &[17u8][..]));
+        assert_eq!(table.get("term34").unwrap(), Some((17, &[17u8][..])));
+        assert_eq!(table.get("term35").unwrap(), None);
+        assert_eq!(table.get("term").unwrap(), None);
+        // The first key of a block
+        assert_eq!(table.get("term32").unwrap(), Some((16, &[16u8][..])));
+
+        let seek = |bound| table.seek(bound).unwrap().map(|(id, key, _)| (id, key));
+        assert_eq!(seek(Bound::Unbounded), Some((0, "term00".to_owned())));
+        assert_eq!(
+            seek(Bound::Included("term31")),
+            Some((16, "term32".to_owned()))
+        );
+        assert_eq!(
+            seek(Bound::Excluded("term32")),
+            Some((17, "term34".to_owned()))
+        );
+        assert_eq!(
+            seek(Bound::Excluded("term30")),
+            Some((16, "term32".to_owned()))
+        );
+        assert_eq!(seek(Bound::Included("a")), Some((0, "term00".to_owned())));
+        assert_eq!(seek(Bound::Excluded("term78")), None);
+
+        let empty = encode_sorted_table(&[]);
+        let empty = SortedTable::new(&empty).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.seek(Bound::Unbounded).unwrap(), None);
+    }
+
+    #[test]
+    fn test_encode_and_decode_index() {
+        let mut index = Index::new();
+        index.enable_edge_ngrams(2, 4);
+        for (path, text) in [
+            ("notes.txt", "Kubernetes clusters, kubernetes upgrade"),
+            ("español.md", "La migración de la base de datos"),
+            ("more.txt", "Upgrading the database"),
+        ] {
+            let document = Document::new(path.to_owned());
+            index.store_processed_text_in_index(&document, text);
+            index
+                .document_metadata
+                .insert(document, DocumentMetadata::new(1_700_000_000, 42, text));
+        }
+        index.calculate_idf();
+        index.built_at = Some(1_700_000_000);
+
+        let bytes = encode_index(&index);
+        assert!(is_binary_index(&bytes));
+        assert_eq!(bytes, encode_index(&index));
+        let decoded = decode_index(&bytes).unwrap();
+        assert_eq!(decoded.inverted_index, index.inverted_index);
+        assert_eq!(decoded.idf, index.idf);
+        assert_eq!(decoded.document_norms, index.document_norms);
+        assert_eq!(decoded.document_lengths, index.document_lengths);
+        assert_eq!(decoded.document_metadata, index.document_metadata);
+        assert_eq!(decoded.document_languages, index.document_languages);
+        assert_eq!(decoded.num_docs, index.num_docs);
+        assert_eq!(decoded.built_with, index.built_with);
+        assert_eq!(decoded.built_at, index.built_at);
+        assert_eq!(decoded.edge_ngrams, index.edge_ngrams);
+
+        // Smaller than the JSON export
+        let json_path = std::env::temp_dir().join("look_test_binary_format.json");
+        index.save_index_to_json_file(&json_path).unwrap();
+        let json_length = std::fs::metadata(&json_path).unwrap().len() as usize;
+        std::fs::remove_file(&json_path).unwrap();
+        assert!(bytes.len() < json_length / 2);
+    }
+
+    #[test]
+    fn test_version_2_is_migrated() {
+        let mut index = Index::new();
+        let document = Document::new("notes.txt".to_owned());
+        index.store_processed_text_in_index(&document, "Kubernetes clusters");
+        index.calculate_idf();
+
+        // A version 2 file is a version 3 one without the build time, the
+        // last byte of the analyzer section
+        let bytes = encode_index(&index);
+        let header = read_header(&bytes).unwrap();
+        let analyzer_end = header.sections[Section::Analyzer as usize].end;
+        let mut version_2 = Vec::new();
+        version_2.extend_from_slice(MAGIC);
+        version_2.extend_from_slice(&2u32.to_le_bytes());
+        for section in &header.sections {
+            version_2.extend_from_slice(&(section.end as u64 - 1).to_le_bytes());
+        }
+        version_2.extend_from_slice(&bytes[HEADER_LENGTH..analyzer_end - 1]);
+        version_2.extend_from_slice(&bytes[analyzer_end..]);
+
+        let decoded = decode_index(&version_2).unwrap();
+        assert_eq!(decoded.built_at, None);
+        assert_eq!(decoded.built_with, index.built_with);
+        assert_eq!(decoded.inverted_index, index.inverted_index);
+        assert_eq!(decoded.idf, index.idf);
+        // Saved again in the current version
+        assert_eq!(encode_index(&decoded), bytes);
+
+        // The edge n-grams of a version 3 file are built again
+        let mut index = Index::new();
+        index.enable_edge_ngrams(2, 4);
+        index.store_processed_text_in_index(&document, "Kubernetes clusters");
+        let mut version_3 = encode_index(&index);
+        version_3[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&3u32.to_le_bytes());
+        let decoded = decode_index(&version_3).unwrap();
+        assert_eq!(decoded.edge_ngrams, None);
+        assert_eq!(decoded.inverted_index, index.inverted_index);
+    }
+
+    #[test]
+    fn test_invalid_index_files() {
+        let index = Index::new();
+        let bytes = encode_index(&index);
+
+        let error = decode_index(b"{\"inverted_index\": []}").unwrap_err();
+        assert_eq!(error.to_string(), "Not a look index file");
+
+        let with_version = |version: u32| {
+            let mut bytes = bytes.clone();
+            bytes[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&version.to_le_bytes());
+            bytes
+        };
+        let error = decode_index(&with_version(1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The index was built by an older version of look (format version 1), run `look reindex`."
+        );
+        let error = decode_index(&with_version(FORMAT_VERSION + 1)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "The index was built by a newer version of look (format version {}), upgrade look or run `look reindex`.",
+                FORMAT_VERSION + 1
+            )
+        );
+
+        let error = decode_index(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.to_string(), "The index file is truncated");
+    }
+
+    #[test]
+    fn test_corrupted_counts() {
+        let decode = |postings: &[u8]| {
+            let entry = TermEntry {
+                term: "cluster".to_owned(),
+                idf: None,
+                document_frequency: 1,
+                postings: 0..postings.len(),
+            };
+            decode_postings(postings, &entry, |id| Ok(Document::new(id.to_string())))
+        };
+        // A count larger than the postings isn't allocated up front
+        let mut postings = Vec::new();
+        write_varint(&mut postings, u64::MAX);
+        let error = decode(&postings).unwrap_err();
+        assert_eq!(error.to_string(), "The index file is truncated");
+
+        let mut postings = Vec::new();
+        for value in [1, 0, u64::MAX] {
+            write_varint(&mut postings, value);
+        }
+        let error = decode(&postings).unwrap_err();
+        assert_eq!(error.to_string(), "The index file is truncated");
+
+        let mut postings = Vec::new();
+        for value in [2, u64::MAX, 0, u64::MAX, 0] {
+            write_varint(&mut postings, value);
+        }
+        let error = decode(&postings).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown document in the index file");
+
+        let mut ids = Vec::new();
+        write_varint(&mut ids, u64::MAX);
+        assert!(decode_term_ids(&ids).is_err());
+
+        // The end of the postings of a term doesn't overflow
+        let mut value = vec![0];
+        for number in [1, u64::MAX, 1] {
+            write_varint(&mut value, number);
+        }
+        let error = decode_term_entry("cluster".to_owned(), &value).unwrap_err();
+        assert_eq!(error.to_string(), "The index file is truncated");
+
+        let mut decoder = Decoder::new(b"look");
+        decoder.take(1).unwrap();
+        assert!(decoder.take(usize::MAX).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Debug, Hash, PartialOrd, Ord)]
 pub struct Document {
@@ -20,5 +21,44 @@ impl std::fmt::Display for Document {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Clone)]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
 pub struct Term(pub String);
+
+/// The positions of a term in each document that contains it
+pub type Postings = HashMap<Document, Vec<u32>>;
+
+/// What we know about a document's file the last time it was indexed
+///
+/// Used by incremental reindexing to skip files that haven't changed.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct DocumentMetadata {
+    /// Modification time, in nanoseconds since the UNIX epoch
+    pub modified: u64,
+    /// File size in bytes
+    pub size: u64,
+    /// Hash of the file contents
+    pub content_hash: u64,
+}
+
+impl DocumentMetadata {
+    pub fn new(modified: u64, size: u64, content: &str) -> DocumentMetadata {
+        DocumentMetadata {
+            modified,
+            size,
+            content_hash: hash_content(content),
+        }
+    }
+}
+
+/// Hashes the contents of a document with 64-bit FNV-1a
+///
+/// We don't use `DefaultHasher` because its output may change between
+/// Rust releases, and the hash is stored on disk.
+pub fn hash_content(content: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
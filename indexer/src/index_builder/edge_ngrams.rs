@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Shortest prefix stored by default, a single character matches too many terms
+pub const DEFAULT_EDGE_NGRAM_MIN_LENGTH: usize = 2;
+
+/// Longest prefix stored by default, longer prefixes fall back to a prefix scan
+pub const DEFAULT_EDGE_NGRAM_MAX_LENGTH: usize = 15;
+
+/// The edge n-grams of the words of an index: for each prefix, the terms of
+/// the words starting with it
+///
+/// A partially typed word is looked up directly instead of scanning the
+/// terms sharing its prefix, at the cost of storing every prefix. The
+/// prefixes are the ones of the words as written, before stemming, so
+/// "deploym" finds the term "deploy" of "deployments", and the ones of the
+/// terms themselves.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EdgeNgrams {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// The terms of the words starting with each prefix, a word shorter than
+    /// `max_length` is its own longest prefix
+    pub prefixes: HashMap<String, BTreeSet<String>>,
+    /// The words of each document, by path, that aren't their own term with
+    /// the term they lead to, so their prefixes are removed with the last
+    /// document holding them
+    #[serde(default)]
+    pub document_words: HashMap<String, BTreeMap<String, String>>,
+    /// The prefixes leading to each term, built the first time a term is removed
+    #[serde(skip)]
+    term_prefixes: Option<HashMap<String, HashSet<String>>>,
+}
+
+impl PartialEq for EdgeNgrams {
+    fn eq(&self, other: &Self) -> bool {
+        self.min_length == other.min_length
+            && self.max_length == other.max_length
+            && self.prefixes == other.prefixes
+            && self.document_words == other.document_words
+    }
+}
+
+impl Eq for EdgeNgrams {}
+
+impl EdgeNgrams {
+    pub fn new(min_length: usize, max_length: usize) -> EdgeNgrams {
+        EdgeNgrams {
+            min_length,
+            max_length,
+            prefixes: HashMap::new(),
+            document_words: HashMap::new(),
+            term_prefixes: None,
+        }
+    }
+
+    /// Whether the terms starting with `prefix` can be found in the edge n-grams
+    pub fn covers(&self, prefix: &str) -> bool {
+        let length = prefix.chars().count();
+        self.min_length <= length && length <= self.max_length
+    }
+
+    /// Returns the terms of the words starting with `prefix`, `None` if the
+    /// prefix isn't covered
+    pub fn terms_starting_with(&self, prefix: &str) -> Option<Vec<&String>> {
+        if !self.covers(prefix) {
+            return None;
+        }
+        Some(
+            self.prefixes
+                .get(prefix)
+                .map(|terms| terms.iter().collect())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Adds the prefixes of a word of the index, leading to the term it was analyzed into
+    ///
+    /// # Arguments
+    ///  * `document` - The path of the document the word is in
+    ///  * `word` - The word before stemming
+    ///  * `term` - The term stored in the index for the word
+    pub fn insert_word(&mut self, document: &str, word: &str, term: &str) {
+        if word != term && self.min_length <= word.chars().count() {
+            let words = match self.document_words.get_mut(document) {
+                Some(words) => words,
+                None => self.document_words.entry(document.to_owned()).or_default(),
+            };
+            if words.get(word).map(String::as_str) != Some(term) {
+                words.insert(word.to_owned(), term.to_owned());
+            }
+            self.insert_prefixes(term, term);
+        }
+        self.insert_prefixes(word, term);
+    }
+
+    /// Adds the prefixes of a word, leading to `term`
+    fn insert_prefixes(&mut self, word: &str, term: &str) {
+        let prefixes = self.edge_ngrams(word);
+        // A word already added has its longest prefix leading to the term
+        let longest_prefix = match prefixes.last() {
+            Some(longest_prefix) => *longest_prefix,
+            None => return,
+        };
+        if self
+            .prefixes
+            .get(longest_prefix)
+            .is_some_and(|terms| terms.contains(term))
+        {
+            return;
+        }
+        for prefix in prefixes {
+            self.prefixes
+                .entry(prefix.to_owned())
+                .or_default()
+                .insert(term.to_owned());
+            if let Some(term_prefixes) = &mut self.term_prefixes {
+                term_prefixes
+                    .entry(term.to_owned())
+                    .or_default()
+                    .insert(prefix.to_owned());
+            }
+        }
+    }
+
+    /// Adds the prefixes of the words of other edge n-grams with the same lengths
+    pub fn merge(&mut self, other: EdgeNgrams) {
+        self.document_words.extend(other.document_words);
+        for (prefix, terms) in other.prefixes {
+            for term in terms {
+                if let Some(term_prefixes) = &mut self.term_prefixes {
+                    term_prefixes
+                        .entry(term.clone())
+                        .or_default()
+                        .insert(prefix.clone());
+                }
+                self.prefixes
+                    .entry(prefix.clone())
+                    .or_default()
+                    .insert(term);
+            }
+        }
+    }
+
+    /// Removes a term that is no longer in the index
+    pub fn remove_term(&mut self, term: &str) {
+        let prefixes = &self.prefixes;
+        let term_prefixes = self.term_prefixes.get_or_insert_with(|| {
+            let mut term_prefixes: HashMap<String, HashSet<String>> = HashMap::new();
+            for (prefix, terms) in prefixes {
+                for term in terms {
+                    term_prefixes
+                        .entry(term.clone())
+                        .or_default()
+                        .insert(prefix.clone());
+                }
+            }
+            term_prefixes
+        });
+        for prefix in term_prefixes.remove(term).unwrap_or_default() {
+            if let Some(terms) = self.prefixes.get_mut(&prefix) {
+                terms.remove(term);
+                if terms.is_empty() {
+                    self.prefixes.remove(&prefix);
+                }
+            }
+        }
+    }
+
+    /// Removes the words of documents that are no longer in the index
+    ///
+    /// A prefix of a removed word keeps leading to its term while the term
+    /// or a word of another document with the same term starts with it. The
+    /// terms no longer in the index are removed with `remove_term`.
+    ///
+    /// # Arguments
+    ///  * `documents` - The paths of the removed documents
+    pub fn remove_documents<'d>(&mut self, documents: impl IntoIterator<Item = &'d str>) {
+        let mut removed_words = Vec::new();
+        for document in documents {
+            if let Some(words) = self.document_words.remove(document) {
+                removed_words.extend(words);
+            }
+        }
+        if removed_words.is_empty() {
+            return;
+        }
+
+        // The words of the same terms in the other documents, read in one pass
+        let terms: HashSet<&str> = removed_words
+            .iter()
+            .map(|(_, term)| term.as_str())
+            .collect();
+        let mut remaining_words: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for words in self.document_words.values() {
+            for (word, term) in words {
+                if terms.contains(term.as_str()) {
+                    remaining_words
+                        .entry(term.as_str())
+                        .or_default()
+                        .insert(word.as_str());
+                }
+            }
+        }
+        let mut unused_prefixes = Vec::new();
+        for (word, term) in &removed_words {
+            let remaining_words = remaining_words.get(term.as_str());
+            for prefix in self.edge_ngrams(word) {
+                let used = term.starts_with(prefix)
+                    || remaining_words
+                        .is_some_and(|words| words.iter().any(|word| word.starts_with(prefix)));
+                if !used {
+                    unused_prefixes.push((prefix.to_owned(), term.clone()));
+                }
+            }
+        }
+
+        for (prefix, term) in unused_prefixes {
+            if let Some(terms) = self.prefixes.get_mut(&prefix) {
+                terms.remove(&term);
+                if terms.is_empty() {
+                    self.prefixes.remove(&prefix);
+                }
+            }
+            if let Some(prefixes) = self
+                .term_prefixes
+                .as_mut()
+                .and_then(|term_prefixes| term_prefixes.get_mut(&term))
+            {
+                prefixes.remove(&prefix);
+            }
+        }
+    }
+
+    /// The number of (prefix, term) pairs stored
+    pub fn len(&self) -> usize {
+        self.prefixes.values().map(|terms| terms.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// The prefixes of a word between the minimum and maximum lengths
+    fn edge_ngrams<'t>(&self, word: &'t str) -> Vec<&'t str> {
+        word.char_indices()
+            .map(|(offset, character)| &word[..offset + character.len_utf8()])
+            .skip(self.min_length.saturating_sub(1))
+            .take(self.max_length + 1 - self.min_length.max(1))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_ngrams() {
+        let mut edge_ngrams = EdgeNgrams::new(2, 4);
+        assert_eq!(
+            edge_ngrams.edge_ngrams("kubectl"),
+            vec!["ku", "kub", "kube"]
+        );
+        assert_eq!(edge_ngrams.edge_ngrams("añil"), vec!["añ", "añi", "añil"]);
+        assert!(edge_ngrams.edge_ngrams("k").is_empty());
+
+        edge_ngrams.insert_word("notes.md", "kubectl", "kubectl");
+        edge_ngrams.insert_word("notes.md", "kube", "kube");
+        edge_ngrams.insert_word("notes.md", "kafka", "kafka");
+        assert_eq!(
+            edge_ngrams.terms_starting_with("kub"),
+            Some(vec![&"kube".to_owned(), &"kubectl".to_owned()])
+        );
+        assert_eq!(edge_ngrams.terms_starting_with("kx"), Some(vec![]));
+        // Outside the lengths the index has to be scanned
+        assert_eq!(edge_ngrams.terms_starting_with("k"), None);
+        assert_eq!(edge_ngrams.terms_starting_with("kubec"), None);
+        assert_eq!(edge_ngrams.len(), 9);
+
+        edge_ngrams.remove_term("kubectl");
+        assert_eq!(
+            edge_ngrams.terms_starting_with("kub"),
+            Some(vec![&"kube".to_owned()])
+        );
+        edge_ngrams.remove_term("kube");
+        edge_ngrams.remove_term("kafka");
+        assert!(edge_ngrams.is_empty());
+    }
+
+    #[test]
+    fn test_edge_ngrams_of_stemmed_words() {
+        let mut edge_ngrams = EdgeNgrams::new(2, 15);
+        edge_ngrams.insert_word("notes.md", "deployments", "deploy");
+        edge_ngrams.insert_word("notes.md", "deploying", "deploy");
+        edge_ngrams.insert_word("notes.md", "deck", "deck");
+        // The prefixes of the words lead to their term
+        assert_eq!(
+            edge_ngrams.terms_starting_with("deploym"),
+            Some(vec![&"deploy".to_owned()])
+        );
+        assert_eq!(
+            edge_ngrams.terms_starting_with("de"),
+            Some(vec![&"deck".to_owned(), &"deploy".to_owned()])
+        );
+
+        let mut other = EdgeNgrams::new(2, 15);
+        other.insert_word("other.md", "decks", "deck");
+        edge_ngrams.merge(other);
+        assert_eq!(
+            edge_ngrams.terms_starting_with("decks"),
+            Some(vec![&"deck".to_owned()])
+        );
+
+        edge_ngrams.remove_term("deploy");
+        assert_eq!(edge_ngrams.terms_starting_with("deploym"), Some(vec![]));
+        edge_ngrams.insert_word("notes.md", "deployed", "deploy");
+        edge_ngrams.remove_term("deck");
+        edge_ngrams.remove_term("deploy");
+        assert!(edge_ngrams.is_empty());
+    }
+
+    #[test]
+    fn test_removed_words_lose_their_prefixes() {
+        let mut edge_ngrams = EdgeNgrams::new(2, 15);
+        edge_ngrams.insert_word("deployments.md", "deployments", "deploy");
+        edge_ngrams.insert_word("deploying.md", "deploying", "deploy");
+        edge_ngrams.insert_word("both.md", "deploying", "deploy");
+        let term = "deploy".to_owned();
+        let deploy = Some(vec![&term]);
+
+        edge_ngrams.remove_documents(["deployments.md"]);
+        assert_eq!(edge_ngrams.terms_starting_with("deploym"), Some(vec![]));
+        assert_eq!(edge_ngrams.terms_starting_with("deployi"), deploy);
+        // The prefixes of the term stay while the term is in the index
+        assert_eq!(edge_ngrams.terms_starting_with("deploy"), deploy);
+
+        // A word stays while another document holds it
+        edge_ngrams.remove_documents(["deploying.md"]);
+        assert_eq!(edge_ngrams.terms_starting_with("deployi"), deploy);
+        edge_ngrams.remove_documents(["both.md"]);
+        assert_eq!(edge_ngrams.terms_starting_with("deployi"), Some(vec![]));
+        assert_eq!(edge_ngrams.terms_starting_with("deploy"), deploy);
+        assert!(edge_ngrams.document_words.is_empty());
+    }
+}
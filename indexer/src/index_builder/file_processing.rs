@@ -1,34 +1,380 @@
 use crate::data_ingestion;
-use crate::data_ingestion::text_processing::process_text;
 use data_ingestion::file_handler::*;
+use data_ingestion::DocumentKind;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::{Document, Index};
+use super::{Document, DocumentMetadata, Index};
+
+/// Counts of what happened to each file during an incremental reindex
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReindexSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+impl std::fmt::Display for ReindexSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed, {} unchanged",
+            self.added, self.updated, self.removed, self.unchanged
+        )
+    }
+}
+
+/// The name of the file listing what look shouldn't index, with the same syntax as `.gitignore`
+pub const LOOK_IGNORE_FILENAME: &str = ".lookignore";
+
+/// Which files of a directory are indexed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalkOptions {
+    /// Whether the files listed in `.gitignore`, the global git excludes and
+    /// `.lookignore` are skipped, along with hidden files and folders like `.git`
+    pub ignore_files: bool,
+    /// Only the files matching one of these globs are indexed, all of them if empty
+    pub include: Vec<String>,
+    /// The files and folders matching one of these globs are skipped
+    pub exclude: Vec<String>,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            ignore_files: true,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// A file to read and analyze on one of the worker threads
+struct FileJob {
+    path: PathBuf,
+    document: Document,
+    /// The hash of the contents it was indexed with, it isn't analyzed again if they are the same
+    previous_hash: Option<u64>,
+}
+
+/// What a worker found when reading a file
+enum FileOutcome {
+    /// The file changed, its postings are in the partial index of the worker
+    Analyzed,
+    /// The file was touched but its contents are the same
+    Touched(DocumentMetadata),
+}
+
+/// The partial index of a worker thread, and the outcome of each file it read
+/// along with the position of the file
+type WorkerResult = (Index, Vec<(usize, Result<FileOutcome, String>)>);
+
+/// Returns the number of threads used to read and analyze the files by default,
+/// one per CPU core
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+}
 
 pub fn process_directory<P: AsRef<Path>>(
     path: P,
     index: &mut Index,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let path = path.as_ref();
-    if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                // We will skip files that return an error when we try to process them.
-                if let Err(e) = process_file(&path, index) {
-                    println!("Error processing file: {}", e);
+    let files = collect_files(path.as_ref(), &WalkOptions::default())?;
+    let jobs: Vec<FileJob> = files
+        .into_iter()
+        .map(|path| FileJob {
+            document: Document::new(path.to_str().unwrap().to_owned()),
+            path,
+            previous_hash: None,
+        })
+        .collect();
+
+    let (partial_indexes, outcomes) = analyze_files(&jobs, index, default_jobs());
+    for outcome in outcomes {
+        // We will skip files that return an error when we try to process them.
+        if let Err(e) = outcome {
+            println!("Error processing file: {}", e);
+        }
+    }
+    for partial_index in partial_indexes {
+        index.merge(partial_index);
+    }
+
+    Ok(())
+}
+
+/// Brings the index up to date with the files in a directory
+///
+/// Only files whose modification time or size changed since they were
+/// indexed are read again, and they are only re-processed when their
+/// contents changed. Documents whose file no longer exists are removed.
+///
+/// # Arguments
+///  * `path` - The directory to index
+///  * `index` - The index to update, its IDF is recalculated and its build
+///    time set at the end
+///
+/// # Returns
+///  * A `ReindexSummary` with the number of added, updated, removed and unchanged files
+pub fn update_directory<P: AsRef<Path>>(
+    path: P,
+    index: &mut Index,
+) -> Result<ReindexSummary, Box<dyn std::error::Error>> {
+    update_directory_with_options(path, index, &WalkOptions::default(), default_jobs())
+}
+
+/// Same as `update_directory`, the files are picked following the `options`
+/// and read and analyzed on `jobs` threads
+///
+/// The documents of the files that are now ignored or excluded are removed
+/// from the index, like the deleted ones. The index is the same whatever the
+/// number of threads.
+pub fn update_directory_with_options<P: AsRef<Path>>(
+    path: P,
+    index: &mut Index,
+    options: &WalkOptions,
+    jobs: usize,
+) -> Result<ReindexSummary, Box<dyn std::error::Error>> {
+    let files = collect_files(path.as_ref(), options)?;
+
+    let mut summary = ReindexSummary::default();
+    let mut seen_documents = HashSet::new();
+    let mut changed_files = Vec::new();
+    for path in files {
+        let document = Document::new(path.to_str().unwrap().to_owned());
+        let previous_metadata = index.document_metadata.get(&document);
+        match read_file_stamp(&path) {
+            Ok((modified, size)) => match previous_metadata {
+                Some(previous_metadata)
+                    if previous_metadata.modified == modified && previous_metadata.size == size =>
+                {
+                    summary.unchanged += 1;
+                    seen_documents.insert(document);
+                }
+                _ => changed_files.push(FileJob {
+                    previous_hash: previous_metadata.map(|metadata| metadata.content_hash),
+                    path,
+                    document,
+                }),
+            },
+            // We will skip files that return an error when we try to process them,
+            // the ones already indexed keep their postings until they can be read.
+            Err(e) => {
+                println!("Error processing file: {}", e);
+                if index.document_lengths.contains_key(&document) {
+                    seen_documents.insert(document);
                 }
-            } else if path.is_dir() {
-                process_directory(&path, index)?;
             }
         }
-    } else {
+    }
+
+    // The outcomes are in the order of the files, whatever thread read them
+    let (partial_indexes, outcomes) = analyze_files(&changed_files, index, jobs);
+    let mut replaced_documents = HashSet::new();
+    for (file, outcome) in changed_files.into_iter().zip(outcomes) {
+        match outcome {
+            Ok(FileOutcome::Analyzed) if index.document_lengths.contains_key(&file.document) => {
+                replaced_documents.insert(file.document.clone());
+                summary.updated += 1
+            }
+            Ok(FileOutcome::Analyzed) => summary.added += 1,
+            Ok(FileOutcome::Touched(metadata)) => {
+                index
+                    .document_metadata
+                    .insert(file.document.clone(), metadata);
+                summary.unchanged += 1;
+            }
+            Err(e) => {
+                println!("Error processing file: {}", e);
+                if !index.document_lengths.contains_key(&file.document) {
+                    continue;
+                }
+            }
+        }
+        seen_documents.insert(file.document);
+    }
+
+    // The documents whose files were deleted, and the previous postings of
+    // the documents analyzed again, are removed in a single pass
+    let removed_documents: Vec<Document> = index
+        .document_lengths
+        .keys()
+        .filter(|document| !seen_documents.contains(*document))
+        .cloned()
+        .collect();
+    summary.removed = removed_documents.len();
+    replaced_documents.extend(removed_documents);
+    index.remove_documents_postings(&replaced_documents);
+    for partial_index in partial_indexes {
+        index.merge(partial_index);
+    }
+
+    index.calculate_idf();
+    index.built_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|elapsed| elapsed.as_secs());
+    Ok(summary)
+}
+
+/// Reads and analyzes files on a pool of threads
+///
+/// Each thread takes the next file not read yet and stores it in its own
+/// partial index, so the threads never wait on each other.
+///
+/// # Arguments
+///  * `files` - The files to read
+///  * `index` - The index the files are merged into, the partial indexes
+///    use its analyzer and edge n-gram lengths
+///  * `jobs` - The number of threads
+///
+/// # Returns
+///  * The partial indexes to merge, and the outcome of each file in the order of `files`
+fn analyze_files(
+    files: &[FileJob],
+    index: &Index,
+    jobs: usize,
+) -> (Vec<Index>, Vec<Result<FileOutcome, String>>) {
+    let next_file = AtomicUsize::new(0);
+    let workers = jobs.clamp(1, files.len().max(1));
+    let results: Vec<WorkerResult> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut partial_index = index.empty_like();
+                    let mut outcomes = Vec::new();
+                    loop {
+                        let position = next_file.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(position) else {
+                            break;
+                        };
+                        // The errors aren't `Send`, they are sent back as their message
+                        let outcome =
+                            analyze_file(file, &mut partial_index).map_err(|e| e.to_string());
+                        outcomes.push((position, outcome));
+                    }
+                    (partial_index, outcomes)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("A thread indexing the files panicked"))
+            .collect()
+    });
+
+    let mut partial_indexes = Vec::new();
+    let mut outcomes = Vec::new();
+    for (partial_index, worker_outcomes) in results {
+        partial_indexes.push(partial_index);
+        outcomes.extend(worker_outcomes);
+    }
+    outcomes.sort_by_key(|(position, _)| *position);
+    let outcomes = outcomes.into_iter().map(|(_, outcome)| outcome).collect();
+    (partial_indexes, outcomes)
+}
+
+/// Reads a file and stores it in a partial index, unless its contents are
+/// the ones it was indexed with
+fn analyze_file(
+    file: &FileJob,
+    partial_index: &mut Index,
+) -> Result<FileOutcome, Box<dyn std::error::Error>> {
+    let (modified, size) = read_file_stamp(&file.path)?;
+    let (content, kind) = read_document(&file.path)?;
+    let metadata = DocumentMetadata::new(modified, size, &content);
+    if file.previous_hash == Some(metadata.content_hash) {
+        return Ok(FileOutcome::Touched(metadata));
+    }
+
+    partial_index.store_document(&file.document, &content, kind);
+    partial_index
+        .document_metadata
+        .insert(file.document.clone(), metadata);
+    Ok(FileOutcome::Analyzed)
+}
+
+/// Recursively collects the paths of the files in a directory
+///
+/// # Arguments
+///  * `path` - The directory to walk
+///  * `options` - Whether the ignore files are honoured, and the globs of
+///    the files to include or exclude, relative to `path`
+///
+/// # Returns
+///  * The paths of the files to index, sorted by name
+fn collect_files(
+    path: &Path,
+    options: &WalkOptions,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    if !path.is_dir() {
         return Err(From::from("Input path must be a directory."));
     }
+    let include = build_glob_set(&options.include)?;
+    let exclude = build_glob_set(&options.exclude)?;
 
-    Ok(())
+    let root = path.to_path_buf();
+    let mut walker = WalkBuilder::new(path);
+    walker
+        .standard_filters(options.ignore_files)
+        // Notes folders usually aren't git repositories, their .gitignore still applies
+        .require_git(false)
+        .ignore(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| !matches_glob(&exclude, &root, entry.path()));
+    if options.ignore_files {
+        walker.add_custom_ignore_filename(LOOK_IGNORE_FILENAME);
+    }
+
+    let mut files = Vec::new();
+    for entry in walker.build() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // We will skip the folders and ignore files that can't be read.
+            Err(e) => {
+                println!("Error walking the directory: {}", e);
+                continue;
+            }
+        };
+        let is_file = entry
+            .file_type()
+            .is_some_and(|file_type| file_type.is_file());
+        if is_file && (options.include.is_empty() || matches_glob(&include, path, entry.path())) {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
+
+fn build_glob_set(globs: &[String]) -> Result<GlobSet, Box<dyn std::error::Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = Glob::new(glob).map_err(|e| format!("Invalid glob \"{}\": {}", glob, e))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Whether a path matches one of the globs, relative to the walked
+/// directory or by its name alone, so `drafts` skips every `drafts` folder
+fn matches_glob(globs: &GlobSet, root: &Path, path: &Path) -> bool {
+    let relative_path = path.strip_prefix(root).unwrap_or(path);
+    globs.is_match(relative_path) || path.file_name().is_some_and(|name| globs.is_match(name))
+}
+
+/// Returns the modification time (in nanoseconds since the UNIX epoch) and size of a file
+fn read_file_stamp(path: &Path) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    Ok((modified, metadata.len()))
 }
 
 pub fn get_file_extension<P: AsRef<Path>>(path: P) -> Result<String, Box<dyn std::error::Error>> {
@@ -46,25 +392,42 @@ pub fn get_file_extension<P: AsRef<Path>>(path: P) -> Result<String, Box<dyn std
 }
 
 pub fn read_file_contents<P: AsRef<Path>>(path: P) -> Result<String, Box<dyn std::error::Error>> {
+    let (content, _) = read_document(path)?;
+    Ok(content)
+}
+
+/// Reads a file with the handler of its extension
+///
+/// # Returns
+///  * The contents of the file and how they should be analyzed
+pub fn read_document<P: AsRef<Path>>(
+    path: P,
+) -> Result<(String, DocumentKind), Box<dyn std::error::Error>> {
     let path = path.as_ref();
-    let file_extension = get_file_extension(&path)?;
+    let file_extension = get_file_extension(path)?;
+    let file_handler = get_file_handler(&file_extension)?;
+    let content = file_handler.read_contents(path.to_str().unwrap())?;
+    Ok((content, file_handler.document_kind()))
+}
 
-    match file_extension.as_str() {
-        "md" => {
-            let file_handler = data_ingestion::MarkdownHandler;
-            let content = file_handler.read_contents(path.to_str().unwrap())?;
-            Ok(content)
-        }
-        "txt" => {
-            let file_handler = data_ingestion::PlainTextHandler;
-            let content = file_handler.read_contents(path.to_str().unwrap())?;
-            Ok(content)
-        }
-        _ => Err(From::from(format!(
-            "File extension {} is not supported.",
-            file_extension
-        ))),
-    }
+/// Returns the handler of the files with this extension
+fn get_file_handler(
+    file_extension: &str,
+) -> Result<Box<dyn FileHandler>, Box<dyn std::error::Error>> {
+    let file_handlers: Vec<Box<dyn FileHandler>> = vec![
+        Box::new(data_ingestion::MarkdownHandler),
+        Box::new(data_ingestion::PlainTextHandler),
+        Box::new(data_ingestion::SourceCodeHandler),
+    ];
+    file_handlers
+        .into_iter()
+        .find(|file_handler| file_handler.can_handle(file_extension))
+        .ok_or_else(|| {
+            From::from(format!(
+                "File extension {} is not supported.",
+                file_extension
+            ))
+        })
 }
 
 pub fn process_file<P: AsRef<Path>>(
@@ -72,9 +435,14 @@ pub fn process_file<P: AsRef<Path>>(
     index: &mut Index,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let path = path.as_ref();
-    let content = read_file_contents(&path)?;
+    let (modified, size) = read_file_stamp(path)?;
+    let (content, kind) = read_document(path)?;
     let document = Document::new(path.to_str().unwrap().to_owned());
-    Ok(index.store_processed_text_in_index(&document, &content))
+    index.store_document(&document, &content, kind);
+    index
+        .document_metadata
+        .insert(document, DocumentMetadata::new(modified, size, &content));
+    Ok(())
 }
 
 #[cfg(test)]
@@ -97,4 +465,304 @@ mod tests {
         print!("{:?}", index.inverted_index);
         assert_eq!(index.inverted_index.len(), 3);
     }
+
+    #[test]
+    fn test_update_directory() {
+        use super::ReindexSummary;
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-update-directory");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        fs::create_dir_all(dir_path.join("nested")).unwrap();
+        fs::write(dir_path.join("kubernetes.txt"), "kubernetes cluster").unwrap();
+        fs::write(dir_path.join("nested/database.md"), "database migration").unwrap();
+        fs::write(dir_path.join("deleted.txt"), "rollback plan").unwrap();
+
+        let mut index = super::Index::new();
+        let summary = super::update_directory(&dir_path, &mut index).unwrap();
+        assert_eq!(summary.added, 3);
+        assert_eq!(index.num_docs, 3);
+
+        fs::write(
+            dir_path.join("kubernetes.txt"),
+            "kubernetes deployment rollout",
+        )
+        .unwrap();
+        fs::remove_file(dir_path.join("deleted.txt")).unwrap();
+        fs::write(dir_path.join("new.txt"), "incident review").unwrap();
+
+        let summary = super::update_directory(&dir_path, &mut index).unwrap();
+        assert_eq!(
+            summary,
+            ReindexSummary {
+                added: 1,
+                updated: 1,
+                removed: 1,
+                unchanged: 1,
+            }
+        );
+        assert_eq!(index.num_docs, 3);
+        assert_eq!(index.document_lengths.len(), 3);
+        for term in ["cluster", "rollback", "plan"] {
+            let term = super::super::Term(term.to_owned());
+            assert!(!index.inverted_index.contains_key(&term));
+            assert!(!index.idf.contains_key(&term));
+        }
+        assert!(index
+            .inverted_index
+            .contains_key(&super::super::Term("rollout".to_owned())));
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_files_keep_their_postings() {
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-unreadable-files");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        fs::create_dir_all(&dir_path).unwrap();
+        fs::write(dir_path.join("kubernetes.txt"), "kubernetes cluster").unwrap();
+
+        let mut index = super::Index::new();
+        super::update_directory(&dir_path, &mut index).unwrap();
+        assert_eq!(index.num_docs, 1);
+
+        // The file isn't valid UTF-8 anymore, it fails to be read
+        fs::write(dir_path.join("kubernetes.txt"), [0xff, 0xfe, 0xfd]).unwrap();
+        let summary = super::update_directory(&dir_path, &mut index).unwrap();
+        assert_eq!(summary.removed, 0);
+        assert_eq!(index.num_docs, 1);
+        assert!(index
+            .inverted_index
+            .contains_key(&super::super::Term("cluster".to_owned())));
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_deleted_words_lose_their_prefixes() {
+        use crate::search_query::search;
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-deleted-words");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        fs::create_dir_all(&dir_path).unwrap();
+        fs::write(dir_path.join("deployments.txt"), "kubernetes deployments").unwrap();
+        fs::write(dir_path.join("deploy.txt"), "deploy the cluster").unwrap();
+
+        let mut index = super::Index::new();
+        index.enable_edge_ngrams(2, 15);
+        super::update_directory(&dir_path, &mut index).unwrap();
+        // "deploym" leads to the term "deploy" of both documents
+        assert_eq!(search("deploym*", &index).unwrap().len(), 2);
+
+        fs::remove_file(dir_path.join("deployments.txt")).unwrap();
+        super::update_directory(&dir_path, &mut index).unwrap();
+        assert!(search("deploym*", &index).unwrap().is_empty());
+        assert_eq!(search("deplo*", &index).unwrap().len(), 1);
+
+        // The words are kept in the index file
+        let bytes = crate::index_builder::binary_format::encode_index(&index);
+        let decoded = crate::index_builder::binary_format::decode_index(&bytes).unwrap();
+        assert_eq!(decoded.edge_ngrams, index.edge_ngrams);
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_indexing_is_deterministic() {
+        use super::WalkOptions;
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-parallel-indexing");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        fs::create_dir_all(dir_path.join("nested")).unwrap();
+        let texts = [
+            "kubernetes cluster upgrade",
+            "database migration rollback",
+            "La migración de la base de datos",
+            "deploy the kubernetes cluster again",
+            "incident review of the database outage",
+        ];
+        for (i, text) in texts.iter().cycle().take(40).enumerate() {
+            let folder = if i % 3 == 0 { "nested" } else { "" };
+            fs::write(dir_path.join(folder).join(format!("{}.txt", i)), text).unwrap();
+        }
+        fs::write(dir_path.join("unsupported.pdf"), "kubernetes").unwrap();
+
+        let build_index = |jobs: usize| {
+            let mut index = super::Index::new();
+            let summary = super::update_directory_with_options(
+                &dir_path,
+                &mut index,
+                &WalkOptions::default(),
+                jobs,
+            )
+            .unwrap();
+            assert_eq!(summary.added, 40);
+            index
+        };
+        let expected = build_index(1);
+        for jobs in [2, 3, 8, 64] {
+            let index = build_index(jobs);
+            assert_eq!(
+                index.inverted_index, expected.inverted_index,
+                "{} jobs",
+                jobs
+            );
+            assert_eq!(index.idf, expected.idf);
+            assert_eq!(index.document_norms, expected.document_norms);
+            assert_eq!(index.document_lengths, expected.document_lengths);
+            assert_eq!(index.document_languages, expected.document_languages);
+            assert_eq!(index.document_metadata, expected.document_metadata);
+            assert_eq!(index.num_docs, 40);
+        }
+
+        // Updating in parallel gives the same index as building it again
+        let mut index = build_index(4);
+        fs::write(dir_path.join("1.txt"), "rollout of the new cluster").unwrap();
+        fs::remove_file(dir_path.join("2.txt")).unwrap();
+        let summary =
+            super::update_directory_with_options(&dir_path, &mut index, &WalkOptions::default(), 4)
+                .unwrap();
+        assert_eq!((summary.updated, summary.removed), (1, 1));
+        let mut rebuilt = super::Index::new();
+        super::update_directory_with_options(&dir_path, &mut rebuilt, &WalkOptions::default(), 1)
+            .unwrap();
+        assert_eq!(index.inverted_index, rebuilt.inverted_index);
+        assert_eq!(index.idf, rebuilt.idf);
+        assert_eq!(index.num_docs, rebuilt.num_docs);
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_ignored_files_are_skipped() {
+        use super::WalkOptions;
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-ignored-files");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        for folder in [".git", "node_modules/lib", "notes/drafts", "target"] {
+            fs::create_dir_all(dir_path.join(folder)).unwrap();
+        }
+        fs::write(dir_path.join(".gitignore"), "node_modules/\n*.log\n").unwrap();
+        fs::write(dir_path.join(".lookignore"), "target\n").unwrap();
+        for file in [
+            ".git/HEAD.txt",
+            "node_modules/lib/readme.md",
+            "target/output.txt",
+            "server.log",
+            "notes/kubernetes.md",
+            "notes/database.txt",
+            "notes/drafts/rollback.md",
+        ] {
+            fs::write(dir_path.join(file), "kubernetes").unwrap();
+        }
+
+        let files = |options: &WalkOptions| -> Vec<String> {
+            super::collect_files(&dir_path, options)
+                .unwrap()
+                .iter()
+                .map(|path| {
+                    let path = path.strip_prefix(&dir_path).unwrap();
+                    path.to_str().unwrap().replace('\\', "/")
+                })
+                .collect()
+        };
+        assert_eq!(
+            files(&WalkOptions::default()),
+            vec![
+                "notes/database.txt",
+                "notes/drafts/rollback.md",
+                "notes/kubernetes.md"
+            ]
+        );
+        let options = WalkOptions {
+            include: vec!["*.md".to_owned()],
+            exclude: vec!["drafts".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(files(&options), vec!["notes/kubernetes.md"]);
+        let options = WalkOptions {
+            ignore_files: false,
+            ..Default::default()
+        };
+        assert_eq!(files(&options).len(), 9);
+
+        let options = WalkOptions {
+            include: vec!["notes/[".to_owned()],
+            ..Default::default()
+        };
+        let error = super::collect_files(&dir_path, &options).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid glob \"notes/[\""));
+
+        // The files that became ignored are removed from the index
+        let mut index = super::Index::new();
+        let options = WalkOptions {
+            ignore_files: false,
+            ..Default::default()
+        };
+        let added = super::update_directory_with_options(&dir_path, &mut index, &options, 2)
+            .unwrap()
+            .added;
+        assert!(added > 3);
+        let summary = super::update_directory(&dir_path, &mut index).unwrap();
+        assert_eq!(summary.removed, added - 3);
+        assert_eq!(index.document_lengths.len(), 3);
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_code_is_split_into_identifiers() {
+        use crate::search_query::search;
+        use std::fs;
+
+        let dir_path = std::env::temp_dir().join("look-test-code-identifiers");
+        if dir_path.exists() {
+            fs::remove_dir_all(&dir_path).unwrap();
+        }
+        fs::create_dir_all(&dir_path).unwrap();
+        let notes = dir_path.join("notes.md");
+        let source = dir_path.join("main.rs");
+        fs::write(
+            &notes,
+            "How to list a folder:\n```rust\nstd::fs::read_dir(path)\n```\n",
+        )
+        .unwrap();
+        fs::write(&source, "let text = readFileContents(path);").unwrap();
+
+        let mut index = super::Index::new();
+        super::update_directory(&dir_path, &mut index).unwrap();
+
+        let documents = |query: &str| -> Vec<String> {
+            let mut paths: Vec<String> = search(query, &index)
+                .unwrap()
+                .into_iter()
+                .map(|result| result.document.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+        let notes = notes.to_str().unwrap().to_owned();
+        let source = source.to_str().unwrap().to_owned();
+        assert_eq!(documents("std::fs::read_dir"), vec![notes.clone()]);
+        assert_eq!(documents("\"read dir\""), vec![notes]);
+        assert_eq!(documents("readfilecontents"), vec![source.clone()]);
+        assert_eq!(documents("\"read file contents\""), vec![source]);
+
+        fs::remove_dir_all(&dir_path).unwrap();
+    }
 }
@@ -0,0 +1,151 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Function to replace a file without ever leaving it half written
+///
+/// The contents are written to a temporary file next to it, flushed to the
+/// disk and renamed over the file. A reader opening the file gets either the
+/// old contents or the new ones, even if the writer is interrupted, and a
+/// reader that opened it before keeps reading the old contents.
+///
+/// # Arguments
+///  * `path` - The file to replace, created if it doesn't exist
+///  * `contents` - The new contents of the file
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary_path = temporary_path(path);
+    let written =
+        write_and_sync(&temporary_path, contents).and_then(|_| fs::rename(&temporary_path, path));
+    if let Err(error) = written {
+        // The temporary file may not exist, the first error is the one reported
+        let _ = fs::remove_file(&temporary_path);
+        return Err(error);
+    }
+    sync_directory(path)
+}
+
+fn write_and_sync(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    // The contents must reach the disk before the rename does, or a crash
+    // could leave an empty file in place of the old one
+    file.sync_all()
+}
+
+/// The temporary file is in the same directory as the file it replaces, a
+/// rename to another file system isn't atomic
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(file_name)
+}
+
+/// Flushes the rename to the disk, it is stored in the directory
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> io::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(directory)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// An advisory lock on an index file, held while the index is updated
+///
+/// The lock is taken on a `.lock` file next to the index file, as the index
+/// file itself is replaced when saved. It is released when dropped or when
+/// the process exits. Searches don't take it, they read the last complete
+/// index saved.
+pub struct IndexLock {
+    _file: File,
+}
+
+impl IndexLock {
+    /// Function to lock an index file, waiting until no other process holds the lock
+    ///
+    /// # Arguments
+    ///  * `index_path` - The path to the index file
+    pub fn acquire(index_path: &Path) -> io::Result<IndexLock> {
+        let file = open_lock_file(index_path)?;
+        file.lock()?;
+        Ok(IndexLock { _file: file })
+    }
+
+    /// Function to lock an index file if no other process holds the lock
+    ///
+    /// # Arguments
+    ///  * `index_path` - The path to the index file
+    ///
+    /// # Returns
+    ///  * The lock, `None` if another process holds it
+    pub fn try_acquire(index_path: &Path) -> io::Result<Option<IndexLock>> {
+        let file = open_lock_file(index_path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(IndexLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(error)) => Err(error),
+        }
+    }
+}
+
+/// Opens the lock file of an index, it is never removed so that every
+/// process locks the same file
+fn open_lock_file(index_path: &Path) -> io::Result<File> {
+    let mut file_name = index_path.file_name().unwrap_or_default().to_owned();
+    file_name.push(".lock");
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(index_path.with_file_name(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomically() {
+        let directory = std::env::temp_dir().join("look_test_write_atomically");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("index.bin");
+
+        write_atomically(&path, b"first").unwrap();
+        let mut opened = File::open(&path).unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // A reader keeps the file it opened
+        let mut contents = String::new();
+        io::Read::read_to_string(&mut opened, &mut contents).unwrap();
+        assert_eq!(contents, "first");
+
+        // Nothing is left behind when the file can't be written
+        let missing = directory.join("missing").join("index.bin");
+        assert!(write_atomically(&missing, b"third").is_err());
+        let files: Vec<_> = fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_index_lock() {
+        let directory = std::env::temp_dir().join("look_test_index_lock");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("index.bin");
+
+        let lock = IndexLock::try_acquire(&path).unwrap();
+        assert!(lock.is_some());
+        assert!(directory.join("index.bin.lock").exists());
+        assert!(IndexLock::try_acquire(&path).unwrap().is_none());
+        drop(lock);
+        assert!(IndexLock::try_acquire(&path).unwrap().is_some());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+use memmap2::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::ops::{Bound, Range};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::data_ingestion::{Analyzer, AnalyzerHeader};
+
+use super::binary_format::{
+    self, decode_analyzer, decode_term_entry, decode_term_ids, read_edge_ngrams, AnalyzerSection,
+    Decoder, DocumentTable, Section, SortedTable, TermEntry, BLOCK_LENGTH,
+};
+use super::{Document, EdgeNgrams, Postings, SearchIndex};
+
+/// The postings of the terms of a block of the term dictionary, decoded when first read
+type PostingsBlock = Box<[OnceLock<Postings>]>;
+
+/// A read-only index that memory-maps the index file
+///
+/// Opening it only reads the header of the file, the postings of a term are
+/// decoded the first time a query needs them. A search reads the few terms
+/// of the query instead of the whole index, so it starts as fast on a large
+/// index as on a small one. Use `Index` to build or update an index.
+pub struct IndexReader {
+    /// The analyzer the queries are analyzed with, the default one until set
+    pub analyzer: Analyzer,
+    mmap: Mmap,
+    /// The format version of the index file
+    version: u32,
+    sections: Vec<Range<usize>>,
+    header: AnalyzerSection,
+    /// The ids of the terms looked up so far, `None` for the terms not in the index
+    term_ids: Mutex<HashMap<String, Option<usize>>>,
+    /// The decoded postings of the terms, by block of the term dictionary
+    postings: Box<[OnceLock<PostingsBlock>]>,
+}
+
+impl IndexReader {
+    /// Function to open an index file without reading it
+    ///
+    /// # Arguments
+    ///  * `path` - The path to the index file, in the binary format
+    ///
+    /// # Returns
+    ///  * The reader, with the default analyzer, or an `InvalidData` error if
+    ///    the file isn't an index in the binary format
+    pub fn open(path: &Path) -> std::io::Result<IndexReader> {
+        let file = File::open(path)?;
+        // SAFETY: the index file is never written in place, it is replaced by
+        // renaming a new file over it, so the mapped file doesn't change
+        let mmap = unsafe { Mmap::map(&file)? };
+        let file_header = binary_format::read_header(&mmap)?;
+        let version = file_header.version;
+        let sections = file_header.sections;
+        let section = |section: Section| &mmap[sections[section as usize].clone()];
+
+        let header = decode_analyzer(&mut Decoder::new(section(Section::Analyzer)), version)?;
+        // The tables are checked once, their entries are read when needed
+        let terms = SortedTable::new(section(Section::Terms))?;
+        DocumentTable::new(section(Section::Documents))?;
+        read_edge_ngrams(section(Section::EdgeNgrams), version)?;
+
+        let postings = (0..terms.block_count()).map(|_| OnceLock::new()).collect();
+        Ok(IndexReader {
+            analyzer: Analyzer::new(),
+            mmap,
+            version,
+            sections,
+            header,
+            term_ids: Mutex::new(HashMap::new()),
+            postings,
+        })
+    }
+
+    fn section(&self, section: Section) -> &[u8] {
+        &self.mmap[self.sections[section as usize].clone()]
+    }
+
+    fn terms(&self) -> SortedTable<'_> {
+        SortedTable::new(self.section(Section::Terms)).expect("The terms are checked on open")
+    }
+
+    fn document_table(&self) -> DocumentTable<'_> {
+        DocumentTable::new(self.section(Section::Documents))
+            .expect("The documents are checked on open")
+    }
+
+    /// Returns the id of a term in the term dictionary, `None` if it isn't in the index
+    fn term_id(&self, term: &str) -> Option<usize> {
+        let mut term_ids = self.term_ids.lock().unwrap();
+        if let Some(id) = term_ids.get(term) {
+            return *id;
+        }
+        let id = self.terms().get(term).ok().flatten().map(|(id, _)| id);
+        term_ids.insert(term.to_owned(), id);
+        id
+    }
+
+    /// Returns the entry of a term in the term dictionary
+    fn term_entry(&self, term: &str) -> Option<TermEntry> {
+        let (term, value) = self.terms().entry(self.term_id(term)?).ok()?;
+        decode_term_entry(term, value).ok()
+    }
+
+    /// Decodes the postings of a term, an entry that can't be decoded is
+    /// read as a term without documents
+    fn decode_postings(&self, id: usize) -> Postings {
+        let documents = self.document_table();
+        self.terms()
+            .entry(id)
+            .and_then(|(term, value)| decode_term_entry(term, value))
+            .and_then(|entry| {
+                binary_format::decode_postings(self.section(Section::Postings), &entry, |id| {
+                    Ok(Document::new(documents.path(id)?.to_owned()))
+                })
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl SearchIndex for IndexReader {
+    fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    fn built_with(&self) -> Option<&AnalyzerHeader> {
+        self.header.built_with.as_ref()
+    }
+
+    fn num_docs(&self) -> usize {
+        self.header.num_docs
+    }
+
+    /// Reads the whole document table, only queries with a `NOT` need it
+    fn documents(&self) -> HashSet<Document> {
+        let documents = self.document_table();
+        (0..documents.len())
+            .filter_map(|id| documents.path(id).ok())
+            .map(|path| Document::new(path.to_owned()))
+            .collect()
+    }
+
+    fn document_length(&self, document: &Document) -> Option<u32> {
+        self.document_table().find(document).ok()??.length
+    }
+
+    fn document_norm(&self, document: &Document) -> Option<f64> {
+        self.document_table().find(document).ok()??.norm
+    }
+
+    fn average_document_length(&self) -> f64 {
+        self.header.average_document_length()
+    }
+
+    fn postings(&self, term: &str) -> Option<&Postings> {
+        let id = self.term_id(term)?;
+        let block = self.postings[id / BLOCK_LENGTH]
+            .get_or_init(|| (0..BLOCK_LENGTH).map(|_| OnceLock::new()).collect());
+        Some(block[id % BLOCK_LENGTH].get_or_init(|| self.decode_postings(id)))
+    }
+
+    fn idf(&self, term: &str) -> Option<f64> {
+        self.term_entry(term)?.idf
+    }
+
+    /// Read from the term dictionary, without decoding the postings
+    fn document_frequency(&self, term: &str) -> Option<usize> {
+        Some(self.term_entry(term)?.document_frequency)
+    }
+
+    fn terms_from(
+        &self,
+        lower_bound: Bound<&str>,
+    ) -> Box<dyn Iterator<Item = (String, usize)> + '_> {
+        let terms = self.terms();
+        let Ok(Some((id, _, _))) = terms.seek(lower_bound) else {
+            return Box::new(std::iter::empty());
+        };
+        Box::new(
+            terms
+                .iter_from(id)
+                .map_while(Result::ok)
+                .map_while(|(term, value)| decode_term_entry(term, value).ok())
+                .map(|entry| (entry.term, entry.document_frequency)),
+        )
+    }
+
+    /// The edge n-grams before version 4 are the prefixes of the stemmed
+    /// terms, the prefixes are scanned until the index is rebuilt
+    fn edge_ngram_terms(&self, prefix: &str) -> Option<Vec<String>> {
+        if self.version < 4 {
+            return None;
+        }
+        let section = read_edge_ngrams(self.section(Section::EdgeNgrams), self.version).ok()??;
+        if !EdgeNgrams::new(section.min_length, section.max_length).covers(prefix) {
+            return None;
+        }
+        let ids = match section.prefixes.get(prefix).ok()? {
+            Some((_, value)) => decode_term_ids(value).ok()?,
+            None => Vec::new(),
+        };
+        let terms = self.terms();
+        Some(
+            ids.into_iter()
+                .filter_map(|id| terms.entry(id).ok().map(|(term, _)| term))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search_query::{search_with_options, Bm25Scorer, SearchOptions, TfIdfScorer};
+    use crate::Index;
+
+    fn build_index() -> Index {
+        let mut index = Index::new();
+        index.enable_edge_ngrams(2, 15);
+        for (path, text) in [
+            ("notes.txt", "Kubernetes clusters, kubernetes upgrade"),
+            ("deploy.md", "Rolling deployments of the kubernetes cluster"),
+            ("database.txt", "The database migration and the blue car"),
+            ("other.txt", "Upgrading the database of the clusters"),
+        ] {
+            index.store_processed_text_in_index(&Document::new(path.to_owned()), text);
+        }
+        index.calculate_idf();
+        index
+    }
+
+    #[test]
+    fn test_index_reader_searches_like_the_index() {
+        let index = build_index();
+        let index_path = std::env::temp_dir().join("look_test_index_reader.bin");
+        index.save_index_to_file(&index_path).unwrap();
+        let reader = IndexReader::open(&index_path).unwrap();
+
+        assert_eq!(reader.num_docs(), index.num_docs);
+        assert_eq!(reader.documents(), index.documents());
+        assert_eq!(
+            reader.average_document_length(),
+            index.average_document_length()
+        );
+        assert!(reader.check_analyzer().is_ok());
+        assert_eq!(reader.postings("kubernet"), index.postings("kubernet"));
+        assert_eq!(reader.postings("missing"), None);
+        assert_eq!(
+            reader.expand_wildcard("d*", 10),
+            index.expand_wildcard("d*", 10)
+        );
+        assert_eq!(
+            reader.expand_wildcard("cl*r?", 10),
+            index.expand_wildcard("cl*r?", 10)
+        );
+        assert_eq!(
+            reader.expand_fuzzy("clustr", 2, 10),
+            index.expand_fuzzy("clustr", 2, 10)
+        );
+
+        let options = SearchOptions {
+            fuzzy: true,
+            ..Default::default()
+        };
+        for query in [
+            "kubernetes",
+            "database NOT car",
+            "\"blue car\"",
+            "upgrad* OR deploy",
+            "kubernets cluster",
+        ] {
+            for scorer in [
+                &Bm25Scorer::default() as &dyn crate::search_query::Scorer,
+                &TfIdfScorer,
+            ] {
+                let mut expected = search_with_options(query, &index, scorer, &options).unwrap();
+                let mut results = search_with_options(query, &reader, scorer, &options).unwrap();
+                expected.sort_by(|a, b| a.document.cmp(&b.document));
+                results.sort_by(|a, b| a.document.cmp(&b.document));
+                assert_eq!(results.len(), expected.len(), "{}", query);
+                for (result, expected) in results.iter().zip(&expected) {
+                    assert_eq!(result.document, expected.document, "{}", query);
+                    assert!((result.score - expected.score).abs() < 1e-9, "{}", query);
+                }
+            }
+        }
+        drop(reader);
+        std::fs::remove_file(&index_path).unwrap();
+    }
+
+    #[test]
+    fn test_index_reader_decodes_only_the_query_terms() {
+        let index = build_index();
+        let index_path = std::env::temp_dir().join("look_test_index_reader_lazy.bin");
+        index.save_index_to_file(&index_path).unwrap();
+        let reader = IndexReader::open(&index_path).unwrap();
+        let decoded = |reader: &IndexReader| {
+            reader
+                .postings
+                .iter()
+                .filter_map(|block| block.get())
+                .flat_map(|block| block.iter())
+                .filter(|postings| postings.get().is_some())
+                .count()
+        };
+        assert_eq!(decoded(&reader), 0);
+
+        search_with_options(
+            "database",
+            &reader,
+            &Bm25Scorer::default(),
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(decoded(&reader), 1);
+        drop(reader);
+        std::fs::remove_file(&index_path).unwrap();
+
+        // The JSON exports aren't memory-mapped
+        let json_path = std::env::temp_dir().join("look_test_index_reader.json");
+        index.save_index_to_json_file(&json_path).unwrap();
+        let error = IndexReader::open(&json_path).err().unwrap();
+        std::fs::remove_file(&json_path).unwrap();
+        assert_eq!(error.to_string(), "Not a look index file");
+    }
+}
@@ -1,45 +1,166 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
+use std::ops::Bound;
 use std::path::Path;
 
-use crate::data_ingestion::text_processing::process_text;
+use crate::data_ingestion::{Analyzer, AnalyzerHeader, DocumentKind};
 
 use super::{
-    json_serialization::{deserialize_inverted_index, deserialize_vec_to_hashmap},
-    Document, Term,
+    binary_format,
+    edge_ngrams::EdgeNgrams,
+    index_file::write_atomically,
+    json_serialization::{
+        deserialize_inverted_index, deserialize_vec_to_hashmap, SerializedPostings,
+    },
+    search_index::SearchIndex,
+    Document, DocumentMetadata, Postings, Term,
 };
 
+/// The version of the JSON export, the exports without a version are version 1
+///
+///  * 2: the version and the time the index was built
+///  * 3: the edge n-grams are the prefixes of the words before stemming
+///  * 4: the words of each document the edge n-grams were made from
+pub const JSON_FORMAT_VERSION: u32 = 4;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Index {
-    pub inverted_index: HashMap<Term, HashMap<Document, u32>>,
+    /// For each term, the positions where it appears in each document
+    ///
+    /// Terms are kept sorted so that the terms sharing a prefix are next to
+    /// each other, which is what wildcard queries rely on.
+    pub inverted_index: BTreeMap<Term, Postings>,
     pub idf: HashMap<Term, f64>,
     pub document_norms: HashMap<Document, f64>,
+    pub document_lengths: HashMap<Document, u32>,
+    pub document_metadata: HashMap<Document, DocumentMetadata>,
+    /// The ISO 639-3 code of the language each document was analyzed in
+    pub document_languages: HashMap<Document, String>,
     pub num_docs: usize,
+    /// The analyzer the documents were indexed with, `None` for the indexes
+    /// saved before it was recorded
+    pub built_with: Option<AnalyzerHeader>,
+    /// When the documents were last indexed, in seconds since the UNIX
+    /// epoch, `None` for the indexes saved before it was recorded
+    pub built_at: Option<u64>,
+    /// The prefixes of the terms, only kept when enabled with `enable_edge_ngrams`
+    pub edge_ngrams: Option<EdgeNgrams>,
+    #[serde(skip)]
+    pub analyzer: Analyzer,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index::new()
+    }
 }
 
 impl Index {
     pub fn new() -> Index {
+        Index::with_analyzer(Analyzer::new())
+    }
+
+    /// Creates an empty index that analyzes documents and queries with `analyzer`
+    pub fn with_analyzer(analyzer: Analyzer) -> Index {
         Index {
-            inverted_index: HashMap::new(),
+            inverted_index: BTreeMap::new(),
             idf: HashMap::new(),
             document_norms: HashMap::new(),
+            document_lengths: HashMap::new(),
+            document_metadata: HashMap::new(),
+            document_languages: HashMap::new(),
             num_docs: 0,
+            built_with: Some(analyzer.header()),
+            built_at: None,
+            edge_ngrams: None,
+            analyzer,
         }
     }
 
+    /// Adds the document's text to the index
+    ///
+    /// If the document was already indexed its previous postings are replaced,
+    /// so storing the same path twice doesn't count it twice. The IDF is not
+    /// updated, call `calculate_idf` once all the documents are stored.
     pub fn store_processed_text_in_index(&mut self, document: &Document, text: &str) {
-        let processed_txt = process_text(&text);
-        let tokens = processed_txt.split_whitespace().collect::<Vec<&str>>();
+        self.store_document(document, text, DocumentKind::Text);
+    }
+
+    /// Same as `store_processed_text_in_index`, the text is analyzed following
+    /// the kind of document, e.g. the code blocks of markdown are split into identifiers
+    pub fn store_document(&mut self, document: &Document, text: &str, kind: DocumentKind) {
+        self.remove_document_postings(document);
+
+        let language = self.analyzer.detect_language(text);
+        let tokens = self.analyzer.analyze_document_words(text, kind, language);
         self.num_docs += 1;
 
-        for token in tokens {
-            self.insert_token(token, document);
+        for (token, position, word) in &tokens {
+            self.insert_token(token, word, *position, document);
+        }
+
+        self.document_lengths
+            .insert(document.clone(), tokens.len() as u32);
+        self.document_languages
+            .insert(document.clone(), language.code().to_owned());
+    }
+
+    /// Empties the index if its analyzer isn't the one its documents were indexed with
+    ///
+    /// # Returns
+    ///  * `true` if the index was emptied, every document has to be indexed again
+    pub fn reset_if_analyzer_changed(&mut self) -> bool {
+        if self.check_analyzer().is_ok() {
+            return false;
+        }
+        *self = Index::with_analyzer(self.analyzer.clone());
+        true
+    }
+
+    /// Stores the edge n-grams of the words of the documents indexed from
+    /// now on, so the terms of the words starting with a prefix are found
+    /// without scanning the index
+    ///
+    /// # Arguments
+    ///  * `min_length` - The length of the shortest prefix stored
+    ///  * `max_length` - The length of the longest prefix stored, the longer
+    ///    prefixes are still found by scanning the index
+    ///
+    /// # Returns
+    ///  * `true` if the index was emptied: the words are only known while a
+    ///    document is analyzed, so every document has to be indexed again when
+    ///    the edge n-grams weren't stored with these lengths
+    pub fn enable_edge_ngrams(&mut self, min_length: usize, max_length: usize) -> bool {
+        if let Some(edge_ngrams) = &self.edge_ngrams {
+            if edge_ngrams.min_length == min_length && edge_ngrams.max_length == max_length {
+                return false;
+            }
+        }
+        let reset = !self.document_lengths.is_empty();
+        if reset {
+            *self = Index::with_analyzer(self.analyzer.clone());
         }
+        self.edge_ngrams = Some(EdgeNgrams::new(min_length, max_length));
+        reset
+    }
 
-        self.update_document_norm(document);
+    /// Drops the edge n-grams, the prefixes are found by scanning the index again
+    pub fn disable_edge_ngrams(&mut self) {
+        self.edge_ngrams = None;
+    }
+
+    /// Returns an empty index with the same analyzer and edge n-gram lengths,
+    /// to index documents apart and `merge` them into this one
+    pub fn empty_like(&self) -> Index {
+        let mut index = Index::with_analyzer(self.analyzer.clone());
+        index.edge_ngrams = self
+            .edge_ngrams
+            .as_ref()
+            .map(|edge_ngrams| EdgeNgrams::new(edge_ngrams.min_length, edge_ngrams.max_length));
+        index
     }
 
     /// Function to calculate the IDF for each term in the index
@@ -50,13 +171,45 @@ impl Index {
     /// # Returns
     /// * `()` - The function returns nothing
     ///
-    /// An example of what IDF does is shown below:
+    /// The IDF of a term is `ln(num_docs / df) + 1`, where `df` is the number
+    /// of documents containing the term. Rare terms get a higher IDF, and a
+    /// term found in every document still gets a weight of 1.
     ///
+    /// The document norms depend on the IDF, so they are recalculated too.
     pub fn calculate_idf(&mut self) {
+        self.idf.clear();
         for (term, docs) in &self.inverted_index {
-            let idf = self.num_docs as f64 / docs.len() as f64;
+            let idf = (self.num_docs as f64 / docs.len() as f64).ln() + 1.0;
             self.idf.insert(term.clone(), idf);
         }
+
+        self.calculate_document_norms();
+    }
+
+    /// Function to load the index from a file
+    ///
+    /// # Arguments
+    ///  * `path` - The path to the index file, in the binary format or
+    ///    exported as JSON
+    ///
+    /// # Returns
+    ///  * `std::io::Result<Index>` - The index, with the default analyzer
+    pub fn load_index_from_file(path: &Path) -> std::io::Result<Index> {
+        let bytes = std::fs::read(path)?;
+        if binary_format::is_binary_index(&bytes) {
+            binary_format::decode_index(&bytes)
+        } else {
+            Index::load_index_from_json_file(path)
+        }
+    }
+
+    /// Function to save the index to a file in the binary format
+    ///
+    /// The postings are delta and varint encoded, so the file is a lot
+    /// smaller than the JSON export and faster to load. The file is replaced
+    /// atomically, it is never left half written.
+    pub fn save_index_to_file(&self, output_path: &Path) -> std::io::Result<()> {
+        write_atomically(output_path, &binary_format::encode_index(self))
     }
 
     /// Function to load the index from a JSON file
@@ -65,14 +218,33 @@ impl Index {
     ///  * `index_path` - The path to the JSON file
     ///  
     /// # Returns
-    ///  * `std::io::Result<Index>` - The index
+    ///  * `std::io::Result<Index>` - The index, or an error asking to reindex
+    ///    if it was exported by another version of look and can't be read
     pub fn load_index_from_json_file(path: &Path) -> std::io::Result<Index> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
 
         let data: Value = serde_json::from_str(&contents)?;
+        let version = match &data["format_version"] {
+            Value::Null => 1,
+            version => version.as_u64().ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid 'format_version' value in JSON",
+            ))? as u32,
+        };
+        if version > JSON_FORMAT_VERSION {
+            return Err(binary_format::newer_version_error(version));
+        }
+        // The older exports only lack the values added since
+        Index::from_json(&data, version).map_err(|error| match version {
+            JSON_FORMAT_VERSION => error,
+            _ => binary_format::older_version_error(version),
+        })
+    }
 
+    /// Reads the values of a JSON export
+    fn from_json(data: &Value, version: u32) -> std::io::Result<Index> {
         let inverted_index = data["inverted_index"]
             .as_array()
             .ok_or(std::io::Error::new(
@@ -89,87 +261,336 @@ impl Index {
                 std::io::ErrorKind::InvalidData,
                 "Invalid 'document_norms' value in JSON",
             ))?;
+        let document_lengths = data["document_lengths"]
+            .as_array()
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid 'document_lengths' value in JSON",
+            ))?;
+        let document_metadata = data["document_metadata"]
+            .as_array()
+            .ok_or(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid 'document_metadata' value in JSON",
+            ))?;
+        let document_languages =
+            data["document_languages"]
+                .as_array()
+                .ok_or(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Invalid 'document_languages' value in JSON",
+                ))?;
         let num_docs = data["num_docs"].as_u64().ok_or(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
             "Invalid 'num_docs' value in JSON",
         ))?;
 
-        let inverted_index: Vec<(Term, Vec<(Document, u32)>)> =
+        let inverted_index: Vec<(Term, SerializedPostings)> =
             serde_json::from_value(Value::Array(inverted_index.clone()))?;
         let idf: Vec<(Term, f64)> = serde_json::from_value(Value::Array(idf.clone()))?;
         let document_norms: Vec<(Document, f64)> =
             serde_json::from_value(Value::Array(document_norms.clone()))?;
+        let document_lengths: Vec<(Document, u32)> =
+            serde_json::from_value(Value::Array(document_lengths.clone()))?;
+        let document_metadata: Vec<(Document, DocumentMetadata)> =
+            serde_json::from_value(Value::Array(document_metadata.clone()))?;
+        let document_languages: Vec<(Document, String)> =
+            serde_json::from_value(Value::Array(document_languages.clone()))?;
+        let built_with: Option<AnalyzerHeader> = serde_json::from_value(data["analyzer"].clone())?;
+        // The edge n-grams before version 3 are the prefixes of the stemmed
+        // terms, they are built again by `look reindex`. The version 3 ones
+        // are read without the words of their documents.
+        let edge_ngrams: Option<EdgeNgrams> = match version {
+            1 | 2 => None,
+            _ => serde_json::from_value(data["edge_ngrams"].clone())?,
+        };
+        let built_at: Option<u64> = serde_json::from_value(data["built_at"].clone())?;
 
         let index = Index {
             inverted_index: deserialize_inverted_index(&inverted_index),
             idf: deserialize_vec_to_hashmap(&idf),
             document_norms: deserialize_vec_to_hashmap(&document_norms),
+            document_lengths: deserialize_vec_to_hashmap(&document_lengths),
+            document_metadata: deserialize_vec_to_hashmap(&document_metadata),
+            document_languages: deserialize_vec_to_hashmap(&document_languages),
             num_docs: num_docs as usize,
+            built_with,
+            built_at,
+            edge_ngrams,
+            analyzer: Analyzer::new(),
         };
 
         Ok(index)
     }
 
-    /// Function to save the index to a JSON file
+    /// Function to save the index to a JSON file, easier to inspect than the
+    /// binary format when debugging, the file is replaced atomically
     pub fn save_index_to_json_file(&self, output_path: &Path) -> std::io::Result<()> {
         use super::json_serialization::{serialize_hashmap_to_vec, serialize_inverted_index};
 
         let inverted_index = serialize_inverted_index(&self.inverted_index);
         let idf = serialize_hashmap_to_vec(&self.idf);
         let document_norms = serialize_hashmap_to_vec(&self.document_norms);
+        let document_lengths = serialize_hashmap_to_vec(&self.document_lengths);
+        let document_metadata = serialize_hashmap_to_vec(&self.document_metadata);
+        let document_languages = serialize_hashmap_to_vec(&self.document_languages);
 
         let index = serde_json::json!({
+                "format_version": JSON_FORMAT_VERSION,
                 "inverted_index": inverted_index,
                 "idf": idf,
                 "document_norms": document_norms,
+                "document_lengths": document_lengths,
+                "document_metadata": document_metadata,
+                "document_languages": document_languages,
                 "num_docs": self.num_docs,
+                "analyzer": self.built_with,
+                "built_at": self.built_at,
+                "edge_ngrams": self.edge_ngrams,
         });
 
-        let mut file = File::create(output_path)?;
-        file.write_all(index.to_string().as_bytes())?;
+        write_atomically(output_path, index.to_string().as_bytes())
+    }
 
-        Ok(())
+    /// Removes a document from the index
+    ///
+    /// # Arguments
+    ///  * `document` - The document to remove
+    ///
+    /// # Returns
+    ///  * `true` if the document was in the index
+    ///
+    /// The IDF and the document norms are recalculated, so the index can be
+    /// searched right away.
+    pub fn remove_document(&mut self, document: &Document) -> bool {
+        let removed = self.remove_document_postings(document);
+        if removed {
+            self.calculate_idf();
+        }
+        removed
     }
 
-    fn insert_token(&mut self, token: &str, document: &Document) {
+    /// Adds a document to the index, or replaces it if it was already indexed
+    ///
+    /// # Arguments
+    ///  * `document` - The document to add or replace
+    ///  * `text` - The new text of the document
+    ///
+    /// The IDF and the document norms are recalculated, so the index can be
+    /// searched right away.
+    pub fn upsert_document(&mut self, document: &Document, text: &str) {
+        self.store_processed_text_in_index(document, text);
+        self.calculate_idf();
+    }
+
+    /// Moves the documents of another index into this one, replacing the ones
+    /// already indexed
+    ///
+    /// # Arguments
+    ///  * `other` - An index built with the same analyzer and edge n-gram
+    ///    lengths, e.g. by another thread from `empty_like`
+    ///
+    /// The IDF and document norms are stale afterwards, call `calculate_idf`
+    /// once all the indexes are merged.
+    pub fn merge(&mut self, other: Index) {
+        let replaced_documents: HashSet<Document> = other
+            .document_lengths
+            .keys()
+            .filter(|document| self.document_lengths.contains_key(*document))
+            .cloned()
+            .collect();
+        self.remove_documents_postings(&replaced_documents);
+        for (term, docs) in other.inverted_index {
+            match self.inverted_index.get_mut(&term) {
+                Some(postings) => postings.extend(docs),
+                None => {
+                    self.inverted_index.insert(term, docs);
+                }
+            }
+        }
+        if let (Some(edge_ngrams), Some(other_edge_ngrams)) =
+            (&mut self.edge_ngrams, other.edge_ngrams)
+        {
+            edge_ngrams.merge(other_edge_ngrams);
+        }
+        self.num_docs += other.document_lengths.len();
+        self.document_lengths.extend(other.document_lengths);
+        self.document_metadata.extend(other.document_metadata);
+        self.document_languages.extend(other.document_languages);
+    }
+
+    /// Removes every posting of `document` from the index
+    ///
+    /// The IDF and document norms are stale afterwards, call `calculate_idf`
+    /// once all the changes to the index are done.
+    pub(crate) fn remove_document_postings(&mut self, document: &Document) -> bool {
+        self.remove_documents_postings(&HashSet::from([document.clone()])) == 1
+    }
+
+    /// Removes every posting of the documents from the index
+    ///
+    /// The terms are scanned once whatever the number of documents, so
+    /// removing the documents together is faster than one by one.
+    ///
+    /// # Returns
+    ///  * The number of documents that were in the index
+    pub(crate) fn remove_documents_postings(&mut self, documents: &HashSet<Document>) -> usize {
+        let documents: HashSet<&Document> = documents
+            .iter()
+            .filter(|document| self.document_lengths.contains_key(*document))
+            .collect();
+        if documents.is_empty() {
+            return 0;
+        }
+        let mut removed_terms = Vec::new();
+        self.inverted_index.retain(|term, docs| {
+            // Whichever of the postings and the documents is the shortest
+            if docs.len() <= documents.len() {
+                docs.retain(|document, _| !documents.contains(document));
+            } else {
+                for document in &documents {
+                    docs.remove(*document);
+                }
+            }
+            if docs.is_empty() {
+                removed_terms.push(term.0.clone());
+            }
+            !docs.is_empty()
+        });
+        if let Some(edge_ngrams) = &mut self.edge_ngrams {
+            for term in &removed_terms {
+                edge_ngrams.remove_term(term);
+            }
+            edge_ngrams.remove_documents(documents.iter().map(|document| document.path.as_str()));
+        }
+        for document in &documents {
+            self.document_lengths.remove(*document);
+            self.document_norms.remove(*document);
+            self.document_metadata.remove(*document);
+            self.document_languages.remove(*document);
+        }
+        self.num_docs -= documents.len();
+        documents.len()
+    }
+
+    fn insert_token(&mut self, token: &str, word: &str, position: u32, document: &Document) {
+        if let Some(edge_ngrams) = &mut self.edge_ngrams {
+            edge_ngrams.insert_word(&document.path, word, token);
+        }
         let term = Term(token.to_owned());
-        let entry = self.inverted_index.entry(term).or_insert_with(HashMap::new);
-        let term_frequency = entry.entry(document.clone()).or_insert(0);
-        *term_frequency += 1;
+        let entry = self.inverted_index.entry(term).or_default();
+        let positions = entry.entry(document.clone()).or_default();
+        positions.push(position);
     }
 
-    fn update_document_norm(&mut self, document: &Document) {
-        let mut document_norm = 0.0;
-        for (_, term_frequency) in self.inverted_index.values().flatten() {
-            document_norm += (*term_frequency as f64).powi(2);
+    /// Calculates the length of each document's TF-IDF vector
+    fn calculate_document_norms(&mut self) {
+        self.document_norms.clear();
+        for (term, docs) in &self.inverted_index {
+            let idf = self.idf.get(term).unwrap_or(&1.0);
+            for (document, positions) in docs {
+                let weight = positions.len() as f64 * idf;
+                *self.document_norms.entry(document.clone()).or_insert(0.0) += weight * weight;
+            }
+        }
+        for document_norm in self.document_norms.values_mut() {
+            *document_norm = document_norm.sqrt();
         }
-        document_norm = document_norm.sqrt();
-        self.document_norms.insert(document.clone(), document_norm);
+    }
+}
+
+impl SearchIndex for Index {
+    fn analyzer(&self) -> &Analyzer {
+        &self.analyzer
+    }
+
+    fn built_with(&self) -> Option<&AnalyzerHeader> {
+        self.built_with.as_ref()
+    }
+
+    fn num_docs(&self) -> usize {
+        self.num_docs
+    }
+
+    fn documents(&self) -> HashSet<Document> {
+        self.document_lengths.keys().cloned().collect()
+    }
+
+    fn document_length(&self, document: &Document) -> Option<u32> {
+        self.document_lengths.get(document).copied()
+    }
+
+    fn document_norm(&self, document: &Document) -> Option<f64> {
+        self.document_norms.get(document).copied()
+    }
+
+    fn average_document_length(&self) -> f64 {
+        if self.document_lengths.is_empty() {
+            return 0.0;
+        }
+        let total_length: u64 = self.document_lengths.values().map(|l| *l as u64).sum();
+        total_length as f64 / self.document_lengths.len() as f64
+    }
+
+    fn postings(&self, term: &str) -> Option<&Postings> {
+        self.inverted_index.get(&Term(term.to_owned()))
+    }
+
+    fn idf(&self, term: &str) -> Option<f64> {
+        self.idf.get(&Term(term.to_owned())).copied()
+    }
+
+    fn terms_from(
+        &self,
+        lower_bound: Bound<&str>,
+    ) -> Box<dyn Iterator<Item = (String, usize)> + '_> {
+        let lower_bound = lower_bound.map(|term| Term(term.to_owned()));
+        Box::new(
+            self.inverted_index
+                .range((lower_bound, Bound::Unbounded))
+                .map(|(term, docs)| (term.0.clone(), docs.len())),
+        )
+    }
+
+    fn edge_ngram_terms(&self, prefix: &str) -> Option<Vec<String>> {
+        let terms = self.edge_ngrams.as_ref()?.terms_starting_with(prefix)?;
+        Some(terms.into_iter().cloned().collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use serde_json::Value;
     use std::{collections::HashMap, path::Path};
 
-    use crate::{index_builder::index_storage::Term, Index};
+    use super::JSON_FORMAT_VERSION;
+    use crate::{
+        data_ingestion::{Analyzer, Lang},
+        index_builder::{index_storage::Term, SearchIndex},
+        Index,
+    };
+
+    /// An index of the words as they are written, to test the term dictionary
+    fn unstemmed_index() -> Index {
+        Index::with_analyzer(Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap())
+    }
 
     fn build_index_with_3_docs() -> super::Index {
         use super::Index;
         use std::path::Path;
 
         let mut index = Index::new();
-        let text = "This is a test sentence.";
+        let text = "This is a sample sentence.";
         let path = Path::new("test.txt");
         let document = super::Document::new(path.to_str().unwrap().to_owned());
         index.store_processed_text_in_index(&document, text);
 
-        let text = "This is another test sentence.";
+        let text = "This is another sample sentence.";
         let path = Path::new("test2.txt");
         let document = super::Document::new(path.to_str().unwrap().to_owned());
         index.store_processed_text_in_index(&document, text);
 
-        let text = "This is a third test sentence.";
+        let text = "This is a third sample sentence.";
         let path = Path::new("test3.txt");
         let document = super::Document::new(path.to_str().unwrap().to_owned());
         index.store_processed_text_in_index(&document, text);
@@ -188,7 +609,7 @@ mod tests {
         use std::path::Path;
 
         let mut index = Index::new();
-        let text = "This is a test sentence.";
+        let text = "This is a sample sentence.";
         let path = Path::new("test.txt");
         let document = super::Document::new(path.to_str().unwrap().to_owned());
 
@@ -196,20 +617,18 @@ mod tests {
         index.store_processed_text_in_index(&document, text);
 
         assert_eq!(index.num_docs, 1);
-        assert_eq!(index.inverted_index.len(), 5);
+        // "this", "is" and "a" are English stop words
+        assert_eq!(index.inverted_index.len(), 2);
 
         // Create a HashMap of the expected results
-        let mut expected: HashMap<Term, HashMap<super::Document, u32>> = HashMap::new();
-
-        for term in vec![
-            Term("This".to_owned()),
-            Term("is".to_owned()),
-            Term("a".to_owned()),
-            Term("test".to_owned()),
-            Term("sentence.".to_owned()),
+        let mut expected: HashMap<Term, HashMap<super::Document, Vec<u32>>> = HashMap::new();
+
+        for (term, position) in [
+            (Term("sampl".to_owned()), 3),
+            (Term("sentenc".to_owned()), 4),
         ] {
             let mut documents = HashMap::new();
-            documents.insert(document.clone(), 1);
+            documents.insert(document.clone(), vec![position]);
             expected.insert(term, documents);
         }
 
@@ -227,16 +646,8 @@ mod tests {
         // Calculate the IDF for each term
         let mut expected: HashMap<Term, f64> = HashMap::new();
 
-        for term in vec![
-            Term("This".to_owned()),
-            Term("is".to_owned()),
-            Term("a".to_owned()),
-            Term("test".to_owned()),
-            Term("sentence.".to_owned()),
-            Term("another".to_owned()),
-            Term("third".to_owned()),
-        ] {
-            let idf = 3.0 / index.inverted_index.get(&term).unwrap().len() as f64;
+        for term in [Term("sampl".to_owned()), Term("sentenc".to_owned())] {
+            let idf = (3.0 / index.inverted_index.get(&term).unwrap().len() as f64).ln() + 1.0;
             expected.insert(term, idf);
         }
 
@@ -245,6 +656,221 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_document_norms_only_use_the_document_terms() {
+        let mut index = Index::new();
+        let short = super::Document::new("short.txt".to_owned());
+        let long = super::Document::new("long.txt".to_owned());
+        index.store_processed_text_in_index(&short, "kubernetes");
+        index.store_processed_text_in_index(&long, "database migration rollback cluster");
+        index.calculate_idf();
+
+        let idf = index.idf[&Term("kubernet".to_owned())];
+        assert_eq!(index.document_norms[&short], idf);
+        assert_eq!(index.document_lengths[&short], 1);
+        assert_eq!(index.document_lengths[&long], 4);
+        assert_eq!(index.average_document_length(), 2.5);
+    }
+
+    #[test]
+    fn test_store_same_document_twice() {
+        let mut index = Index::new();
+        let document = super::Document::new("notes.txt".to_owned());
+        index.store_processed_text_in_index(&document, "kubernetes cluster");
+        index.store_processed_text_in_index(&document, "kubernetes cluster");
+        index.calculate_idf();
+
+        assert_eq!(index.num_docs, 1);
+        assert_eq!(
+            index.inverted_index[&Term("kubernet".to_owned())][&document],
+            vec![0]
+        );
+        assert_eq!(index.document_lengths[&document], 2);
+    }
+
+    #[test]
+    fn test_documents_are_stemmed_in_their_language() {
+        let mut index = Index::new();
+        let english = super::Document::new("notes.txt".to_owned());
+        let spanish = super::Document::new("notas.txt".to_owned());
+        index.store_processed_text_in_index(&english, "Deploying the new clusters");
+        index.store_processed_text_in_index(
+            &spanish,
+            "Los despliegues de las aplicaciones en el servidor nuevo",
+        );
+
+        assert_eq!(index.document_languages[&english], "eng");
+        assert_eq!(index.document_languages[&spanish], "spa");
+        assert!(index
+            .inverted_index
+            .contains_key(&Term("deploy".to_owned())));
+        assert!(index.inverted_index[&Term("desplieg".to_owned())].contains_key(&spanish));
+    }
+
+    #[test]
+    fn test_remove_document() {
+        let mut index = build_index_with_3_docs();
+        let document = super::Document::new("test3.txt".to_owned());
+
+        assert!(index.remove_document(&document));
+        assert!(!index.remove_document(&document));
+
+        assert_eq!(index.num_docs, 2);
+        assert!(!index.document_norms.contains_key(&document));
+        assert!(!index.document_lengths.contains_key(&document));
+        assert!(index
+            .inverted_index
+            .values()
+            .all(|docs| !docs.contains_key(&document)));
+
+        // "sampl" is now in 2 out of 2 documents
+        let idf = index.idf[&Term("sampl".to_owned())];
+        assert_eq!(idf, 1.0);
+    }
+
+    #[test]
+    fn test_merge_replaces_documents() {
+        let mut index = build_index_with_3_docs();
+        let mut other = index.empty_like();
+        let document = super::Document::new("test2.txt".to_owned());
+        let new_document = super::Document::new("test4.txt".to_owned());
+        other.store_processed_text_in_index(&document, "A rewritten paragraph");
+        other.store_processed_text_in_index(&new_document, "One more sample sentence");
+        index.merge(other);
+        index.calculate_idf();
+
+        assert_eq!(index.num_docs, 4);
+        assert_eq!(index.document_lengths.len(), 4);
+        let sample = &index.inverted_index[&Term("sampl".to_owned())];
+        assert!(!sample.contains_key(&document));
+        assert!(sample.contains_key(&new_document));
+        assert!(index.inverted_index[&Term("paragraph".to_owned())].contains_key(&document));
+    }
+
+    #[test]
+    fn test_upsert_document() {
+        let mut index = Index::new();
+        let document = super::Document::new("notes.txt".to_owned());
+        let other = super::Document::new("other.txt".to_owned());
+        index.upsert_document(&other, "database migration");
+        index.upsert_document(&document, "kubernetes cluster");
+        index.upsert_document(&document, "database rollback");
+
+        assert_eq!(index.num_docs, 2);
+        assert!(!index
+            .inverted_index
+            .contains_key(&Term("kubernet".to_owned())));
+        assert!(!index.idf.contains_key(&Term("kubernet".to_owned())));
+        assert_eq!(index.inverted_index[&Term("databas".to_owned())].len(), 2);
+        assert_eq!(index.idf[&Term("databas".to_owned())], 1.0);
+        assert_eq!(index.idf[&Term("rollback".to_owned())], 2f64.ln() + 1.0);
+    }
+
+    #[test]
+    fn test_expand_wildcard() {
+        let mut index = unstemmed_index();
+        let documents = [
+            ("notes.txt", "deploy deployment deployed"),
+            ("more.txt", "deployment deploys database"),
+            ("other.txt", "deployment rollback"),
+        ];
+        for (path, text) in documents {
+            index.store_processed_text_in_index(&super::Document::new(path.to_owned()), text);
+        }
+
+        assert_eq!(
+            index.expand_wildcard("deploy*", 10),
+            vec!["deploy", "deployed", "deployment", "deploys"]
+        );
+        assert_eq!(index.expand_wildcard("deploy?", 10), vec!["deploys"]);
+        assert_eq!(index.expand_wildcard("d*e?", 10), vec!["deployed"]);
+        assert_eq!(index.expand_wildcard("*back", 10), vec!["rollback"]);
+        assert!(index.expand_wildcard("cluster*", 10).is_empty());
+        // The most common terms are kept when there are too many
+        assert_eq!(
+            index.expand_wildcard("deploy*", 2),
+            vec!["deploy", "deployment"]
+        );
+    }
+
+    #[test]
+    fn test_edge_ngrams() {
+        let mut index = unstemmed_index();
+        assert!(!index.enable_edge_ngrams(2, 6));
+        index.store_processed_text_in_index(
+            &super::Document::new("notes.txt".to_owned()),
+            "deploy deployment",
+        );
+        let other = super::Document::new("other.txt".to_owned());
+        index.store_processed_text_in_index(&other, "deployed database");
+
+        // Same terms as the prefix scan, whether the prefix is covered or not
+        for pattern in ["de*", "deploy*", "deploym*", "d*e?"] {
+            let edge_ngrams = index.edge_ngrams.take();
+            let scanned = index.expand_wildcard(pattern, 10);
+            index.edge_ngrams = edge_ngrams;
+            assert_eq!(index.expand_wildcard(pattern, 10), scanned, "{}", pattern);
+        }
+        assert_eq!(
+            index.expand_wildcard("dep*", 10),
+            vec!["deploy", "deployed", "deployment"]
+        );
+
+        index.remove_document(&other);
+        assert_eq!(
+            index.expand_wildcard("dep*", 10),
+            vec!["deploy", "deployment"]
+        );
+        assert!(index.expand_wildcard("da*", 10).is_empty());
+        let edge_ngrams = index.edge_ngrams.as_ref().unwrap();
+        assert!(!edge_ngrams.prefixes.contains_key("da"));
+
+        // The edge n-grams are saved with the index
+        let index_path = std::env::temp_dir().join("look_test_edge_ngrams.json");
+        index.save_index_to_json_file(&index_path).unwrap();
+        let loaded = Index::load_index_from_json_file(&index_path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(loaded.edge_ngrams, index.edge_ngrams);
+
+        // The words aren't stored, other lengths need the documents again
+        assert!(!index.enable_edge_ngrams(2, 6));
+        assert!(index.enable_edge_ngrams(2, 4));
+        assert_eq!(index.num_docs, 0);
+        assert!(index.edge_ngrams.as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expand_fuzzy() {
+        let mut index = unstemmed_index();
+        let documents = [
+            ("notes.txt", "kubernetes cluster clusters"),
+            ("more.txt", "kubernetes clustering culture"),
+            ("other.txt", "kubernetes clusters database"),
+        ];
+        for (path, text) in documents {
+            index.store_processed_text_in_index(&super::Document::new(path.to_owned()), text);
+        }
+
+        assert_eq!(
+            index.expand_fuzzy("clustr", 1, 10),
+            vec![("cluster".to_owned(), 1)]
+        );
+        assert_eq!(
+            index.expand_fuzzy("clustr", 2, 10),
+            vec![("cluster".to_owned(), 1), ("clusters".to_owned(), 2)]
+        );
+        assert_eq!(
+            index.expand_fuzzy("kubernetes", 1, 10),
+            vec![("kubernetes".to_owned(), 0)]
+        );
+        assert!(index.expand_fuzzy("migration", 2, 10).is_empty());
+        // The closest terms are kept when there are too many
+        assert_eq!(
+            index.expand_fuzzy("clusters", 3, 2),
+            vec![("cluster".to_owned(), 1), ("clusters".to_owned(), 0)]
+        );
+    }
+
     #[test]
     fn test_load_index_from_json_file() {
         // Create an index with 3 documents
@@ -260,7 +886,118 @@ mod tests {
         assert_eq!(expected_index.inverted_index, index.inverted_index);
         assert_eq!(expected_index.idf, index.idf);
         assert_eq!(expected_index.document_norms, index.document_norms);
+        assert_eq!(expected_index.document_lengths, index.document_lengths);
+        assert_eq!(expected_index.document_metadata, index.document_metadata);
+        assert_eq!(expected_index.document_languages, index.document_languages);
         assert_eq!(expected_index.num_docs, index.num_docs);
+        assert_eq!(expected_index.built_with, index.built_with);
+    }
+
+    #[test]
+    fn test_load_index_from_file() {
+        let mut expected_index = Index::new();
+        expected_index.enable_edge_ngrams(2, 15);
+        for (path, text) in [
+            ("test.txt", "This is a sample sentence."),
+            ("test2.txt", "This is another sample sentence."),
+        ] {
+            expected_index
+                .store_processed_text_in_index(&super::Document::new(path.to_owned()), text);
+        }
+        expected_index.calculate_idf();
+
+        let index_path = std::env::temp_dir().join("look_test_index.bin");
+        expected_index.save_index_to_file(&index_path).unwrap();
+        let index = Index::load_index_from_file(&index_path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(expected_index.inverted_index, index.inverted_index);
+        assert_eq!(expected_index.idf, index.idf);
+        assert_eq!(expected_index.document_lengths, index.document_lengths);
+        assert_eq!(expected_index.built_with, index.built_with);
+        assert_eq!(expected_index.edge_ngrams, index.edge_ngrams);
+
+        // The JSON exports can be loaded too
+        let index_path = std::env::temp_dir().join("look_test_index_export.json");
+        expected_index.save_index_to_json_file(&index_path).unwrap();
+        let index = Index::load_index_from_file(&index_path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+        assert_eq!(expected_index.inverted_index, index.inverted_index);
+        assert_eq!(expected_index.edge_ngrams, index.edge_ngrams);
+    }
+
+    #[test]
+    fn test_json_format_versions() {
+        let mut expected_index = build_index_with_3_docs();
+        expected_index.built_at = Some(1_700_000_000);
+        let index_path = std::env::temp_dir().join("look_test_index_versions.json");
+        expected_index.save_index_to_json_file(&index_path).unwrap();
+        let index = Index::load_index_from_json_file(&index_path).unwrap();
+        assert_eq!(expected_index.built_at, index.built_at);
+
+        let mut data: Value =
+            serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+        assert_eq!(data["format_version"], JSON_FORMAT_VERSION);
+        let mut load_with = |name: &str, value: Value| {
+            data[name] = value;
+            std::fs::write(&index_path, data.to_string()).unwrap();
+            Index::load_index_from_json_file(&index_path)
+        };
+
+        // An export without a version nor a build time is still read
+        load_with("built_at", Value::Null).unwrap();
+        let index = load_with("format_version", Value::Null).unwrap();
+        assert_eq!(index.built_at, None);
+        assert_eq!(expected_index.inverted_index, index.inverted_index);
+
+        let error = load_with("num_docs", Value::Null).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The index was built by an older version of look (format version 1), run `look reindex`."
+        );
+        let error = load_with("format_version", (JSON_FORMAT_VERSION + 1).into()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "The index was built by a newer version of look (format version {}), upgrade look or run `look reindex`.",
+                JSON_FORMAT_VERSION + 1
+            )
+        );
+        std::fs::remove_file(&index_path).unwrap();
+    }
+
+    #[test]
+    fn test_analyzer_changes_are_detected() {
+        let mut index = build_index_with_3_docs();
+        assert!(index.check_analyzer().is_ok());
+        assert!(!index.reset_if_analyzer_changed());
+
+        index.analyzer = Analyzer::from_pipeline("lowercase,stem").unwrap();
+        let error = index.check_analyzer().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The index was built with another analyzer (tokenizer: words, pipeline: \
+             lowercase,stop_words,stem,ascii_fold) than the configured one (tokenizer: words, \
+             pipeline: lowercase,stem), run `look reindex`."
+        );
+        assert!(index.reset_if_analyzer_changed());
+        assert_eq!(index.num_docs, 0);
+        assert!(index.inverted_index.is_empty());
+        assert!(index.check_analyzer().is_ok());
+
+        // So do the custom stop words
+        index.analyzer = Analyzer::new();
+        assert!(index.reset_if_analyzer_changed());
+        index
+            .analyzer
+            .custom_stop_words
+            .insert(Lang::Eng, ["todo".to_owned()].into());
+        assert!(index.check_analyzer().is_err());
+        assert!(index.reset_if_analyzer_changed());
+        assert!(index.check_analyzer().is_ok());
+
+        // The indexes saved before the analyzer was recorded are rebuilt too
+        index.built_with = None;
+        assert!(index.check_analyzer().is_err());
     }
 
     #[test]
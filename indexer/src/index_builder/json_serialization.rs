@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use super::{Document, Term};
+use super::{Document, Postings, Term};
+
+/// The postings of a term as stored in the JSON index
+pub type SerializedPostings = Vec<(Document, Vec<u32>)>;
 
 pub fn serialize_hashmap_to_vec<T: Clone, U: Clone>(
     hashmap: &std::collections::HashMap<T, U>,
@@ -12,15 +15,15 @@ pub fn serialize_hashmap_to_vec<T: Clone, U: Clone>(
 }
 
 pub fn serialize_inverted_index(
-    inverted_index: &std::collections::HashMap<Term, HashMap<Document, u32>>,
-) -> Vec<(Term, Vec<(Document, u32)>)> {
+    inverted_index: &BTreeMap<Term, Postings>,
+) -> Vec<(Term, SerializedPostings)> {
     inverted_index
         .iter()
         .map(|(term, docs)| {
             (
                 term.clone(),
                 docs.iter()
-                    .map(|(doc, freq)| (doc.clone(), *freq))
+                    .map(|(doc, positions)| (doc.clone(), positions.clone()))
                     .collect(),
             )
         })
@@ -33,9 +36,7 @@ pub fn deserialize_vec_to_hashmap<T: Clone + Eq + std::hash::Hash, U: Clone>(
     vec.iter().cloned().collect()
 }
 
-pub fn deserialize_inverted_index(
-    vec: &[(Term, Vec<(Document, u32)>)],
-) -> HashMap<Term, HashMap<Document, u32>> {
+pub fn deserialize_inverted_index(vec: &[(Term, SerializedPostings)]) -> BTreeMap<Term, Postings> {
     vec.iter()
         .map(|(term, docs)| (term.clone(), deserialize_vec_to_hashmap(docs)))
         .collect()
@@ -0,0 +1,91 @@
+/// A Levenshtein automaton, accepts the words within `max_distance` edits of a word
+///
+/// The automaton is fed one character at a time. Its state is the row of
+/// edit distances between the characters read so far and every prefix of the
+/// word, so walking a sorted dictionary can reuse the state of the prefix two
+/// consecutive terms share, and skip every term of a prefix once it can't
+/// match anymore.
+pub struct LevenshteinAutomaton {
+    word: Vec<char>,
+    max_distance: u32,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(word: &str, max_distance: u32) -> LevenshteinAutomaton {
+        LevenshteinAutomaton {
+            word: word.chars().collect(),
+            max_distance,
+        }
+    }
+
+    /// The state before reading any character
+    pub fn start(&self) -> Vec<u32> {
+        (0..=self.word.len() as u32).collect()
+    }
+
+    /// The state after reading `character` in `state`
+    pub fn step(&self, state: &[u32], character: char) -> Vec<u32> {
+        let mut next = Vec::with_capacity(state.len());
+        next.push(state[0] + 1);
+        for (i, &word_character) in self.word.iter().enumerate() {
+            let substitution = state[i] + u32::from(word_character != character);
+            let distance = substitution.min(state[i + 1] + 1).min(next[i] + 1);
+            // Distances past the maximum are all the same to us
+            next.push(distance.min(self.max_distance + 1));
+        }
+        next
+    }
+
+    /// Returns the distance to the word, if the characters read so far are close enough
+    pub fn distance(&self, state: &[u32]) -> Option<u32> {
+        let distance = state[state.len() - 1];
+        if distance <= self.max_distance {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    /// Whether reading more characters could still reach an accepted word
+    pub fn can_match(&self, state: &[u32]) -> bool {
+        state.iter().any(|&distance| distance <= self.max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(word: &str, other: &str, max_distance: u32) -> Option<u32> {
+        let automaton = LevenshteinAutomaton::new(word, max_distance);
+        let mut state = automaton.start();
+        for character in other.chars() {
+            state = automaton.step(&state, character);
+        }
+        automaton.distance(&state)
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(distance("kubernetes", "kubernetes", 2), Some(0));
+        assert_eq!(distance("kubernetes", "kubernets", 2), Some(1));
+        assert_eq!(distance("kubernetes", "kuberentes", 2), Some(2));
+        assert_eq!(distance("kubernetes", "kubrnts", 2), None);
+        assert_eq!(distance("cluster", "clusters", 1), Some(1));
+        assert_eq!(distance("", "ab", 2), Some(2));
+    }
+
+    #[test]
+    fn test_can_match() {
+        let automaton = LevenshteinAutomaton::new("cluster", 1);
+        let mut state = automaton.start();
+        for character in "cl".chars() {
+            state = automaton.step(&state, character);
+        }
+        assert!(automaton.can_match(&state));
+        for character in "xyz".chars() {
+            state = automaton.step(&state, character);
+        }
+        assert!(!automaton.can_match(&state));
+    }
+}
@@ -1,9 +1,21 @@
+pub mod binary_format;
 pub mod document;
+pub mod edge_ngrams;
 pub mod file_processing;
+pub mod index_file;
+pub mod index_reader;
 pub mod index_storage;
 pub mod json_serialization;
+pub mod levenshtein;
+pub mod search_index;
+pub mod stats;
 
-pub use document::{Document, Term};
+pub use document::{Document, DocumentMetadata, Postings, Term};
+pub use edge_ngrams::EdgeNgrams;
+pub use index_file::IndexLock;
+pub use index_reader::IndexReader;
 pub use index_storage::Index;
 pub use json_serialization::serialize_hashmap_to_vec;
 pub use json_serialization::serialize_inverted_index;
+pub use search_index::SearchIndex;
+pub use stats::{EdgeNgramStats, IndexStats};
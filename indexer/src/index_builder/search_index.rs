@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::ops::Bound;
+
+use crate::data_ingestion::{Analyzer, AnalyzerHeader};
+
+use super::levenshtein::LevenshteinAutomaton;
+use super::{Document, Postings};
+
+/// What a search reads from an index
+///
+/// Implemented by `Index`, which holds everything in memory, and by
+/// `IndexReader`, which only decodes what the query needs from the index file.
+pub trait SearchIndex {
+    /// The analyzer the queries are analyzed with
+    fn analyzer(&self) -> &Analyzer;
+
+    /// The analyzer the documents were indexed with, `None` for an index
+    /// saved before it was recorded
+    fn built_with(&self) -> Option<&AnalyzerHeader>;
+
+    /// The number of documents of the index
+    fn num_docs(&self) -> usize;
+
+    /// Every document of the index
+    fn documents(&self) -> HashSet<Document>;
+
+    /// The number of terms of a document
+    fn document_length(&self, document: &Document) -> Option<u32>;
+
+    /// The norm of the TF-IDF vector of a document
+    fn document_norm(&self, document: &Document) -> Option<f64>;
+
+    /// Returns the average number of terms per document
+    fn average_document_length(&self) -> f64;
+
+    /// The documents containing a term, with the positions of the term
+    fn postings(&self, term: &str) -> Option<&Postings>;
+
+    /// The IDF of a term, `None` until it is calculated
+    fn idf(&self, term: &str) -> Option<f64>;
+
+    /// The number of documents containing a term, `None` if it isn't in the index
+    fn document_frequency(&self, term: &str) -> Option<usize> {
+        self.postings(term).map(|docs| docs.len())
+    }
+
+    /// Function to walk the terms of the index in order
+    ///
+    /// # Arguments
+    ///  * `lower_bound` - Where to start, the terms before it are skipped
+    ///
+    /// # Returns
+    ///  * The terms with the number of documents containing them
+    fn terms_from(
+        &self,
+        lower_bound: Bound<&str>,
+    ) -> Box<dyn Iterator<Item = (String, usize)> + '_>;
+
+    /// Returns the terms starting with `prefix` from the edge n-grams, `None`
+    /// if they are disabled or don't cover the prefix
+    fn edge_ngram_terms(&self, prefix: &str) -> Option<Vec<String>>;
+
+    /// Checks that the analyzer of the index is the one its documents were indexed with
+    ///
+    /// # Returns
+    ///  * An error asking to reindex if the analyzer changed since, the
+    ///    queries wouldn't be analyzed like the documents
+    fn check_analyzer(&self) -> Result<(), Box<dyn Error>> {
+        let header = self.analyzer().header();
+        match self.built_with() {
+            Some(built_with) if *built_with == header => Ok(()),
+            Some(built_with) => Err(format!(
+                "The index was built with another analyzer ({}) than the configured one ({}), run `look reindex`.",
+                built_with, header
+            )
+            .into()),
+            None => Err("The index doesn't record the analyzer it was built with, run `look reindex`.".into()),
+        }
+    }
+
+    /// Function to find the terms of the index that match a wildcard pattern
+    ///
+    /// # Arguments
+    ///  * `pattern` - An analyzed term where `*` matches any number of
+    ///    characters and `?` exactly one
+    ///  * `max_expansions` - The maximum number of terms to return, the terms
+    ///    found in more documents are kept
+    ///
+    /// # Returns
+    ///  * The matching terms, sorted alphabetically
+    fn expand_wildcard(&self, pattern: &str, max_expansions: usize) -> Vec<String> {
+        // Only the terms starting with the literal prefix of the pattern can match
+        let prefix_length = pattern.find(['*', '?']).unwrap_or(pattern.len());
+        let prefix = &pattern[..prefix_length];
+
+        // A prefix query is answered from the edge n-grams when they cover it
+        let edge_ngram_terms = match &pattern[prefix_length..] {
+            "*" => self.edge_ngram_terms(prefix),
+            _ => None,
+        };
+        let mut terms: Vec<(String, usize)> = match edge_ngram_terms {
+            Some(terms) => terms
+                .into_iter()
+                .filter_map(|term| {
+                    let document_frequency = self.document_frequency(&term)?;
+                    Some((term, document_frequency))
+                })
+                .collect(),
+            None => {
+                let pattern: Vec<char> = pattern.chars().collect();
+                self.terms_from(Bound::Included(prefix))
+                    .take_while(|(term, _)| term.starts_with(prefix))
+                    .filter(|(term, _)| {
+                        wildcard_matches(&pattern, &term.chars().collect::<Vec<char>>())
+                    })
+                    .collect()
+            }
+        };
+
+        terms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        terms.truncate(max_expansions);
+        let mut terms: Vec<String> = terms.into_iter().map(|(term, _)| term).collect();
+        terms.sort();
+        terms
+    }
+
+    /// Function to find the terms of the index within an edit distance of a term
+    ///
+    /// The sorted terms are walked with a Levenshtein automaton, so the terms
+    /// sharing a prefix that can't match are skipped all at once.
+    ///
+    /// # Arguments
+    ///  * `term` - An analyzed term
+    ///  * `max_distance` - The maximum number of edits
+    ///  * `max_expansions` - The maximum number of terms to return, the closest
+    ///    terms and then the ones found in more documents are kept
+    ///
+    /// # Returns
+    ///  * The matching terms with their distance to `term`, sorted alphabetically
+    fn expand_fuzzy(
+        &self,
+        term: &str,
+        max_distance: u32,
+        max_expansions: usize,
+    ) -> Vec<(String, u32)> {
+        let automaton = LevenshteinAutomaton::new(term, max_distance);
+        // The automaton state after each character of the previous term
+        let mut states = vec![automaton.start()];
+        let mut previous: Vec<char> = Vec::new();
+        let mut lower_bound: Bound<String> = Bound::Unbounded;
+        let mut terms: Vec<(String, u32, usize)> = Vec::new();
+
+        while let Some((candidate, document_frequency)) = self
+            .terms_from(lower_bound.as_ref().map(String::as_str))
+            .next()
+        {
+            let characters: Vec<char> = candidate.chars().collect();
+            let common_prefix = previous
+                .iter()
+                .zip(&characters)
+                .take_while(|(a, b)| a == b)
+                .count();
+            states.truncate(common_prefix + 1);
+
+            let mut dead_end = None;
+            for (i, &character) in characters.iter().enumerate().skip(common_prefix) {
+                let state = automaton.step(&states[i], character);
+                if !automaton.can_match(&state) {
+                    dead_end = Some(i);
+                    break;
+                }
+                states.push(state);
+            }
+
+            match dead_end {
+                Some(i) => {
+                    // No term starting with these characters can match, jump past them
+                    previous = characters[..i].to_vec();
+                    lower_bound = match prefix_successor(&characters[..=i]) {
+                        Some(successor) => Bound::Included(successor),
+                        None => Bound::Excluded(candidate),
+                    };
+                }
+                None => {
+                    if let Some(distance) = automaton.distance(&states[characters.len()]) {
+                        terms.push((candidate.clone(), distance, document_frequency));
+                    }
+                    previous = characters;
+                    lower_bound = Bound::Excluded(candidate);
+                }
+            }
+        }
+
+        terms.sort_by(|a, b| {
+            a.1.cmp(&b.1)
+                .then(b.2.cmp(&a.2))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        terms.truncate(max_expansions);
+        let mut terms: Vec<(String, u32)> = terms
+            .into_iter()
+            .map(|(term, distance, _)| (term, distance))
+            .collect();
+        terms.sort();
+        terms
+    }
+}
+
+/// Returns the smallest string greater than every string starting with `prefix`
+fn prefix_successor(prefix: &[char]) -> Option<String> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        // Skip the surrogate range, which isn't made of valid chars
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            successor.push(next);
+            return Some(successor.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// Checks whether `text` matches `pattern`, where `*` matches any number of
+/// characters and `?` exactly one
+fn wildcard_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was and the text position it's currently matching up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` match one more character
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(super::prefix_successor(&['a', 'b']), Some("ac".to_owned()));
+        assert_eq!(
+            super::prefix_successor(&['a', char::MAX]),
+            Some("b".to_owned())
+        );
+        assert_eq!(super::prefix_successor(&[char::MAX]), None);
+    }
+
+    #[test]
+    fn test_wildcard_matches() {
+        let matches = |pattern: &str, text: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let text: Vec<char> = text.chars().collect();
+            super::wildcard_matches(&pattern, &text)
+        };
+        assert!(matches("te?t", "text"));
+        assert!(!matches("te?t", "tet"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(matches("a**", "a"));
+        assert!(!matches("a*b", "acbd"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+
+use crate::data_ingestion::AnalyzerHeader;
+
+use super::binary_format::{encode_index, read_header, Section};
+use super::Index;
+
+/// The size of an index, as shown by `look stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexStats {
+    pub documents: usize,
+    pub terms: usize,
+    /// The number of positions stored for all the terms
+    pub positions: usize,
+    /// The size of the index file, in bytes
+    pub file_bytes: usize,
+    /// The size of the terms and their postings in the index file, in bytes
+    pub inverted_index_bytes: usize,
+    pub edge_ngrams: Option<EdgeNgramStats>,
+    /// The analyzer the documents were indexed with
+    pub built_with: Option<AnalyzerHeader>,
+    /// When the documents were last indexed, in seconds since the UNIX epoch
+    pub built_at: Option<u64>,
+}
+
+/// The extra size of the edge n-grams of an index
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeNgramStats {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub prefixes: usize,
+    /// The number of (prefix, term) pairs stored
+    pub entries: usize,
+    /// The size of the edge n-grams in the index file, in bytes
+    pub bytes: usize,
+}
+
+impl Index {
+    /// Function to measure the size of the index
+    ///
+    /// # Returns
+    ///  * The number of documents, terms and positions, the size of the
+    ///    inverted index and of the edge n-grams in the index file, and how
+    ///    and when the index was built
+    pub fn stats(&self) -> IndexStats {
+        let positions = self
+            .inverted_index
+            .values()
+            .flat_map(|docs| docs.values())
+            .map(|positions| positions.len())
+            .sum();
+        let bytes = encode_index(self);
+        let sections = read_header(&bytes)
+            .expect("An index encoded in memory is valid")
+            .sections;
+        let section_bytes = |section: Section| sections[section as usize].len();
+
+        IndexStats {
+            documents: self.num_docs,
+            terms: self.inverted_index.len(),
+            positions,
+            file_bytes: bytes.len(),
+            inverted_index_bytes: section_bytes(Section::Terms) + section_bytes(Section::Postings),
+            edge_ngrams: self.edge_ngrams.as_ref().map(|edge_ngrams| EdgeNgramStats {
+                min_length: edge_ngrams.min_length,
+                max_length: edge_ngrams.max_length,
+                prefixes: edge_ngrams.prefixes.len(),
+                entries: edge_ngrams.len(),
+                bytes: section_bytes(Section::EdgeNgrams),
+            }),
+            built_with: self.built_with.clone(),
+            built_at: self.built_at,
+        }
+    }
+}
+
+impl fmt::Display for IndexStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Documents: {}", self.documents)?;
+        writeln!(f, "Terms: {}", self.terms)?;
+        writeln!(f, "Positions: {}", self.positions)?;
+        writeln!(f, "Index file: {}", format_bytes(self.file_bytes))?;
+        writeln!(
+            f,
+            "Inverted index: {}",
+            format_bytes(self.inverted_index_bytes)
+        )?;
+        match &self.built_with {
+            Some(built_with) => writeln!(f, "Analyzer: {}", built_with)?,
+            None => writeln!(f, "Analyzer: unknown")?,
+        }
+        match self.built_at {
+            Some(built_at) => writeln!(f, "Last indexed: {}", format_timestamp(built_at))?,
+            None => writeln!(f, "Last indexed: unknown")?,
+        }
+        match &self.edge_ngrams {
+            Some(edge_ngrams) => {
+                let extra = if self.inverted_index_bytes > 0 {
+                    100.0 * edge_ngrams.bytes as f64 / self.inverted_index_bytes as f64
+                } else {
+                    0.0
+                };
+                write!(
+                    f,
+                    "Edge n-grams ({} to {} characters): {} prefixes, {} entries, {} (+{:.0}% of the inverted index)",
+                    edge_ngrams.min_length,
+                    edge_ngrams.max_length,
+                    edge_ngrams.prefixes,
+                    edge_ngrams.entries,
+                    format_bytes(edge_ngrams.bytes),
+                    extra
+                )
+            }
+            None => write!(f, "Edge n-grams: disabled"),
+        }
+    }
+}
+
+/// Formats a number of bytes like "12.3 KB"
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Formats a number of seconds since the UNIX epoch like "2024-03-01 09:30 UTC"
+fn format_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let minutes = seconds % 86_400 / 60;
+    // Converts the days since the epoch to a date of the proleptic Gregorian
+    // calendar, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::Document;
+
+    #[test]
+    fn test_stats() {
+        let mut index = Index::new();
+        let document = Document::new("notes.txt".to_owned());
+        index.store_processed_text_in_index(&document, "Kubernetes clusters, kubernetes");
+
+        let stats = index.stats();
+        assert_eq!(stats.documents, 1);
+        assert_eq!(stats.terms, 2);
+        assert_eq!(stats.positions, 3);
+        assert!(stats.inverted_index_bytes > 0);
+        assert!(stats.file_bytes > stats.inverted_index_bytes);
+        assert_eq!(stats.edge_ngrams, None);
+        assert!(stats.to_string().ends_with("Edge n-grams: disabled"));
+        assert!(stats.to_string().contains("Last indexed: unknown"));
+        index.built_at = Some(1_709_285_400);
+        assert!(index
+            .stats()
+            .to_string()
+            .contains("Last indexed: 2024-03-01 09:30 UTC"));
+
+        index.enable_edge_ngrams(2, 4);
+        index.store_processed_text_in_index(&document, "Kubernetes clusters, kubernetes");
+        let edge_ngrams = index.stats().edge_ngrams.unwrap();
+        // "ku", "kub", "kube", "cl", "clu", "clus"
+        assert_eq!(edge_ngrams.prefixes, 6);
+        assert_eq!(edge_ngrams.entries, 6);
+        assert!(edge_ngrams.bytes > 0);
+        assert!(index
+            .stats()
+            .to_string()
+            .contains("Edge n-grams (2 to 4 characters): 6 prefixes, 6 entries"));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+        assert_eq!(format_timestamp(1_709_285_400), "2024-03-01 09:30 UTC");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(12_595), "12.3 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MB");
+    }
+}
@@ -1,5 +1,11 @@
+pub mod query_parser;
 pub mod query_processing;
+pub mod scorer;
 pub mod search_result;
+pub mod suggestions;
 
-pub use query_processing::search;
+pub use query_parser::{parse_query, QueryNode, QueryParseError};
+pub use query_processing::{search, search_with_options, search_with_scorer, SearchOptions};
+pub use scorer::{get_scorer, Bm25Scorer, ProximityScorer, Scorer, TfIdfScorer};
 pub use search_result::SearchResult;
+pub use suggestions::suggest_queries;
@@ -0,0 +1,1083 @@
+use crate::data_ingestion::Analyzer;
+
+/// Words allowed between the operands of a `NEAR` without an explicit distance
+pub const DEFAULT_NEAR_DISTANCE: u32 = 10;
+
+/// The largest edit distance of a fuzzy term
+pub const MAX_FUZZY_DISTANCE: u32 = 2;
+
+/// A node of the boolean query tree
+///
+/// The terms stored in the tree are already analyzed, so they can be looked
+/// up in the index directly.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryNode {
+    Term(String),
+    /// Terms that must appear at these positions relative to each other
+    Phrase(Vec<(String, u32)>),
+    /// A term with `*` and `?` wildcards, expanded to the matching index terms
+    Wildcard(String),
+    /// A term expanded to the index terms within this edit distance
+    Fuzzy(String, u32),
+    /// Both operands must appear, in any order, with at most this many words between them
+    Near(Box<QueryNode>, Box<QueryNode>, u32),
+    And(Vec<QueryNode>),
+    Or(Vec<QueryNode>),
+    Not(Box<QueryNode>),
+}
+
+/// The result of parsing a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedQuery {
+    /// The boolean structure documents must satisfy, `None` if the query has no terms
+    pub root: Option<QueryNode>,
+    /// The terms that are not negated, used to rank the matching documents
+    pub terms: Vec<String>,
+    /// The phrases that are not negated, they add to the score of the documents they appear in
+    pub phrases: Vec<Vec<(String, u32)>>,
+    /// What to highlight in the matching documents: single terms and whole phrases
+    pub highlights: Vec<String>,
+    /// The terms added as synonyms of the query terms, they count less in the score
+    pub synonyms: Vec<String>,
+    /// The phrases added as synonyms of the query terms, they count less in the score
+    pub synonym_phrases: Vec<Vec<(String, u32)>>,
+}
+
+/// Error returned when a query can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryParseError {
+    /// Byte offset of the problem in the query
+    pub position: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid query at position {}: {}",
+            self.position, self.message
+        )
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Phrase(String),
+    And,
+    Or,
+    Not,
+    Near(u32),
+    Required,
+    Excluded,
+    OpenParen,
+    CloseParen,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+/// How a clause of a sequence of clauses affects matching
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Occur {
+    /// Plain clauses, documents must match at least one of them
+    Should,
+    /// `+clause`, documents must match it
+    Must,
+    /// `-clause` or `NOT clause`, documents must not match it
+    MustNot,
+}
+
+/// Parses a query into a boolean query tree
+///
+/// The query language supports:
+///  * `alpha beta` - documents with any of the terms
+///  * `alpha AND beta`, `alpha OR beta`, `NOT alpha` - boolean operators,
+///    `NOT` binds tighter than `AND`, which binds tighter than `OR`
+///  * `(alpha OR beta) AND gamma` - grouping
+///  * `+alpha -beta` - required and excluded terms
+///  * `"alpha beta"` - phrases, the terms must appear next to each other
+///  * `alph*`, `al?ha` - wildcards, `*` matches any characters and `?` one
+///  * `alpha~1`, `alpha~2` - fuzzy terms, the index terms within 1 or 2 edits,
+///    `alpha~` picks the distance from the length of the term
+///  * `alpha NEAR/5 beta` - proximity, at most 5 words between the terms in
+///    any order, `NEAR` alone allows `DEFAULT_NEAR_DISTANCE` words. It binds
+///    tighter than `AND` and only applies to terms, phrases and other `NEAR`s
+///
+/// # Arguments
+///  * `query` - The raw query
+///  * `analyzer` - The analyzer applied to each term, it should be the same the index uses.
+///    Terms are analyzed one by one, so the language of the whole query is
+///    detected first unless the analyzer already has one
+///
+/// # Returns
+///  * The `ParsedQuery`, or a `QueryParseError` with the position of the problem
+pub fn parse_query(query: &str, analyzer: &Analyzer) -> Result<ParsedQuery, QueryParseError> {
+    let tokens = lex(query)?;
+    let analyzer = &analyzer.with_language(analyzer.detect_language(query));
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        end: query.len(),
+        analyzer,
+        terms: Vec::new(),
+        phrases: Vec::new(),
+        highlights: Vec::new(),
+        synonyms: Vec::new(),
+        synonym_phrases: Vec::new(),
+    };
+    let root = parser.parse_sequence(false)?;
+    if let Some(token) = parser.peek() {
+        return Err(QueryParseError {
+            position: token.position,
+            message: "unexpected ')'".to_owned(),
+        });
+    }
+
+    Ok(ParsedQuery {
+        root,
+        terms: parser.terms,
+        phrases: parser.phrases,
+        highlights: parser.highlights,
+        synonyms: parser.synonyms,
+        synonym_phrases: parser.synonym_phrases,
+    })
+}
+
+/// Splits the query into words, phrases, operators and parentheses
+fn lex(query: &str) -> Result<Vec<Token>, QueryParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+
+    while let Some((position, character)) = chars.next() {
+        let kind = match character {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '"' => {
+                let start = position + 1;
+                let mut end = None;
+                for (next_position, next) in chars.by_ref() {
+                    if next == '"' {
+                        end = Some(next_position);
+                        break;
+                    }
+                }
+                match end {
+                    Some(end) => TokenKind::Phrase(query[start..end].to_owned()),
+                    None => {
+                        return Err(QueryParseError {
+                            position,
+                            message: "missing closing '\"'".to_owned(),
+                        })
+                    }
+                }
+            }
+            // Modifiers only count when they are attached to what they modify
+            '+' | '-' if matches!(chars.peek(), Some((_, c)) if !c.is_whitespace()) => {
+                if character == '+' {
+                    TokenKind::Required
+                } else {
+                    TokenKind::Excluded
+                }
+            }
+            _ => {
+                let mut end = position + character.len_utf8();
+                while let Some((next_position, next)) = chars.peek() {
+                    if next.is_whitespace() || matches!(next, '(' | ')' | '"') {
+                        break;
+                    }
+                    end = next_position + next.len_utf8();
+                    chars.next();
+                }
+                match &query[position..end] {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    "NEAR" => TokenKind::Near(DEFAULT_NEAR_DISTANCE),
+                    word => match word.strip_prefix("NEAR/") {
+                        Some(distance) => match distance.parse() {
+                            Ok(distance) => TokenKind::Near(distance),
+                            Err(_) => {
+                                return Err(QueryParseError {
+                                    position,
+                                    message: "expected a number of words after NEAR/".to_owned(),
+                                })
+                            }
+                        },
+                        None => TokenKind::Word(word.to_owned()),
+                    },
+                }
+            }
+        };
+        tokens.push(Token { kind, position });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Length of the query, used as the position of errors at the end of it
+    end: usize,
+    analyzer: &'a Analyzer,
+    terms: Vec<String>,
+    phrases: Vec<Vec<(String, u32)>>,
+    highlights: Vec<String>,
+    synonyms: Vec<String>,
+    synonym_phrases: Vec<Vec<(String, u32)>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn error_at_next(&self, message: &str) -> QueryParseError {
+        QueryParseError {
+            position: self.peek().map_or(self.end, |token| token.position),
+            message: message.to_owned(),
+        }
+    }
+
+    /// sequence := and_expression ( "OR"? and_expression )*
+    fn parse_sequence(&mut self, negated: bool) -> Result<Option<QueryNode>, QueryParseError> {
+        let mut clauses = Vec::new();
+
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                None | Some(TokenKind::CloseParen) => break,
+                Some(TokenKind::Or) => {
+                    if clauses.is_empty() {
+                        return Err(self.error_at_next("expected a term before OR"));
+                    }
+                    let operator = self.next().unwrap();
+                    self.expect_operand(&operator, "OR")?;
+                }
+                _ => clauses.push(self.parse_and(negated)?),
+            }
+        }
+
+        Ok(combine_clauses(clauses))
+    }
+
+    /// and_expression := near_expression ( "AND" near_expression )*
+    fn parse_and(&mut self, negated: bool) -> Result<(Occur, Option<QueryNode>), QueryParseError> {
+        let first = self.parse_near(negated)?;
+        if self.peek().map(|token| &token.kind) != Some(&TokenKind::And) {
+            return Ok(first);
+        }
+
+        let mut operands = vec![first];
+        while self.peek().map(|token| &token.kind) == Some(&TokenKind::And) {
+            let operator = self.next().unwrap();
+            self.expect_operand(&operator, "AND")?;
+            operands.push(self.parse_near(negated)?);
+        }
+
+        let mut children = Vec::new();
+        for (occur, node) in operands {
+            match (occur, node) {
+                (_, None) => {}
+                (Occur::MustNot, Some(node)) => children.push(QueryNode::Not(Box::new(node))),
+                (_, Some(node)) => children.push(node),
+            }
+        }
+        Ok((Occur::Should, and_node(children)))
+    }
+
+    /// near_expression := unary ( "NEAR" unary )*
+    fn parse_near(&mut self, negated: bool) -> Result<(Occur, Option<QueryNode>), QueryParseError> {
+        let mut left = self.parse_unary(negated)?;
+        while let Some(TokenKind::Near(distance)) = self.peek().map(|token| token.kind.clone()) {
+            let operator = self.next().unwrap();
+            self.expect_operand(&operator, "NEAR")?;
+            let right = self.parse_unary(negated)?;
+            left = (Occur::Should, near_node(left, right, distance, &operator)?);
+        }
+        Ok(left)
+    }
+
+    /// unary := ( "NOT" | "+" | "-" ) unary | "(" sequence ")" | phrase | word
+    fn parse_unary(
+        &mut self,
+        negated: bool,
+    ) -> Result<(Occur, Option<QueryNode>), QueryParseError> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(self.error_at_next("expected a term")),
+        };
+
+        match token.kind {
+            TokenKind::Not | TokenKind::Excluded => {
+                let (occur, node) = self.parse_operand(!negated, &token)?;
+                // NOT NOT alpha is alpha
+                let occur = if occur == Occur::MustNot {
+                    Occur::Should
+                } else {
+                    Occur::MustNot
+                };
+                Ok((occur, node))
+            }
+            TokenKind::Required => {
+                let (occur, node) = self.parse_operand(negated, &token)?;
+                let occur = if occur == Occur::MustNot {
+                    Occur::MustNot
+                } else {
+                    Occur::Must
+                };
+                Ok((occur, node))
+            }
+            TokenKind::OpenParen => {
+                let node = self.parse_sequence(negated)?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::CloseParen,
+                        ..
+                    }) => Ok((Occur::Should, node)),
+                    _ => Err(QueryParseError {
+                        position: token.position,
+                        message: "missing closing ')'".to_owned(),
+                    }),
+                }
+            }
+            TokenKind::Word(word) if word.contains(['*', '?']) => {
+                Ok((Occur::Should, self.analyze_wildcard(&word)))
+            }
+            TokenKind::Word(word) if word.contains('~') => {
+                Ok((Occur::Should, self.analyze_fuzzy(&word, token.position)?))
+            }
+            TokenKind::Word(word) => Ok((Occur::Should, self.analyze_word(&word, negated))),
+            TokenKind::Phrase(phrase) => Ok((Occur::Should, self.analyze_phrase(&phrase, negated))),
+            TokenKind::CloseParen => Err(QueryParseError {
+                position: token.position,
+                message: "unexpected ')'".to_owned(),
+            }),
+            TokenKind::And | TokenKind::Or | TokenKind::Near(_) => Err(QueryParseError {
+                position: token.position,
+                message: format!(
+                    "expected a term before {}",
+                    match token.kind {
+                        TokenKind::And => "AND",
+                        TokenKind::Or => "OR",
+                        _ => "NEAR",
+                    }
+                ),
+            }),
+        }
+    }
+
+    /// Parses the operand of a `NOT`, `+` or `-` operator
+    fn parse_operand(
+        &mut self,
+        negated: bool,
+        operator: &Token,
+    ) -> Result<(Occur, Option<QueryNode>), QueryParseError> {
+        let name = match operator.kind {
+            TokenKind::Not => "NOT",
+            TokenKind::Required => "+",
+            _ => "-",
+        };
+        self.expect_operand(operator, name)?;
+        self.parse_unary(negated)
+    }
+
+    /// Checks that an operator is followed by something it can apply to
+    fn expect_operand(&self, operator: &Token, name: &str) -> Result<(), QueryParseError> {
+        match self.peek().map(|token| &token.kind) {
+            None
+            | Some(TokenKind::CloseParen)
+            | Some(TokenKind::And)
+            | Some(TokenKind::Or)
+            | Some(TokenKind::Near(_)) => Err(QueryParseError {
+                position: operator.position,
+                message: format!("expected a term after {}", name),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a word through the analyzer, a word may become several terms or none
+    ///
+    /// A term with synonyms matches any of them. The terms of a word that
+    /// follow each other, like the pairs of characters of Chinese text, are
+    /// matched as a phrase. An identifier matches either whole or as the
+    /// phrase of its sub-words.
+    fn analyze_word(&mut self, word: &str, negated: bool) -> Option<QueryNode> {
+        let (identifiers, terms) = self.analyzer.analyze_identifiers(word);
+        if identifiers.is_empty() && terms.is_empty() {
+            // A stop word still stands for its synonyms, like "pr" for "pull request"
+            let stop_words = self
+                .analyzer
+                .without_stop_words()
+                .analyze_with_positions(word);
+            return self.with_synonyms(None, &stop_words, negated);
+        }
+
+        if !negated {
+            let identifier_terms = identifiers.iter().map(|(term, _, _)| term);
+            for term in identifier_terms.chain(terms.iter().map(|(term, _)| term)) {
+                self.terms.push(term.clone());
+                self.highlights.push(term.clone());
+            }
+        }
+        if identifiers.is_empty() {
+            return self.terms_node(terms, negated);
+        }
+
+        // The whole identifiers, with the words that aren't part of one
+        let mut wholes: Vec<(String, u32)> = terms
+            .iter()
+            .filter(|(_, position)| {
+                !identifiers
+                    .iter()
+                    .any(|(_, start, width)| (*start..start + width).contains(position))
+            })
+            .cloned()
+            .collect();
+        wholes.extend(
+            identifiers
+                .into_iter()
+                .map(|(term, position, _)| (term, position)),
+        );
+        wholes.sort_by_key(|(_, position)| *position);
+
+        let mut alternatives: Vec<QueryNode> = self
+            .terms_node(wholes, negated)
+            .into_iter()
+            .chain(self.terms_node(terms, negated))
+            .collect();
+        match alternatives.len() {
+            0 => None,
+            1 => alternatives.pop(),
+            _ => Some(QueryNode::Or(alternatives)),
+        }
+    }
+
+    /// Returns a node matching the terms of a word, a phrase when they follow
+    /// each other
+    fn terms_node(&mut self, terms: Vec<(String, u32)>, negated: bool) -> Option<QueryNode> {
+        if terms.len() > 1 && terms.windows(2).all(|pair| pair[0].1 < pair[1].1) {
+            let phrase = relative_positions(terms);
+            return self.with_synonyms(Some(QueryNode::Phrase(phrase.clone())), &phrase, negated);
+        }
+
+        // The terms at the same position, like the n-grams of a word
+        let mut nodes = Vec::new();
+        for (term, _) in terms {
+            let node = QueryNode::Term(term.clone());
+            nodes.extend(self.with_synonyms(Some(node), &[(term, 0)], negated));
+        }
+        and_node(nodes)
+    }
+
+    /// Returns a node matching `node` or any of the synonyms of its `terms`
+    fn with_synonyms(
+        &mut self,
+        node: Option<QueryNode>,
+        terms: &[(String, u32)],
+        negated: bool,
+    ) -> Option<QueryNode> {
+        let mut alternatives: Vec<QueryNode> = node.into_iter().collect();
+        for synonym in self.analyzer.synonyms(terms) {
+            if !negated {
+                self.highlights
+                    .extend(synonym.iter().map(|(term, _)| term.clone()));
+            }
+            match synonym.as_slice() {
+                [(term, _)] => {
+                    if !negated {
+                        self.synonyms.push(term.clone());
+                    }
+                    alternatives.push(QueryNode::Term(term.clone()));
+                }
+                _ => {
+                    if !negated {
+                        self.synonym_phrases.push(synonym.clone());
+                    }
+                    alternatives.push(QueryNode::Phrase(synonym.clone()));
+                }
+            }
+        }
+        match alternatives.len() {
+            0 => None,
+            1 => alternatives.pop(),
+            _ => Some(QueryNode::Or(alternatives)),
+        }
+    }
+
+    /// Normalizes a wildcard pattern, it's expanded once the index is known
+    fn analyze_wildcard(&self, word: &str) -> Option<QueryNode> {
+        let pattern = self.analyzer.analyze_pattern(word)?;
+        Some(QueryNode::Wildcard(pattern))
+    }
+
+    /// Analyzes a `word~distance` fuzzy term, it's expanded once the index is known
+    fn analyze_fuzzy(
+        &self,
+        word: &str,
+        position: usize,
+    ) -> Result<Option<QueryNode>, QueryParseError> {
+        let (word, distance) = word.rsplit_once('~').unwrap();
+        let distance = match distance {
+            "" => None,
+            distance => match distance.parse() {
+                Ok(distance) if distance <= MAX_FUZZY_DISTANCE => Some(distance),
+                _ => {
+                    return Err(QueryParseError {
+                        position: position + word.len(),
+                        message: format!(
+                            "expected a distance of at most {} after ~",
+                            MAX_FUZZY_DISTANCE
+                        ),
+                    })
+                }
+            },
+        };
+
+        let terms = self.analyzer.analyze(word);
+        Ok(and_node(
+            terms
+                .into_iter()
+                .map(|term| {
+                    let distance = distance.unwrap_or_else(|| auto_fuzzy_distance(&term));
+                    QueryNode::Fuzzy(term, distance)
+                })
+                .collect(),
+        ))
+    }
+
+    /// Runs a phrase through the analyzer, keeping the relative position of its terms
+    fn analyze_phrase(&mut self, phrase: &str, negated: bool) -> Option<QueryNode> {
+        let terms = self.analyzer.analyze_with_positions(phrase);
+        if terms.len() < 2 {
+            return self.analyze_word(phrase, negated);
+        }
+
+        let terms = relative_positions(terms);
+        if !negated {
+            self.terms
+                .extend(terms.iter().map(|(term, _)| term.clone()));
+            self.phrases.push(terms.clone());
+            self.highlights
+                .push(phrase.split_whitespace().collect::<Vec<&str>>().join(" "));
+        }
+        let node = QueryNode::Phrase(terms.clone());
+        self.with_synonyms(Some(node), &terms, negated)
+    }
+}
+
+/// Combines the clauses of a sequence, following the `+required` and
+/// `-excluded` modifiers
+///
+/// When there are required clauses the optional ones don't restrict the
+/// matching documents, they only contribute to their score.
+fn combine_clauses(clauses: Vec<(Occur, Option<QueryNode>)>) -> Option<QueryNode> {
+    let mut must = Vec::new();
+    let mut should = Vec::new();
+    let mut must_not = Vec::new();
+    for (occur, node) in clauses {
+        match (occur, node) {
+            (_, None) => {}
+            (Occur::Must, Some(node)) => must.push(node),
+            (Occur::Should, Some(node)) => should.push(node),
+            (Occur::MustNot, Some(node)) => must_not.push(QueryNode::Not(Box::new(node))),
+        }
+    }
+
+    let mut children = if must.is_empty() {
+        match should.len() {
+            0 => Vec::new(),
+            1 => should,
+            _ => vec![QueryNode::Or(should)],
+        }
+    } else {
+        must
+    };
+    children.extend(must_not);
+    and_node(children)
+}
+
+/// Makes the positions of the terms relative to the first one
+fn relative_positions(mut terms: Vec<(String, u32)>) -> Vec<(String, u32)> {
+    let first_position = terms.first().map_or(0, |(_, position)| *position);
+    for (_, position) in terms.iter_mut() {
+        *position -= first_position;
+    }
+    terms
+}
+
+/// The edit distance used for a fuzzy term without an explicit one
+///
+/// Short terms are too easy to turn into unrelated words, so they get fewer edits.
+pub fn auto_fuzzy_distance(term: &str) -> u32 {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => MAX_FUZZY_DISTANCE,
+    }
+}
+
+/// Builds a `NEAR` node, an operand without terms (e.g. only stop words) is dropped
+fn near_node(
+    left: (Occur, Option<QueryNode>),
+    right: (Occur, Option<QueryNode>),
+    distance: u32,
+    operator: &Token,
+) -> Result<Option<QueryNode>, QueryParseError> {
+    match (left, right) {
+        ((Occur::Should, Some(left)), (Occur::Should, Some(right)))
+            if is_positional(&left) && is_positional(&right) =>
+        {
+            Ok(Some(QueryNode::Near(
+                Box::new(left),
+                Box::new(right),
+                distance,
+            )))
+        }
+        ((Occur::Should, None), (Occur::Should, node)) => Ok(node),
+        ((Occur::Should, node), (Occur::Should, None)) => Ok(node),
+        _ => Err(QueryParseError {
+            position: operator.position,
+            message: "NEAR only applies to terms and phrases".to_owned(),
+        }),
+    }
+}
+
+/// Whether the node matches at positions in the documents, a term with
+/// synonyms is an `Or` of its alternatives
+fn is_positional(node: &QueryNode) -> bool {
+    match node {
+        QueryNode::Term(_)
+        | QueryNode::Phrase(_)
+        | QueryNode::Wildcard(_)
+        | QueryNode::Fuzzy(..)
+        | QueryNode::Near(..) => true,
+        QueryNode::Or(children) => children.iter().all(is_positional),
+        _ => false,
+    }
+}
+
+fn and_node(mut children: Vec<QueryNode>) -> Option<QueryNode> {
+    match children.len() {
+        0 => None,
+        1 => children.pop(),
+        _ => Some(QueryNode::And(children)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: &str) -> QueryNode {
+        QueryNode::Term(term.to_owned())
+    }
+
+    fn not(node: QueryNode) -> QueryNode {
+        QueryNode::Not(Box::new(node))
+    }
+
+    /// The structure of the queries is easier to read without stemming
+    fn analyzer() -> Analyzer {
+        Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap()
+    }
+
+    fn parse(query: &str) -> Option<QueryNode> {
+        parse_query(query, &analyzer()).unwrap().root
+    }
+
+    #[test]
+    fn test_implicit_or() {
+        assert_eq!(
+            parse("kubernetes database"),
+            Some(QueryNode::Or(vec![term("kubernetes"), term("database")]))
+        );
+        assert_eq!(
+            parse("kubernetes OR database"),
+            parse("kubernetes database")
+        );
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        assert_eq!(
+            parse("kubernetes OR database AND NOT migration"),
+            Some(QueryNode::Or(vec![
+                term("kubernetes"),
+                QueryNode::And(vec![term("database"), not(term("migration"))]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_grouping() {
+        assert_eq!(
+            parse("(kubernetes OR database) AND Migration"),
+            Some(QueryNode::And(vec![
+                QueryNode::Or(vec![term("kubernetes"), term("database")]),
+                term("migration"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_required_and_excluded() {
+        assert_eq!(
+            parse("+kubernetes cluster -database"),
+            Some(QueryNode::And(vec![
+                term("kubernetes"),
+                not(term("database"))
+            ]))
+        );
+        assert_eq!(
+            parse("kubernetes cluster NOT database"),
+            Some(QueryNode::And(vec![
+                QueryNode::Or(vec![term("kubernetes"), term("cluster")]),
+                not(term("database")),
+            ]))
+        );
+        assert_eq!(parse("-database"), Some(not(term("database"))));
+    }
+
+    #[test]
+    fn test_terms_are_analyzed() {
+        let parsed = parse_query("The Kubernetes! -Database", &analyzer()).unwrap();
+        assert_eq!(
+            parsed.root,
+            Some(QueryNode::And(vec![
+                term("kubernetes"),
+                not(term("database"))
+            ]))
+        );
+        // Negated terms don't contribute to the score
+        assert_eq!(parsed.terms, vec!["kubernetes"]);
+        assert_eq!(parse("the AND a"), None);
+    }
+
+    #[test]
+    fn test_terms_are_stemmed() {
+        let parsed = parse_query("deploying clusters", &Analyzer::new()).unwrap();
+        assert_eq!(parsed.terms, vec!["deploy", "cluster"]);
+        // The language of the query is detected once, not word by word
+        let parsed = parse_query(
+            "Los despliegues de las aplicaciones en el servidor nuevo",
+            &Analyzer::new(),
+        )
+        .unwrap();
+        assert!(parsed.terms.contains(&"desplieg".to_owned()));
+    }
+
+    #[test]
+    fn test_phrases() {
+        let parsed =
+            parse_query("\"Kubernetes in  the Cluster\" -\"blue car\"", &analyzer()).unwrap();
+        assert_eq!(
+            parsed.root,
+            Some(QueryNode::And(vec![
+                QueryNode::Phrase(vec![
+                    ("kubernetes".to_owned(), 0),
+                    ("cluster".to_owned(), 3)
+                ]),
+                not(QueryNode::Phrase(vec![
+                    ("blue".to_owned(), 0),
+                    ("car".to_owned(), 1)
+                ])),
+            ]))
+        );
+        assert_eq!(parsed.terms, vec!["kubernetes", "cluster"]);
+        assert_eq!(
+            parsed.phrases,
+            vec![vec![
+                ("kubernetes".to_owned(), 0),
+                ("cluster".to_owned(), 3)
+            ]]
+        );
+        assert_eq!(parsed.highlights, vec!["Kubernetes in the Cluster"]);
+
+        // A phrase with a single term is a plain term
+        assert_eq!(parse("\"the kubernetes\""), Some(term("kubernetes")));
+    }
+
+    #[test]
+    fn test_near() {
+        let near =
+            |left, right, distance| QueryNode::Near(Box::new(left), Box::new(right), distance);
+        assert_eq!(
+            parse("kubernetes NEAR/3 database"),
+            Some(near(term("kubernetes"), term("database"), 3))
+        );
+        assert_eq!(
+            parse("kubernetes NEAR \"blue car\" AND migration"),
+            Some(QueryNode::And(vec![
+                near(
+                    term("kubernetes"),
+                    QueryNode::Phrase(vec![("blue".to_owned(), 0), ("car".to_owned(), 1)]),
+                    DEFAULT_NEAR_DISTANCE
+                ),
+                term("migration"),
+            ]))
+        );
+        assert_eq!(
+            parse("kubernetes NEAR/2 database NEAR/5 migration"),
+            Some(near(
+                near(term("kubernetes"), term("database"), 2),
+                term("migration"),
+                5
+            ))
+        );
+        // Stop words don't have positions to compare
+        assert_eq!(parse("kubernetes NEAR/2 the"), Some(term("kubernetes")));
+        // NEAR is only an operator in uppercase, otherwise it's a stop word
+        assert_eq!(
+            parse("kubernetes near database"),
+            parse("kubernetes database")
+        );
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let parsed = parse_query("Deploy* -te?t *", &analyzer()).unwrap();
+        assert_eq!(
+            parsed.root,
+            Some(QueryNode::And(vec![
+                QueryNode::Wildcard("deploy*".to_owned()),
+                not(QueryNode::Wildcard("te?t".to_owned())),
+            ]))
+        );
+        assert!(parsed.terms.is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_terms() {
+        let fuzzy = |term: &str, distance| QueryNode::Fuzzy(term.to_owned(), distance);
+        assert_eq!(
+            parse("Kubernets~1 -databse~2"),
+            Some(QueryNode::And(vec![
+                fuzzy("kubernets", 1),
+                not(fuzzy("databse", 2))
+            ]))
+        );
+        assert_eq!(
+            parse("clstr~ db~ kubernets~"),
+            Some(QueryNode::Or(vec![
+                fuzzy("clstr", 1),
+                fuzzy("db", 0),
+                fuzzy("kubernets", 2)
+            ]))
+        );
+        assert_eq!(parse("the~1"), None);
+    }
+
+    #[test]
+    fn test_synonyms() {
+        let mut analyzer = analyzer();
+        let kubernetes = vec![("kubernetes".to_owned(), 0)];
+        let container_orchestration =
+            vec![("container".to_owned(), 0), ("orchestration".to_owned(), 1)];
+        analyzer.synonyms.insert(
+            "k8s".to_owned(),
+            vec![kubernetes.clone(), container_orchestration.clone()],
+        );
+        analyzer
+            .synonyms
+            .insert("container orchestration".to_owned(), vec![kubernetes]);
+
+        let parsed = parse_query("k8s upgrade", &analyzer).unwrap();
+        assert_eq!(
+            parsed.root,
+            Some(QueryNode::Or(vec![
+                QueryNode::Or(vec![
+                    term("k8s"),
+                    term("kubernetes"),
+                    QueryNode::Phrase(container_orchestration.clone()),
+                ]),
+                term("upgrade"),
+            ]))
+        );
+        assert_eq!(parsed.terms, vec!["k8s", "upgrade"]);
+        assert_eq!(parsed.synonyms, vec!["kubernetes"]);
+        assert_eq!(
+            parsed.synonym_phrases,
+            vec![container_orchestration.clone()]
+        );
+        assert_eq!(
+            parsed.highlights,
+            vec!["k8s", "kubernetes", "container", "orchestration", "upgrade"]
+        );
+
+        // Phrases have synonyms too
+        assert_eq!(
+            parse_query("\"container orchestration\"", &analyzer)
+                .unwrap()
+                .root,
+            Some(QueryNode::Or(vec![
+                QueryNode::Phrase(container_orchestration.clone()),
+                term("kubernetes"),
+            ]))
+        );
+
+        // Excluding a term excludes its synonyms
+        let parsed = parse_query("upgrade -k8s", &analyzer).unwrap();
+        assert_eq!(parsed.terms, vec!["upgrade"]);
+        assert!(parsed.synonyms.is_empty());
+
+        // A term with synonyms is still an operand of NEAR
+        assert_eq!(
+            parse_query("k8s NEAR/2 upgrade", &analyzer).unwrap().root,
+            Some(QueryNode::Near(
+                Box::new(QueryNode::Or(vec![
+                    term("k8s"),
+                    term("kubernetes"),
+                    QueryNode::Phrase(container_orchestration),
+                ])),
+                Box::new(term("upgrade")),
+                2
+            ))
+        );
+    }
+
+    #[test]
+    fn test_text_without_spaces_is_a_phrase() {
+        let bigrams = QueryNode::Phrase(vec![
+            ("数据".to_owned(), 0),
+            ("据中".to_owned(), 1),
+            ("中心".to_owned(), 2),
+        ]);
+        let parsed = parse_query("数据中心", &analyzer()).unwrap();
+        assert_eq!(parsed.root, Some(bigrams.clone()));
+        assert_eq!(parsed.terms, vec!["数据", "据中", "中心"]);
+        assert_eq!(
+            parse("数据中心 NEAR/2 kubernetes"),
+            Some(QueryNode::Near(
+                Box::new(bigrams),
+                Box::new(term("kubernetes")),
+                2
+            ))
+        );
+    }
+
+    #[test]
+    fn test_identifiers_match_whole_or_as_a_phrase() {
+        let mut analyzer = analyzer();
+        analyzer.split_identifiers = true;
+        let phrase = |terms: &[&str]| {
+            QueryNode::Phrase(
+                terms
+                    .iter()
+                    .zip(0..)
+                    .map(|(term, position)| (term.to_string(), position))
+                    .collect(),
+            )
+        };
+        let read_file_contents = QueryNode::Or(vec![
+            term("readfilecontents"),
+            phrase(&["read", "file", "contents"]),
+        ]);
+
+        let parsed = parse_query("readFileContents", &analyzer).unwrap();
+        assert_eq!(parsed.root, Some(read_file_contents.clone()));
+        assert_eq!(
+            parsed.terms,
+            vec!["readfilecontents", "read", "file", "contents"]
+        );
+        assert_eq!(
+            parse_query("std::fs::read_dir", &analyzer).unwrap().root,
+            Some(QueryNode::Or(vec![
+                term("stdfsreaddir"),
+                phrase(&["std", "fs", "read", "dir"]),
+            ]))
+        );
+        assert_eq!(
+            parse_query("readFileContents NEAR/1 path", &analyzer)
+                .unwrap()
+                .root,
+            Some(QueryNode::Near(
+                Box::new(read_file_contents),
+                Box::new(term("path")),
+                1
+            ))
+        );
+        // The words after an identifier follow it in both alternatives
+        assert_eq!(
+            parse_query("readFile/path", &analyzer).unwrap().root,
+            Some(QueryNode::Or(vec![
+                QueryNode::Phrase(vec![("readfile".to_owned(), 0), ("path".to_owned(), 2)]),
+                phrase(&["read", "file", "path"]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_hyphen_inside_a_word() {
+        let parsed = parse_query("state-of-the-art", &analyzer()).unwrap();
+        assert_eq!(parsed.terms, vec!["stateoftheart"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errors = [
+            ("kubernetes AND", 11, "expected a term after AND"),
+            ("AND kubernetes", 0, "expected a term before AND"),
+            ("kubernetes OR", 11, "expected a term after OR"),
+            ("kubernetes OR OR database", 11, "expected a term after OR"),
+            ("kubernetes NEAR/2", 11, "expected a term after NEAR"),
+            ("NEAR/2 kubernetes", 0, "expected a term before NEAR"),
+            (
+                "kubernetes NEAR/x database",
+                11,
+                "expected a number of words after NEAR/",
+            ),
+            (
+                "kubernetes NEAR/2 (database AND migration)",
+                11,
+                "NEAR only applies to terms and phrases",
+            ),
+            (
+                "kubernetes NEAR/2 -database",
+                11,
+                "NEAR only applies to terms and phrases",
+            ),
+            (
+                "kubernetes~3",
+                10,
+                "expected a distance of at most 2 after ~",
+            ),
+            (
+                "kubernetes~x",
+                10,
+                "expected a distance of at most 2 after ~",
+            ),
+            ("(kubernetes OR database", 0, "missing closing ')'"),
+            ("kubernetes) database", 10, "unexpected ')'"),
+            ("kubernetes NOT", 11, "expected a term after NOT"),
+            ("kubernetes (-)", 12, "expected a term after -"),
+            ("kubernetes \"blue car", 11, "missing closing '\"'"),
+        ];
+        for (query, position, message) in errors {
+            let error = parse_query(query, &analyzer()).unwrap_err();
+            assert_eq!(
+                error,
+                QueryParseError {
+                    position,
+                    message: message.to_owned()
+                },
+                "query: {:?}",
+                query
+            );
+        }
+    }
+}
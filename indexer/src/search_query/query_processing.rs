@@ -3,68 +3,328 @@ use std::{
     error::Error,
 };
 
-use crate::index_builder::{Document, Index, Term};
+use crate::data_ingestion::Lang;
+use crate::index_builder::{Document, SearchIndex};
 
-use super::SearchResult;
+use super::{
+    query_parser::{auto_fuzzy_distance, parse_query, QueryNode, QueryParseError},
+    scorer::{Bm25Scorer, Scorer},
+    SearchResult,
+};
+
+/// Default maximum number of index terms a wildcard or fuzzy term expands to
+pub const DEFAULT_MAX_EXPANSIONS: usize = 50;
+
+/// How much a synonym of a query term counts in the score, next to the term itself
+pub const SYNONYM_WEIGHT: f64 = 0.8;
+
+/// Settings of a search that don't come from the query itself
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    /// Maximum number of index terms a wildcard or fuzzy term expands to,
+    /// the closest terms and the ones found in more documents are kept
+    pub max_expansions: usize,
+    /// Whether every term also matches the index terms a few edits away
+    pub fuzzy: bool,
+    /// The language the query terms are stemmed in, detected from the query if `None`
+    pub language: Option<Lang>,
+    /// Whether the query terms also match their synonyms
+    pub synonyms: bool,
+    /// Whether the last word of the query also matches the terms starting
+    /// with it, for a query typed in an interactive UI
+    pub prefix: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            max_expansions: DEFAULT_MAX_EXPANSIONS,
+            fuzzy: false,
+            language: None,
+            synonyms: true,
+            prefix: false,
+        }
+    }
+}
 
 pub struct Query {
     pub raw: String,
     pub tokens: Vec<String>,
     pub tf_idf: HashMap<String, f64>,
-}
-
-fn tokenize_query(query: &str) -> Vec<String> {
-    query
-        .split_whitespace()
-        .map(|token| token.to_owned())
-        .collect()
+    pub root: Option<QueryNode>,
+    pub phrases: Vec<Vec<(String, u32)>>,
+    pub highlight_tokens: Vec<String>,
+    /// How much each term counts in the score, fuzzy matches and synonyms count less
+    pub term_weights: HashMap<String, f64>,
+    /// How much each phrase counts in the score, synonyms count less
+    pub phrase_weights: HashMap<Vec<(String, u32)>, f64>,
 }
 
 impl Query {
-    pub fn new(query: &str, index: &Index) -> Self {
-        let tokens = tokenize_query(query);
-        let tf_idf = calculate_query_tfidf(query, index);
-        Query {
+    /// Parses and analyzes the query with the analyzer the index was built
+    /// with, so the query terms are normalized the same way as the indexed terms
+    pub fn new(query: &str, index: &dyn SearchIndex) -> Result<Self, QueryParseError> {
+        Query::with_options(query, index, &SearchOptions::default())
+    }
+
+    /// Same as `new`, wildcards and fuzzy terms are expanded following the
+    /// `options`, and the terms are stemmed in `options.language` if set
+    pub fn with_options(
+        query: &str,
+        index: &dyn SearchIndex,
+        options: &SearchOptions,
+    ) -> Result<Self, QueryParseError> {
+        let mut analyzer = match options.language {
+            Some(language) => index.analyzer().with_language(language),
+            None => index.analyzer().clone(),
+        };
+        if !options.synonyms {
+            analyzer.synonyms.clear();
+        }
+        let mut parsed_query = if options.prefix {
+            parse_query(&with_prefix_word(query), &analyzer)?
+        } else {
+            parse_query(query, &analyzer)?
+        };
+        let mut term_weights: HashMap<String, f64> = parsed_query
+            .terms
+            .iter()
+            .map(|term| (term.clone(), 1.0))
+            .collect();
+        let mut phrase_weights: HashMap<Vec<(String, u32)>, f64> = parsed_query
+            .phrases
+            .iter()
+            .map(|phrase| (phrase.clone(), 1.0))
+            .collect();
+
+        // Synonyms count less than the terms of the query
+        for term in std::mem::take(&mut parsed_query.synonyms) {
+            term_weights.entry(term.clone()).or_insert(SYNONYM_WEIGHT);
+            parsed_query.terms.push(term);
+        }
+        for phrase in std::mem::take(&mut parsed_query.synonym_phrases) {
+            phrase_weights
+                .entry(phrase.clone())
+                .or_insert(SYNONYM_WEIGHT);
+            parsed_query.phrases.push(phrase);
+        }
+
+        // Wildcards and fuzzy terms become the index terms they match
+        let mut expander = TermExpander {
+            index,
+            options,
+            expanded_terms: Vec::new(),
+        };
+        let root = parsed_query.root.map(|root| expander.expand(root, false));
+        for (term, weight) in expander.expanded_terms {
+            let term_weight = term_weights.entry(term.clone()).or_insert(0.0);
+            *term_weight = term_weight.max(weight);
+            parsed_query.terms.push(term.clone());
+            parsed_query.highlights.push(term);
+        }
+
+        let mut tf_idf = calculate_query_tfidf(&parsed_query.terms, index);
+        for (term, value) in tf_idf.iter_mut() {
+            *value *= term_weights[term];
+        }
+        Ok(Query {
             raw: query.to_owned(),
-            tokens,
+            tokens: parsed_query.terms,
             tf_idf,
-        }
+            root,
+            phrases: parsed_query.phrases,
+            highlight_tokens: parsed_query.highlights,
+            term_weights,
+            phrase_weights,
+        })
+    }
+
+    /// Returns how much a term of the query counts in the score
+    pub fn term_weight(&self, term: &str) -> f64 {
+        *self.term_weights.get(term).unwrap_or(&1.0)
+    }
+
+    /// Returns how much a phrase of the query counts in the score
+    pub fn phrase_weight(&self, phrase: &[(String, u32)]) -> f64 {
+        *self.phrase_weights.get(phrase).unwrap_or(&1.0)
     }
 }
 
 // Structure to store the document information and its relevance score
-pub fn search(query: &str, index: &Index) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-    let query = Query::new(query, index);
+pub fn search(query: &str, index: &dyn SearchIndex) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    search_with_scorer(query, index, &Bm25Scorer::default())
+}
+
+/// Searches the index and ranks the matching documents with `scorer`
+pub fn search_with_scorer(
+    query: &str,
+    index: &dyn SearchIndex,
+    scorer: &dyn Scorer,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    search_with_options(query, index, scorer, &SearchOptions::default())
+}
+
+/// Searches the index with `scorer` and the given search options
+pub fn search_with_options(
+    query: &str,
+    index: &dyn SearchIndex,
+    scorer: &dyn Scorer,
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+    index.check_analyzer()?;
+    let query = Query::with_options(query, index, options)?;
     let candidate_documents = retrieve_candidate_documents(&query, index);
-    let mut ranked_documents = rank_documents(&candidate_documents, &query.tf_idf, index);
-    // Sort the documents by score in descending order
-    ranked_documents.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    let mut ranked_documents = rank_documents(&candidate_documents, &query, index, scorer);
+    // Sort the documents by score in descending order, a NaN score from a
+    // corrupted index doesn't stop the search
+    ranked_documents.sort_by(|a, b| b.score.total_cmp(&a.score));
 
-    // Add the query tokens to the ranked documents structure
+    // Add the query tokens to the ranked documents structure, phrases are
+    // kept whole so they are highlighted as a unit
     ranked_documents.iter_mut().for_each(|doc| {
-        doc.query_tokens = query.tokens.clone();
+        doc.query_tokens = query.highlight_tokens.clone();
     });
     Ok(ranked_documents)
 }
 
+/// Replaces the wildcards and fuzzy terms of a query tree with the index terms they match
+struct TermExpander<'a> {
+    index: &'a dyn SearchIndex,
+    options: &'a SearchOptions,
+    /// The terms that are not negated, with their weight in the score
+    expanded_terms: Vec<(String, f64)>,
+}
+
+impl<'a> TermExpander<'a> {
+    /// Expands the terms of a node, `negated` is true under an odd number of `Not`
+    fn expand(&mut self, node: QueryNode, negated: bool) -> QueryNode {
+        match node {
+            QueryNode::Wildcard(pattern) => {
+                let terms = self
+                    .index
+                    .expand_wildcard(&pattern, self.options.max_expansions);
+                self.add_terms(terms.iter().map(|term| (term, 0)), negated);
+                terms_node(terms)
+            }
+            QueryNode::Fuzzy(term, distance) => {
+                let terms = self
+                    .index
+                    .expand_fuzzy(&term, distance, self.options.max_expansions);
+                self.add_terms(
+                    terms.iter().map(|(term, distance)| (term, *distance)),
+                    negated,
+                );
+                terms_node(terms.into_iter().map(|(term, _)| term).collect())
+            }
+            // The exact term is already part of the query, only its variants are added
+            QueryNode::Term(term) if self.options.fuzzy && auto_fuzzy_distance(&term) > 0 => {
+                let distance = auto_fuzzy_distance(&term);
+                let variants: Vec<(String, u32)> = self
+                    .index
+                    .expand_fuzzy(&term, distance, self.options.max_expansions)
+                    .into_iter()
+                    .filter(|(variant, _)| *variant != term)
+                    .collect();
+                self.add_terms(
+                    variants
+                        .iter()
+                        .map(|(variant, distance)| (variant, *distance)),
+                    negated,
+                );
+                let mut terms = vec![term];
+                terms.extend(variants.into_iter().map(|(variant, _)| variant));
+                terms_node(terms)
+            }
+            QueryNode::Near(left, right, distance) => QueryNode::Near(
+                Box::new(self.expand(*left, negated)),
+                Box::new(self.expand(*right, negated)),
+                distance,
+            ),
+            QueryNode::And(children) => QueryNode::And(
+                children
+                    .into_iter()
+                    .map(|child| self.expand(child, negated))
+                    .collect(),
+            ),
+            QueryNode::Or(children) => QueryNode::Or(
+                children
+                    .into_iter()
+                    .map(|child| self.expand(child, negated))
+                    .collect(),
+            ),
+            QueryNode::Not(child) => QueryNode::Not(Box::new(self.expand(*child, !negated))),
+            node => node,
+        }
+    }
+
+    /// Records the terms a node expanded to, with their edit distance to the query
+    fn add_terms<'t>(&mut self, terms: impl Iterator<Item = (&'t String, u32)>, negated: bool) {
+        if !negated {
+            self.expanded_terms
+                .extend(terms.map(|(term, distance)| (term.clone(), fuzzy_weight(distance))));
+        }
+    }
+}
+
+/// A node matching any of the terms, an empty `Or` matches no documents
+fn terms_node(mut terms: Vec<String>) -> QueryNode {
+    match terms.len() {
+        1 => QueryNode::Term(terms.pop().unwrap()),
+        _ => QueryNode::Or(terms.into_iter().map(QueryNode::Term).collect()),
+    }
+}
+
+/// Rewrites the word being typed at the end of a query, like `deploym`, to
+/// `(deploym OR deploym*)`, so it matches the complete words it starts
+///
+/// The query is left unchanged if it doesn't end with a plain word, for
+/// example inside a phrase or after an operator.
+fn with_prefix_word(query: &str) -> String {
+    let word_start = query
+        .char_indices()
+        .rev()
+        .take_while(|(_, character)| character.is_alphanumeric())
+        .last()
+        .map(|(offset, _)| offset);
+    let Some(word_start) = word_start else {
+        return query.to_owned();
+    };
+    let word = &query[word_start..];
+    let before = &query[..word_start];
+    let in_phrase = before.matches('"').count() % 2 == 1;
+    let is_operator = matches!(word, "AND" | "OR" | "NOT" | "NEAR");
+    let follows_word_break = before
+        .chars()
+        .last()
+        .is_none_or(|character| character.is_whitespace() || character == '(');
+    if in_phrase || is_operator || !follows_word_break {
+        return query.to_owned();
+    }
+    format!("{}({} OR {}*)", before, word, word)
+}
+
+/// The weight of a term found `distance` edits away from the query term,
+/// so fuzzy matches score lower than exact ones
+fn fuzzy_weight(distance: u32) -> f64 {
+    1.0 / (1.0 + distance as f64)
+}
+
 /// Function to calculate the query's TF-IDF
 ///
 /// # Arguments
-///  * `query` - The query to calculate the TF-IDF for
+///  * `tokens` - The analyzed query terms to calculate the TF-IDF for
 ///  * `index` - The index to use to calculate the TF-IDF
 ///
 /// # Returns
 ///  * A HashMap containing the TF-IDF for each term in the query
 ///
-pub fn calculate_query_tfidf(query: &str, index: &Index) -> HashMap<String, f64> {
+pub fn calculate_query_tfidf(tokens: &[String], index: &dyn SearchIndex) -> HashMap<String, f64> {
     // Calculate the TF-IDF for each term in the query
     let mut query_tfidf = HashMap::new();
-    let tokens = query.split_whitespace().collect::<Vec<&str>>();
 
     for token in tokens {
-        let term = Term(token.to_owned());
-        let idf = index.idf.get(&term).unwrap_or(&1.0);
-        let count = query_tfidf.entry(token.to_owned()).or_insert(0.0);
+        let idf = index.idf(token).unwrap_or(1.0);
+        let count = query_tfidf.entry(token.clone()).or_insert(0.0);
         *count += 1.0 * idf;
     }
 
@@ -78,102 +338,456 @@ pub fn calculate_query_tfidf(query: &str, index: &Index) -> HashMap<String, f64>
 ///  * `index` - The index to use to retrieve the candidate documents
 ///
 /// # Returns
-///  * A HashSet containing the paths of the candidate documents
-pub fn retrieve_candidate_documents(query: &Query, index: &Index) -> HashSet<Document> {
-    let mut candidate_documents = HashSet::new();
-    for token in &query.tokens {
-        let term = Term(token.to_owned());
-        let docs = index.inverted_index.get(&term);
-        if let Some(docs) = docs {
-            for (doc, _) in docs {
-                candidate_documents.insert(doc.to_owned());
+///  * A HashSet containing the documents that satisfy the query's boolean structure
+pub fn retrieve_candidate_documents(query: &Query, index: &dyn SearchIndex) -> HashSet<Document> {
+    match &query.root {
+        Some(root) => evaluate_query_node(root, index),
+        None => HashSet::new(),
+    }
+}
+
+/// Finds the documents matching a node of the query tree using the postings
+fn evaluate_query_node(node: &QueryNode, index: &dyn SearchIndex) -> HashSet<Document> {
+    match node {
+        QueryNode::Term(token) => index
+            .postings(token)
+            .map(|docs| docs.keys().cloned().collect())
+            .unwrap_or_default(),
+        QueryNode::Phrase(phrase) => phrase_frequencies(phrase, index).into_keys().collect(),
+        // Wildcards and fuzzy terms are expanded into terms when the query is built
+        QueryNode::Wildcard(_) | QueryNode::Fuzzy(..) => HashSet::new(),
+        QueryNode::Near(left, right, _) => {
+            let right_documents = evaluate_query_node(right, index);
+            evaluate_query_node(left, index)
+                .into_iter()
+                .filter(|document| {
+                    right_documents.contains(document)
+                        && !node_spans(node, document, index).is_empty()
+                })
+                .collect()
+        }
+        QueryNode::And(children) => {
+            // Intersect the positive children first, then remove the negated ones
+            let (negated, positive): (Vec<&QueryNode>, Vec<&QueryNode>) = children
+                .iter()
+                .partition(|child| matches!(child, QueryNode::Not(_)));
+            let mut documents = match positive.split_first() {
+                Some((first, rest)) => {
+                    let mut documents = evaluate_query_node(first, index);
+                    for child in rest {
+                        let child_documents = evaluate_query_node(child, index);
+                        documents.retain(|document| child_documents.contains(document));
+                    }
+                    documents
+                }
+                None => index.documents(),
+            };
+            for child in negated {
+                if let QueryNode::Not(child) = child {
+                    for document in evaluate_query_node(child, index) {
+                        documents.remove(&document);
+                    }
+                }
             }
+            documents
+        }
+        QueryNode::Or(children) => children
+            .iter()
+            .flat_map(|child| evaluate_query_node(child, index))
+            .collect(),
+        QueryNode::Not(child) => {
+            let excluded = evaluate_query_node(child, index);
+            index
+                .documents()
+                .into_iter()
+                .filter(|document| !excluded.contains(document))
+                .collect()
         }
     }
-    candidate_documents
+}
+
+/// Counts how many times a phrase appears in each document
+///
+/// # Arguments
+///  * `phrase` - The phrase terms, with their positions relative to the first term
+///  * `index` - The index to look the phrase up in
+///
+/// # Returns
+///  * A HashMap with the number of matches of each document containing the phrase
+pub fn phrase_frequencies(
+    phrase: &[(String, u32)],
+    index: &dyn SearchIndex,
+) -> HashMap<Document, u32> {
+    let mut frequencies = HashMap::new();
+    let first_term = match phrase.first() {
+        Some((term, _)) => term,
+        None => return frequencies,
+    };
+
+    if let Some(documents) = index.postings(first_term) {
+        for document in documents.keys() {
+            let frequency = phrase_starts(phrase, document, index).len() as u32;
+            if frequency > 0 {
+                frequencies.insert(document.clone(), frequency);
+            }
+        }
+    }
+
+    frequencies
+}
+
+/// Finds the positions where a phrase starts in a document
+fn phrase_starts(
+    phrase: &[(String, u32)],
+    document: &Document,
+    index: &dyn SearchIndex,
+) -> Vec<u32> {
+    let positions: Option<Vec<&Vec<u32>>> = phrase
+        .iter()
+        .map(|(term, _)| {
+            index
+                .postings(term)
+                .and_then(|documents| documents.get(document))
+        })
+        .collect();
+    let positions = match positions {
+        Some(positions) if !positions.is_empty() => positions,
+        _ => return Vec::new(),
+    };
+
+    // Positions are stored in increasing order, so we can binary search them
+    positions[0]
+        .iter()
+        .filter(|start| {
+            phrase[1..]
+                .iter()
+                .zip(&positions[1..])
+                .all(|((_, offset), term_positions)| {
+                    term_positions.binary_search(&(*start + offset)).is_ok()
+                })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Finds where a term, phrase or `NEAR` node matches in a document
+///
+/// # Returns
+///  * The first and last position of each match
+fn node_spans(node: &QueryNode, document: &Document, index: &dyn SearchIndex) -> Vec<(u32, u32)> {
+    match node {
+        QueryNode::Term(term) => index
+            .postings(term)
+            .and_then(|documents| documents.get(document))
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|&position| (position, position))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        QueryNode::Phrase(phrase) => {
+            let length = phrase.last().map_or(0, |(_, offset)| *offset);
+            phrase_starts(phrase, document, index)
+                .into_iter()
+                .map(|start| (start, start + length))
+                .collect()
+        }
+        QueryNode::Near(left, right, distance) => {
+            let right_spans = node_spans(right, document, index);
+            let mut spans = Vec::new();
+            for left_span in node_spans(left, document, index) {
+                for right_span in &right_spans {
+                    // Words between the two spans, in whichever order they appear
+                    let gap = if left_span.1 < right_span.0 {
+                        right_span.0 - left_span.1 - 1
+                    } else if right_span.1 < left_span.0 {
+                        left_span.0 - right_span.1 - 1
+                    } else {
+                        0
+                    };
+                    if gap <= *distance {
+                        spans.push((left_span.0.min(right_span.0), left_span.1.max(right_span.1)));
+                    }
+                }
+            }
+            spans.sort_unstable();
+            spans.dedup();
+            spans
+        }
+        // An expanded wildcard used as a NEAR operand
+        QueryNode::Or(children) => {
+            let mut spans: Vec<(u32, u32)> = children
+                .iter()
+                .flat_map(|child| node_spans(child, document, index))
+                .collect();
+            spans.sort_unstable();
+            spans.dedup();
+            spans
+        }
+        _ => Vec::new(),
+    }
 }
 
 /// Function to rank the candidate documents
 ///
 /// # Arguments
 ///  * `candidate_documents` - The candidate documents to rank
-///  * `query_tfidf` - The TF-IDF for the query
+///  * `query` - The query to rank the documents for
 ///  * `index` - The index to use to rank the documents
+///  * `scorer` - The ranking function
 ///
 /// # Returns
 ///  * A vector of `SearchResult`s containing the ranked documents
 fn rank_documents(
     candidate_documents: &HashSet<Document>,
-    query_tfidf: &HashMap<String, f64>,
-    index: &Index,
+    query: &Query,
+    index: &dyn SearchIndex,
+    scorer: &dyn Scorer,
 ) -> Vec<SearchResult> {
-    let mut document_scores: HashMap<Document, f64> = HashMap::new();
-
-    for (term, query_tfidf_value) in query_tfidf.iter() {
-        if let Some(document_frequencies) = index.inverted_index.get(&Term(term.to_string())) {
-            for (document_path, term_frequency) in document_frequencies {
-                if candidate_documents.contains(document_path) {
-                    let idf = index.idf.get(&Term(term.to_string())).unwrap_or(&1.0);
-                    let tf_idf = *term_frequency as f64 * idf;
-
-                    let score = document_scores
-                        .entry(document_path.to_owned())
-                        .or_insert(0.0);
-                    *score += query_tfidf_value * tf_idf;
-                }
-            }
-        }
-    }
+    let document_scores = scorer.score(query, candidate_documents, index);
 
-    // Normalize the scores
-    for (document_path, score) in document_scores.iter_mut() {
-        let document_norm = index.document_norms.get(document_path).unwrap_or(&1.0);
-        let query_norm = query_tfidf
-            .values()
-            .map(|value| value * value)
-            .sum::<f64>()
-            .sqrt();
-
-        *score /= document_norm * query_norm;
-    }
-
-    // Sort the documents by score in descending order
-    let ranked_docs = document_scores
+    // Documents matched only through negations have no terms to score
+    candidate_documents
         .iter()
-        .map(|(document_path, score)| SearchResult::new(document_path.clone(), *score))
-        .collect::<Vec<SearchResult>>();
-
-    ranked_docs
+        .map(|document| {
+            let score = document_scores.get(document).copied().unwrap_or(0.0);
+            SearchResult::new(document.clone(), score)
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::data_ingestion::Analyzer;
+    use crate::index_builder::Index;
+    use crate::search_query::scorer::TfIdfScorer;
 
     #[test]
-    fn test_tokenize_query() {
-        let query = "simple query";
-        let tokens = tokenize_query(query);
-        assert_eq!(tokens, vec!["simple", "query"]);
+    fn test_query_tokens() {
+        let index = Index::new();
+        let query = Query::new("Simple, query!", &index).unwrap();
+        assert_eq!(query.tokens, vec!["simpl", "queri"]);
     }
 
     #[test]
     fn test_calculate_query_tfidf() {
         let index = Index::new();
-        let query = "this is a query";
-        let query_tfidf = calculate_query_tfidf(query, &index);
-        let expected_tfidf: HashMap<String, f64> = [
-            ("this".to_owned(), 1.0),
-            ("is".to_owned(), 1.0),
-            ("a".to_owned(), 1.0),
-            ("query".to_owned(), 1.0),
-        ]
-        .iter()
-        .cloned()
-        .collect();
+        let query = Query::new("this is a query Query", &index).unwrap();
+        let query_tfidf = calculate_query_tfidf(&query.tokens, &index);
+        let expected_tfidf: HashMap<String, f64> =
+            [("queri".to_owned(), 2.0)].iter().cloned().collect();
         assert_eq!(query_tfidf, expected_tfidf);
     }
 
+    #[test]
+    fn test_search_applies_document_analysis_to_query() {
+        let mut index = Index::new();
+        let document = Document {
+            path: "blue_car.txt".to_owned(),
+        };
+        index.store_processed_text_in_index(&document, "This is the sample of a blue car.");
+        index.calculate_idf();
+
+        for query in ["Sample", "SAMPLE", "car!", "the blue car"] {
+            let search_results = search(query, &index).unwrap();
+            assert_eq!(search_results.len(), 1, "no results for {:?}", query);
+            assert_eq!(search_results[0].document, document);
+        }
+
+        // A query made only of stop words has no terms to match
+        assert!(search("the", &index).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_text_without_spaces() {
+        let mut index = Index::new();
+        let chinese = Document::new("chinese.txt".to_owned());
+        let japanese = Document::new("japanese.txt".to_owned());
+        index.store_processed_text_in_index(&chinese, "Deploy to the 上海数据中心 tonight");
+        index.store_processed_text_in_index(&japanese, "東京のデータセンター");
+        index.calculate_idf();
+
+        let results = search("数据中心", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, chinese);
+
+        // The pairs of characters of a word must follow each other
+        let scattered = Document::new("scattered.txt".to_owned());
+        index.store_processed_text_in_index(&scattered, "数据库 据中 中心");
+        index.calculate_idf();
+        let results = search("数据中心", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, chinese);
+
+        let results = search("数据中心 NEAR/1 tonight", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, chinese);
+
+        let results = search("データセンター", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, japanese);
+    }
+
+    #[test]
+    fn test_search_identifiers() {
+        let mut analyzer = Analyzer::new();
+        analyzer.split_identifiers = true;
+        let mut index = Index::with_analyzer(analyzer);
+        let code = Document::new("code.txt".to_owned());
+        let prose = Document::new("prose.txt".to_owned());
+        let scattered = Document::new("scattered.txt".to_owned());
+        index.store_processed_text_in_index(&code, "let text = readFileContents(path);");
+        index.store_processed_text_in_index(&prose, "First read file contents");
+        index.store_processed_text_in_index(&scattered, "The contents of the file I read");
+        index.calculate_idf();
+
+        let documents = |query: &str| -> HashSet<Document> {
+            search(query, &index)
+                .unwrap()
+                .into_iter()
+                .map(|result| result.document)
+                .collect()
+        };
+        // The sub-words of an identifier must follow each other
+        assert_eq!(
+            documents("readFileContents"),
+            [code.clone(), prose].into_iter().collect()
+        );
+        assert_eq!(
+            documents("readFileContents NEAR/0 path"),
+            [code].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_search_matches_other_word_forms() {
+        let mut index = Index::new();
+        let document = Document::new("notes.txt".to_owned());
+        index.store_processed_text_in_index(&document, "The new version was deployed today");
+        index.calculate_idf();
+
+        for query in ["deploying", "deploys", "versions"] {
+            let search_results = search(query, &index).unwrap();
+            assert_eq!(search_results.len(), 1, "no results for {:?}", query);
+        }
+
+        // The language of the query can be set instead of detected
+        let options = SearchOptions {
+            language: Some(Lang::Spa),
+            ..Default::default()
+        };
+        let query = Query::with_options("despliegues", &index, &options).unwrap();
+        assert_eq!(query.tokens, vec!["desplieg"]);
+    }
+
+    #[test]
+    fn test_search_synonyms() {
+        let path = std::env::temp_dir().join("look_test_search_synonyms.txt");
+        std::fs::write(&path, "k8s, kubernetes\npr, pull request\n").unwrap();
+        let mut index = Index::new();
+        index.analyzer.add_synonyms(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let literal = Document::new("literal.txt".to_owned());
+        let synonym = Document::new("synonym.txt".to_owned());
+        let phrase = Document::new("phrase.txt".to_owned());
+        index.store_processed_text_in_index(&literal, "Upgrading k8s clusters");
+        index.store_processed_text_in_index(&synonym, "Upgrading kubernetes clusters");
+        index.store_processed_text_in_index(&phrase, "Opened a pull request");
+        index.calculate_idf();
+
+        // The synonyms match, but lower than the term of the query
+        let search_results = search("k8s", &index).unwrap();
+        let documents: Vec<&Document> = search_results
+            .iter()
+            .map(|result| &result.document)
+            .collect();
+        assert_eq!(documents, vec![&literal, &synonym]);
+        assert!(search_results[0].score > search_results[1].score);
+
+        let search_results = search("pr", &index).unwrap();
+        assert_eq!(search_results.len(), 1);
+        assert_eq!(search_results[0].document, phrase);
+
+        // A term with synonyms is found near another one through any of them
+        let search_results = search("k8s NEAR/0 clusters", &index).unwrap();
+        let documents: HashSet<&Document> = search_results
+            .iter()
+            .map(|result| &result.document)
+            .collect();
+        assert_eq!(documents, [&literal, &synonym].into_iter().collect());
+
+        let options = SearchOptions {
+            synonyms: false,
+            ..Default::default()
+        };
+        let search_results =
+            search_with_options("k8s", &index, &Bm25Scorer::default(), &options).unwrap();
+        assert_eq!(search_results.len(), 1);
+        assert_eq!(search_results[0].document, literal);
+    }
+
+    #[test]
+    fn test_prefix_search() {
+        let mut index = Index::new();
+        let deployment = Document::new("deployment.txt".to_owned());
+        let database = Document::new("database.txt".to_owned());
+        index.enable_edge_ngrams(2, 15);
+        index.store_processed_text_in_index(
+            &deployment,
+            "Rolling deployments of the kubernetes cluster",
+        );
+        index.store_processed_text_in_index(&database, "Deploy the database");
+        index.calculate_idf();
+
+        let options = SearchOptions {
+            prefix: true,
+            ..Default::default()
+        };
+        // The prefixes of the words as written lead to their stems, both
+        // documents hold the stem of "deployments"
+        for query in ["deploym", "deployme", "Deploym"] {
+            let search_results =
+                search_with_options(query, &index, &Bm25Scorer::default(), &options).unwrap();
+            assert_eq!(search_results.len(), 2, "{}", query);
+        }
+        for query in ["kubernete", "rolli"] {
+            let search_results =
+                search_with_options(query, &index, &Bm25Scorer::default(), &options).unwrap();
+            assert_eq!(search_results.len(), 1, "{}", query);
+            assert_eq!(search_results[0].document, deployment, "{}", query);
+        }
+        let search_results =
+            search_with_options("databa", &index, &Bm25Scorer::default(), &options).unwrap();
+        assert_eq!(search_results.len(), 1);
+        assert_eq!(search_results[0].document, database);
+        // A complete word still matches its other forms
+        let search_results =
+            search_with_options("deployments", &index, &Bm25Scorer::default(), &options).unwrap();
+        assert_eq!(search_results.len(), 2);
+        assert!(search("deploym", &index).unwrap().is_empty());
+
+        assert_eq!(
+            with_prefix_word("cluster deploym"),
+            "cluster (deploym OR deploym*)"
+        );
+        assert_eq!(
+            with_prefix_word("(rolling OR deploym"),
+            "(rolling OR (deploym OR deploym*)"
+        );
+        for query in [
+            "\"rolling deploym",
+            "deploy*",
+            "cluster AND",
+            "deploy~1",
+            "cluster ",
+        ] {
+            assert_eq!(with_prefix_word(query), query);
+        }
+    }
+
     #[test]
     fn test_retrieve_candidate_documents() {
         let mut index = Index::new();
@@ -187,13 +801,288 @@ mod tests {
         index.store_processed_text_in_index(&document2, "another example document");
         index.calculate_idf();
 
-        let query = Query::new("sample", &index);
+        let query = Query::new("sample", &index).unwrap();
         let candidate_documents = retrieve_candidate_documents(&query, &index);
 
         let expected_candidates: HashSet<Document> = [document1].iter().cloned().collect();
         assert_eq!(candidate_documents, expected_candidates);
     }
 
+    #[test]
+    fn test_boolean_queries() {
+        let mut index = Index::new();
+        let kubernetes = Document::new("kubernetes.txt".to_owned());
+        let database = Document::new("database.txt".to_owned());
+        let both = Document::new("both.txt".to_owned());
+        index.store_processed_text_in_index(&kubernetes, "kubernetes cluster upgrade");
+        index.store_processed_text_in_index(&database, "database migration");
+        index.store_processed_text_in_index(&both, "kubernetes database operator");
+        index.calculate_idf();
+
+        let documents = |query: &str| -> HashSet<Document> {
+            search(query, &index)
+                .unwrap()
+                .into_iter()
+                .map(|result| result.document)
+                .collect()
+        };
+        let set = |documents: &[&Document]| -> HashSet<Document> {
+            documents
+                .iter()
+                .map(|document| (*document).clone())
+                .collect()
+        };
+
+        assert_eq!(
+            documents("kubernetes database"),
+            set(&[&kubernetes, &database, &both])
+        );
+        assert_eq!(documents("kubernetes AND database"), set(&[&both]));
+        assert_eq!(documents("kubernetes -database"), set(&[&kubernetes]));
+        assert_eq!(
+            documents("+kubernetes database"),
+            set(&[&kubernetes, &both])
+        );
+        assert_eq!(documents("NOT kubernetes"), set(&[&database]));
+        assert_eq!(
+            documents("(upgrade OR migration) AND NOT operator"),
+            set(&[&kubernetes, &database])
+        );
+    }
+
+    #[test]
+    fn test_phrase_search() {
+        let mut index = Index::new();
+        let phrase = Document::new("phrase.txt".to_owned());
+        let apart = Document::new("apart.txt".to_owned());
+        let stop_words = Document::new("stop_words.txt".to_owned());
+        index.store_processed_text_in_index(&phrase, "We rolled back the blue car deployment");
+        index.store_processed_text_in_index(&apart, "The car is blue");
+        index.store_processed_text_in_index(&stop_words, "A blue and a red car");
+        index.calculate_idf();
+
+        let results = search("\"Blue Car\"", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, phrase);
+        assert_eq!(results[0].query_tokens, vec!["Blue Car"]);
+
+        // Removed stop words still count when matching positions
+        let results = search("\"blue and a red car\"", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, stop_words);
+
+        let results = search("car -\"blue car\"", &index).unwrap();
+        let documents: HashSet<Document> = results.into_iter().map(|r| r.document).collect();
+        assert_eq!(documents, [apart, stop_words].into_iter().collect());
+    }
+
+    #[test]
+    fn test_phrase_matches_rank_higher() {
+        let mut index = Index::new();
+        let phrase = Document::new("phrase.txt".to_owned());
+        let apart = Document::new("apart.txt".to_owned());
+        index.store_processed_text_in_index(&phrase, "blue car parked");
+        index.store_processed_text_in_index(&apart, "car parked blue");
+        index.calculate_idf();
+
+        let query = Query::new("blue car \"blue car\"", &index).unwrap();
+        let candidate_documents = retrieve_candidate_documents(&query, &index);
+        for scorer in [&TfIdfScorer as &dyn Scorer, &Bm25Scorer::default()] {
+            let mut results = rank_documents(&candidate_documents, &query, &index, scorer);
+            results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+            assert_eq!(results[0].document, phrase);
+            assert!(results[0].score > results[1].score);
+        }
+    }
+
+    #[test]
+    fn test_nan_scores_are_sorted() {
+        struct NanScorer;
+        impl Scorer for NanScorer {
+            fn score(
+                &self,
+                _query: &Query,
+                candidate_documents: &HashSet<Document>,
+                _index: &dyn SearchIndex,
+            ) -> HashMap<Document, f64> {
+                candidate_documents
+                    .iter()
+                    .map(|document| (document.clone(), f64::NAN))
+                    .collect()
+            }
+        }
+
+        let mut index = Index::new();
+        for path in ["first.txt", "second.txt", "third.txt"] {
+            let document = Document::new(path.to_owned());
+            index.store_processed_text_in_index(&document, "kubernetes cluster");
+        }
+        index.calculate_idf();
+
+        let results = search_with_scorer("kubernetes", &index, &NanScorer).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| result.score.is_nan()));
+    }
+
+    #[test]
+    fn test_near_search() {
+        let mut index = Index::new();
+        let close = Document::new("close.txt".to_owned());
+        let reversed = Document::new("reversed.txt".to_owned());
+        let far = Document::new("far.txt".to_owned());
+        index.store_processed_text_in_index(&close, "kubernetes cluster upgrade went smoothly");
+        index.store_processed_text_in_index(&reversed, "upgrade the kubernetes cluster tomorrow");
+        index.store_processed_text_in_index(
+            &far,
+            &format!("kubernetes {} upgrade", "database ".repeat(20)),
+        );
+        index.calculate_idf();
+
+        let documents = |query: &str| -> HashSet<Document> {
+            search(query, &index)
+                .unwrap()
+                .into_iter()
+                .map(|result| result.document)
+                .collect()
+        };
+        assert_eq!(
+            documents("kubernetes NEAR/1 upgrade"),
+            [close.clone(), reversed.clone()].into_iter().collect()
+        );
+        assert_eq!(
+            documents("cluster NEAR/0 upgrade"),
+            [close.clone()].into_iter().collect()
+        );
+        assert_eq!(
+            documents("kubernetes NEAR/20 upgrade"),
+            [close.clone(), reversed.clone(), far.clone()]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            documents("\"kubernetes cluster\" NEAR/1 upgrade"),
+            [close.clone(), reversed.clone()].into_iter().collect()
+        );
+        assert_eq!(
+            documents("upgrade -(kubernetes NEAR upgrade)"),
+            [far].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_wildcard_search() {
+        let mut index = Index::new();
+        let deployment = Document::new("deployment.txt".to_owned());
+        let deploys = Document::new("deploys.txt".to_owned());
+        let database = Document::new("database.txt".to_owned());
+        index.store_processed_text_in_index(&deployment, "kubernetes deployment notes");
+        index.store_processed_text_in_index(&deploys, "the cluster deploys daily");
+        index.store_processed_text_in_index(&database, "database migration");
+        index.calculate_idf();
+
+        // Wildcards match the stemmed terms of the index
+        let results = search("Deploy*", &index).unwrap();
+        let documents: HashSet<Document> = results.iter().map(|r| r.document.clone()).collect();
+        assert_eq!(
+            documents,
+            [deployment.clone(), deploys.clone()].into_iter().collect()
+        );
+        assert_eq!(results[0].query_tokens, vec!["deploy"]);
+
+        let mut index = Index::with_analyzer(
+            Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap(),
+        );
+        index.store_processed_text_in_index(&deployment, "kubernetes deployment notes");
+        index.store_processed_text_in_index(&deploys, "the cluster deploys daily");
+        index.store_processed_text_in_index(&database, "database migration");
+        index.calculate_idf();
+
+        let results = search("Deploy*", &index).unwrap();
+        let documents: HashSet<Document> = results.iter().map(|r| r.document.clone()).collect();
+        assert_eq!(
+            documents,
+            [deployment.clone(), deploys.clone()].into_iter().collect()
+        );
+        assert_eq!(results[0].query_tokens, vec!["deployment", "deploys"]);
+
+        let query = Query::new("deploy* -kube*", &index).unwrap();
+        assert_eq!(query.tokens, vec!["deployment", "deploys"]);
+        assert_eq!(
+            retrieve_candidate_documents(&query, &index),
+            [deploys.clone()].into_iter().collect()
+        );
+
+        // Only the most common expansions are kept
+        let options = SearchOptions {
+            max_expansions: 1,
+            ..Default::default()
+        };
+        let query = Query::with_options("d*", &index, &options).unwrap();
+        assert_eq!(query.tokens.len(), 1);
+
+        // A wildcard without matches doesn't match any document
+        let results = search("kubernetes AND rollback*", &index).unwrap();
+        assert!(results.is_empty());
+
+        let results = search("cluster NEAR/1 deploy*", &index).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document, deploys);
+    }
+
+    #[test]
+    fn test_fuzzy_search() {
+        // Stemming would merge the typo with the term
+        let mut index = Index::with_analyzer(
+            Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap(),
+        );
+        let exact = Document::new("exact.txt".to_owned());
+        let typo = Document::new("typo.txt".to_owned());
+        let other = Document::new("other.txt".to_owned());
+        index.store_processed_text_in_index(&exact, "kubernetes cluster");
+        index.store_processed_text_in_index(&typo, "kubernets cluster");
+        index.store_processed_text_in_index(&other, "database migration");
+        index.calculate_idf();
+
+        // A misspelled query finds the right term
+        let results = search("kubernetse~2", &index).unwrap();
+        let documents: HashSet<Document> = results.iter().map(|r| r.document.clone()).collect();
+        assert_eq!(
+            documents,
+            [exact.clone(), typo.clone()].into_iter().collect()
+        );
+
+        // Exact matches rank higher than fuzzy ones
+        let results = search("kubernets~1", &index).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].document, typo);
+        assert!(results[0].score > results[1].score);
+        assert_eq!(results[0].query_tokens, vec!["kubernetes", "kubernets"]);
+
+        // With the fuzzy option every term is fuzzy
+        let options = SearchOptions {
+            fuzzy: true,
+            ..Default::default()
+        };
+        let query = Query::with_options("kubernets -databse", &index, &options).unwrap();
+        assert_eq!(query.tokens, vec!["kubernets", "kubernetes"]);
+        assert_eq!(query.term_weight("kubernets"), 1.0);
+        assert_eq!(query.term_weight("kubernetes"), 0.5);
+        assert_eq!(
+            retrieve_candidate_documents(&query, &index),
+            [exact, typo].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_search_with_invalid_query() {
+        let index = Index::new();
+        let error = search("kubernetes AND (database", &index).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid query at position 15: missing closing ')'"
+        );
+    }
+
     #[test]
     fn test_rank_documents() {
         let mut index = Index::new();
@@ -207,18 +1096,19 @@ mod tests {
         index.store_processed_text_in_index(&document2, "another example document");
         index.calculate_idf();
 
-        let query = Query::new("sample document", &index);
+        let query = Query::new("sample document", &index).unwrap();
         let candidate_documents = retrieve_candidate_documents(&query, &index);
 
-        let ranked_docs = rank_documents(&candidate_documents, &query.tf_idf, &index);
+        let mut ranked_docs = rank_documents(&candidate_documents, &query, &index, &TfIdfScorer);
+        ranked_docs.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
 
-        // The first document should have a score of 1.0, and the second document s
-        // hould have a score of 0.0. We will just try to be close to these values
-        let expected_ranked_docs = vec![
-            SearchResult::new(document1.clone(), 1.0),
-            SearchResult::new(document2.clone(), 0.0),
-        ];
-        assert_eq!(ranked_docs, expected_ranked_docs);
+        // The first document has the same terms as the query, so its cosine
+        // similarity is 1.0; the second document only shares "document"
+        assert_eq!(ranked_docs.len(), 2);
+        assert_eq!(ranked_docs[0].document, document1);
+        assert!((ranked_docs[0].score - 1.0).abs() < 1e-9);
+        assert_eq!(ranked_docs[1].document, document2);
+        assert!(ranked_docs[1].score > 0.0 && ranked_docs[1].score < 1.0);
     }
 
     #[test]
@@ -237,11 +1127,11 @@ mod tests {
         let query = "sample document";
         let search_results = search(query, &index).unwrap();
 
-        let expected_search_results = vec![
-            SearchResult::new(document1.clone(), 1.0),
-            SearchResult::new(document2.clone(), 0.0),
-        ];
-        assert_eq!(search_results, expected_search_results);
+        assert_eq!(search_results.len(), 2);
+        assert_eq!(search_results[0].document, document1);
+        assert_eq!(search_results[1].document, document2);
+        assert!(search_results[0].score > search_results[1].score);
+        assert_eq!(search_results[0].query_tokens, vec!["sampl", "document"]);
     }
 
     #[test]
@@ -0,0 +1,392 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use crate::index_builder::{Document, SearchIndex};
+
+use super::query_processing::{phrase_frequencies, Query};
+
+/// A ranking function used to score the candidate documents of a query
+pub trait Scorer {
+    /// Scores each candidate document against the query
+    ///
+    /// # Arguments
+    ///  * `query` - The analyzed query
+    ///  * `candidate_documents` - The documents that matched the query
+    ///  * `index` - The index the documents belong to
+    ///
+    /// # Returns
+    ///  * A HashMap with the score of each candidate document
+    fn score(
+        &self,
+        query: &Query,
+        candidate_documents: &HashSet<Document>,
+        index: &dyn SearchIndex,
+    ) -> HashMap<Document, f64>;
+}
+
+/// Returns the scorer registered under `name`, either "bm25" or "tfidf"
+pub fn get_scorer(name: &str) -> Result<Box<dyn Scorer>, Box<dyn Error>> {
+    match name {
+        "bm25" => Ok(Box::<Bm25Scorer>::default()),
+        "tfidf" => Ok(Box::new(TfIdfScorer)),
+        _ => Err(From::from(format!("Scorer {} is not supported.", name))),
+    }
+}
+
+/// Cosine similarity between the TF-IDF vectors of the query and the document
+pub struct TfIdfScorer;
+
+impl Scorer for TfIdfScorer {
+    fn score(
+        &self,
+        query: &Query,
+        candidate_documents: &HashSet<Document>,
+        index: &dyn SearchIndex,
+    ) -> HashMap<Document, f64> {
+        let mut document_scores: HashMap<Document, f64> = HashMap::new();
+
+        for (term, query_tfidf_value) in query.tf_idf.iter() {
+            if let Some(document_frequencies) = index.postings(term) {
+                let idf = index.idf(term).unwrap_or(1.0);
+                for (document, positions) in document_frequencies {
+                    if candidate_documents.contains(document) {
+                        let tf_idf = positions.len() as f64 * idf;
+                        let score = document_scores.entry(document.to_owned()).or_insert(0.0);
+                        *score += query_tfidf_value * tf_idf;
+                    }
+                }
+            }
+        }
+
+        // Each phrase is an extra dimension of both vectors
+        let mut query_norm = query
+            .tf_idf
+            .values()
+            .map(|value| value * value)
+            .sum::<f64>();
+        let mut phrase_norms: HashMap<Document, f64> = HashMap::new();
+        for phrase in &query.phrases {
+            let frequencies = phrase_frequencies(phrase, index);
+            if frequencies.is_empty() {
+                continue;
+            }
+            let idf = (index.num_docs() as f64 / frequencies.len() as f64).ln() + 1.0;
+            let query_tfidf_value = query.phrase_weight(phrase) * idf;
+            query_norm += query_tfidf_value * query_tfidf_value;
+            for (document, frequency) in frequencies {
+                if candidate_documents.contains(&document) {
+                    let tf_idf = frequency as f64 * idf;
+                    *phrase_norms.entry(document.clone()).or_insert(0.0) += tf_idf * tf_idf;
+                    *document_scores.entry(document).or_insert(0.0) += query_tfidf_value * tf_idf;
+                }
+            }
+        }
+
+        // Normalize the scores by the length of both vectors
+        let query_norm = query_norm.sqrt();
+        for (document, score) in document_scores.iter_mut() {
+            let document_norm = index.document_norm(document).unwrap_or(1.0);
+            let phrase_norm = phrase_norms.get(document).unwrap_or(&0.0);
+            let norm = (document_norm * document_norm + phrase_norm).sqrt() * query_norm;
+            *score = if norm > 0.0 { *score / norm } else { 0.0 };
+        }
+
+        document_scores
+    }
+}
+
+/// Okapi BM25
+///
+/// `k1` controls how quickly repeated terms saturate and `b` how much the
+/// score is normalized by the document length relative to the average.
+pub struct Bm25Scorer {
+    pub k1: f64,
+    pub b: f64,
+}
+
+impl Default for Bm25Scorer {
+    fn default() -> Self {
+        Bm25Scorer { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25Scorer {
+    pub fn new(k1: f64, b: f64) -> Bm25Scorer {
+        Bm25Scorer { k1, b }
+    }
+
+    /// The BM25 IDF, which is always positive even for terms in every document
+    fn idf(num_docs: usize, document_frequency: usize) -> f64 {
+        let num_docs = num_docs as f64;
+        let document_frequency = document_frequency as f64;
+        (1.0 + (num_docs - document_frequency + 0.5) / (document_frequency + 0.5)).ln()
+    }
+
+    /// The saturated term frequency, normalized by the document length
+    /// relative to `average_length`, computed once per search
+    fn term_frequency(
+        &self,
+        frequency: f64,
+        document: &Document,
+        average_length: f64,
+        index: &dyn SearchIndex,
+    ) -> f64 {
+        let length = index.document_length(document).unwrap_or(0) as f64;
+        let length_norm = if average_length > 0.0 {
+            1.0 - self.b + self.b * length / average_length
+        } else {
+            1.0
+        };
+        frequency * (self.k1 + 1.0) / (frequency + self.k1 * length_norm)
+    }
+}
+
+impl Scorer for Bm25Scorer {
+    fn score(
+        &self,
+        query: &Query,
+        candidate_documents: &HashSet<Document>,
+        index: &dyn SearchIndex,
+    ) -> HashMap<Document, f64> {
+        let mut document_scores: HashMap<Document, f64> = HashMap::new();
+        let average_length = index.average_document_length();
+
+        // Repeated query terms count once per occurrence
+        let mut query_term_counts: HashMap<&String, f64> = HashMap::new();
+        for token in &query.tokens {
+            *query_term_counts.entry(token).or_insert(0.0) += 1.0;
+        }
+
+        for (term, query_term_count) in query_term_counts {
+            if let Some(document_frequencies) = index.postings(term) {
+                let idf = Bm25Scorer::idf(index.num_docs(), document_frequencies.len());
+                for (document, positions) in document_frequencies {
+                    if !candidate_documents.contains(document) {
+                        continue;
+                    }
+                    let tf = self.term_frequency(
+                        positions.len() as f64,
+                        document,
+                        average_length,
+                        index,
+                    );
+                    let score = document_scores.entry(document.to_owned()).or_insert(0.0);
+                    *score += query_term_count * query.term_weight(term) * idf * tf;
+                }
+            }
+        }
+
+        // Phrases are scored as if they were a single term
+        for phrase in &query.phrases {
+            let frequencies = phrase_frequencies(phrase, index);
+            let idf = Bm25Scorer::idf(index.num_docs(), frequencies.len());
+            for (document, frequency) in frequencies {
+                if candidate_documents.contains(&document) {
+                    let tf =
+                        self.term_frequency(frequency as f64, &document, average_length, index);
+                    *document_scores.entry(document).or_insert(0.0) +=
+                        query.phrase_weight(phrase) * idf * tf;
+                }
+            }
+        }
+
+        document_scores
+    }
+}
+
+/// Boosts the documents where the query terms appear close together
+///
+/// Wraps another scorer and multiplies its scores by
+/// `1 + weight * matched terms / window`, where the window is the smallest
+/// span of words containing every query term found in the document. Documents
+/// with fewer than two of the terms keep their score.
+pub struct ProximityScorer {
+    pub scorer: Box<dyn Scorer>,
+    pub weight: f64,
+}
+
+impl ProximityScorer {
+    pub fn new(scorer: Box<dyn Scorer>) -> ProximityScorer {
+        ProximityScorer {
+            scorer,
+            weight: 0.5,
+        }
+    }
+}
+
+impl Scorer for ProximityScorer {
+    fn score(
+        &self,
+        query: &Query,
+        candidate_documents: &HashSet<Document>,
+        index: &dyn SearchIndex,
+    ) -> HashMap<Document, f64> {
+        let mut terms: Vec<&String> = query.tokens.iter().collect();
+        terms.sort();
+        terms.dedup();
+
+        let mut document_scores = self.scorer.score(query, candidate_documents, index);
+        for (document, score) in document_scores.iter_mut() {
+            if let Some((matched_terms, window)) = minimum_window(&terms, document, index) {
+                *score *= 1.0 + self.weight * matched_terms as f64 / window as f64;
+            }
+        }
+        document_scores
+    }
+}
+
+/// Finds the smallest span of words that contains every term present in a document
+///
+/// # Returns
+///  * The number of terms present and the length of the span, `None` if
+///    fewer than two of the terms are in the document
+fn minimum_window(
+    terms: &[&String],
+    document: &Document,
+    index: &dyn SearchIndex,
+) -> Option<(usize, u32)> {
+    let mut occurrences: Vec<(u32, usize)> = Vec::new();
+    let mut matched_terms = 0;
+    for (term_index, term) in terms.iter().enumerate() {
+        let positions = index
+            .postings(term)
+            .and_then(|documents| documents.get(document));
+        if let Some(positions) = positions {
+            matched_terms += 1;
+            occurrences.extend(positions.iter().map(|&position| (position, term_index)));
+        }
+    }
+    if matched_terms < 2 {
+        return None;
+    }
+    occurrences.sort_unstable();
+
+    // Slide a window over the occurrences, shrinking it from the left while
+    // it still contains every term
+    let mut counts = vec![0; terms.len()];
+    let mut covered = 0;
+    let mut start = 0;
+    let mut window = u32::MAX;
+    for &(position, term_index) in &occurrences {
+        counts[term_index] += 1;
+        if counts[term_index] == 1 {
+            covered += 1;
+        }
+        while covered == matched_terms {
+            let (start_position, start_term) = occurrences[start];
+            window = window.min(position - start_position + 1);
+            counts[start_term] -= 1;
+            if counts[start_term] == 0 {
+                covered -= 1;
+            }
+            start += 1;
+        }
+    }
+
+    Some((matched_terms, window))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::Index;
+
+    fn build_index(documents: &[(&str, &str)]) -> Index {
+        let mut index = Index::new();
+        for (path, text) in documents {
+            index.store_processed_text_in_index(&Document::new(path.to_string()), text);
+        }
+        index.calculate_idf();
+        index
+    }
+
+    fn score_all(scorer: &dyn Scorer, query: &str, index: &Index) -> HashMap<Document, f64> {
+        let query = Query::new(query, index).unwrap();
+        let candidates = index.document_lengths.keys().cloned().collect();
+        scorer.score(&query, &candidates, index)
+    }
+
+    #[test]
+    fn test_get_scorer() {
+        assert!(get_scorer("bm25").is_ok());
+        assert!(get_scorer("tfidf").is_ok());
+        assert!(get_scorer("pagerank").is_err());
+    }
+
+    #[test]
+    fn test_tfidf_identical_document_scores_one() {
+        let index = build_index(&[
+            ("doc1.txt", "sample document"),
+            ("doc2.txt", "another example document"),
+        ]);
+        let scores = score_all(&TfIdfScorer, "sample document", &index);
+        let score = scores[&Document::new("doc1.txt".to_owned())];
+        assert!((score - 1.0).abs() < 1e-9);
+        assert!(scores[&Document::new("doc2.txt".to_owned())] < score);
+    }
+
+    #[test]
+    fn test_bm25_prefers_shorter_documents() {
+        let long_note = format!("kubernetes {}", "cluster deployment ".repeat(200));
+        let index = build_index(&[
+            ("short.txt", "kubernetes cluster"),
+            ("long.txt", &long_note),
+            ("other.txt", "database migration"),
+        ]);
+        let scores = score_all(&Bm25Scorer::default(), "kubernetes", &index);
+        let short = scores[&Document::new("short.txt".to_owned())];
+        let long = scores[&Document::new("long.txt".to_owned())];
+        assert!(short > long);
+        assert!(!scores.contains_key(&Document::new("other.txt".to_owned())));
+    }
+
+    #[test]
+    fn test_proximity_boosts_close_terms() {
+        let index = build_index(&[
+            ("close.txt", "kubernetes cluster database migration"),
+            ("apart.txt", "kubernetes database migration cluster"),
+            ("single.txt", "kubernetes database migration"),
+        ]);
+        let scores = score_all(&Bm25Scorer::default(), "kubernetes cluster", &index);
+        let proximity = ProximityScorer::new(Box::<Bm25Scorer>::default());
+        let boosted = score_all(&proximity, "kubernetes cluster", &index);
+
+        let close = Document::new("close.txt".to_owned());
+        let apart = Document::new("apart.txt".to_owned());
+        let single = Document::new("single.txt".to_owned());
+        assert!((scores[&close] - scores[&apart]).abs() < 1e-9);
+        assert!((boosted[&close] - scores[&close] * 1.5).abs() < 1e-9);
+        assert!((boosted[&apart] - scores[&apart] * 1.25).abs() < 1e-9);
+        assert_eq!(boosted[&single], scores[&single]);
+    }
+
+    #[test]
+    fn test_minimum_window() {
+        let index = build_index(&[(
+            "doc.txt",
+            "kubernetes database cluster migration kubernetes cluster database",
+        )]);
+        let document = Document::new("doc.txt".to_owned());
+        let terms = ["kubernet", "cluster", "databas", "deploy"].map(str::to_owned);
+        let terms: Vec<&String> = terms.iter().collect();
+        assert_eq!(minimum_window(&terms, &document, &index), Some((3, 3)));
+        assert_eq!(minimum_window(&terms[..1], &document, &index), None);
+    }
+
+    #[test]
+    fn test_bm25_term_frequency_saturates() {
+        let index = build_index(&[
+            ("once.txt", "kubernetes cluster cluster cluster"),
+            ("many.txt", "kubernetes kubernetes kubernetes kubernetes"),
+        ]);
+        let scorer = Bm25Scorer::new(1.2, 0.0);
+        let scores = score_all(&scorer, "kubernetes", &index);
+        let once = scores[&Document::new("once.txt".to_owned())];
+        let many = scores[&Document::new("many.txt".to_owned())];
+        assert!(many > once);
+        // With b = 0 the term frequency part can never exceed k1 + 1
+        assert!(many / once < 2.2);
+    }
+}
@@ -0,0 +1,250 @@
+use std::ops::Range;
+
+use crate::data_ingestion::{Analyzer, Lang};
+use crate::index_builder::SearchIndex;
+
+use super::query_parser::auto_fuzzy_distance;
+
+/// Number of alternatives looked up for each misspelled word
+const CANDIDATES_PER_WORD: usize = 5;
+
+/// The alternatives of a misspelled word of the query
+struct Correction {
+    /// Position of the word in the query
+    word: usize,
+    /// Byte range of the term in the word
+    range: Range<usize>,
+    /// The alternative words with their score, the best first
+    candidates: Vec<(String, f64)>,
+}
+
+/// Function to suggest spelling corrections for a query
+///
+/// Each word of the query whose term isn't in the index is replaced by a
+/// close term of the index, written as a word with the ending of the
+/// misspelled one. The other words are kept as typed. Terms a few edits
+/// away are ranked by their edit distance, weighted by how many documents
+/// contain them, so a common term two edits away can beat a rare one a
+/// single edit away. Operators, modifiers and wildcards are kept as they
+/// are.
+///
+/// # Arguments
+///  * `query` - The raw query
+///  * `index` - The index the vocabulary comes from
+///  * `max_suggestions` - The maximum number of suggestions to return
+///
+/// # Returns
+///  * The corrected queries, the most likely first, empty if every word is in the index
+pub fn suggest_queries(
+    query: &str,
+    index: &dyn SearchIndex,
+    max_suggestions: usize,
+) -> Vec<String> {
+    let words: Vec<&str> = query.split_whitespace().collect();
+    // The words are too short to detect their language one by one
+    let analyzer = index
+        .analyzer()
+        .with_language(index.analyzer().detect_language(query));
+
+    let corrections: Vec<Correction> = words
+        .iter()
+        .enumerate()
+        .filter_map(|(i, word)| correct_word(i, word, index, &analyzer))
+        .collect();
+    if corrections.is_empty() {
+        return Vec::new();
+    }
+
+    // The best candidate for every word, then the variations that change a single word
+    let best: Vec<usize> = vec![0; corrections.len()];
+    let mut choices = vec![best.clone()];
+    for (word, correction) in corrections.iter().enumerate() {
+        for candidate in 1..correction.candidates.len() {
+            let mut choice = best.clone();
+            choice[word] = candidate;
+            choices.push(choice);
+        }
+    }
+
+    let score = |choice: &Vec<usize>| -> f64 {
+        choice
+            .iter()
+            .zip(&corrections)
+            .map(|(&candidate, correction)| correction.candidates[candidate].1)
+            .product()
+    };
+    choices.sort_by(|a, b| score(b).total_cmp(&score(a)));
+
+    choices
+        .iter()
+        .take(max_suggestions)
+        .map(|choice| {
+            let mut corrected: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+            for (&candidate, correction) in choice.iter().zip(&corrections) {
+                let word = words[correction.word];
+                corrected[correction.word] = format!(
+                    "{}{}{}",
+                    &word[..correction.range.start],
+                    correction.candidates[candidate].0,
+                    &word[correction.range.end..]
+                );
+            }
+            corrected.join(" ")
+        })
+        .collect()
+}
+
+/// Finds the alternatives of the `i`th word of the query, `None` if its
+/// term is in the index or it isn't a term
+fn correct_word(
+    i: usize,
+    word: &str,
+    index: &dyn SearchIndex,
+    analyzer: &Analyzer,
+) -> Option<Correction> {
+    // Wildcards aren't spelled out, and operators aren't terms
+    if word.contains(['*', '?'])
+        || matches!(word, "AND" | "OR" | "NOT" | "NEAR")
+        || word.starts_with("NEAR/")
+    {
+        return None;
+    }
+
+    // Keep the quotes, parentheses, modifiers and fuzzy suffix around the term
+    let start = word.find(char::is_alphanumeric)?;
+    let end = word
+        .char_indices()
+        .filter(|(_, character)| character.is_alphanumeric())
+        .map(|(i, character)| i + character.len_utf8())
+        .next_back()?;
+    let end = match word[start..].find('~') {
+        Some(tilde) => end.min(start + tilde),
+        None => end,
+    };
+
+    let language = analyzer.detect_language(&word[start..end]);
+    let terms = analyzer.analyze_words(&word[start..end], language);
+    let (term, typed_word) = match terms.as_slice() {
+        [(term, _, typed_word)] => (term, typed_word),
+        _ => return None,
+    };
+    if index.document_frequency(term).is_some() {
+        return None;
+    }
+
+    let max_distance = auto_fuzzy_distance(term).max(1);
+    let mut candidates: Vec<(String, f64)> = index
+        .expand_fuzzy(term, max_distance, usize::MAX)
+        .into_iter()
+        .map(|(candidate, distance)| {
+            let document_frequency = index.document_frequency(&candidate).unwrap_or(0);
+            let score = (1.0 + document_frequency as f64).ln() / (1.0 + distance as f64).powi(2);
+            (candidate, score)
+        })
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    candidates.truncate(CANDIDATES_PER_WORD);
+    for (candidate, _) in candidates.iter_mut() {
+        *candidate = candidate_word(candidate, term, typed_word, analyzer, language);
+    }
+
+    Some(Correction {
+        word: i,
+        range: start..end,
+        candidates,
+    })
+}
+
+/// Writes a candidate term as a word, with the ending the stemming removed
+/// from the misspelled word, e.g. the term "kubernet" for "kubrnetes" becomes
+/// "kubernetes"
+///
+/// # Returns
+///  * The word, or the term itself if the word isn't analyzed into it
+fn candidate_word(
+    candidate: &str,
+    term: &str,
+    typed_word: &str,
+    analyzer: &Analyzer,
+    language: Lang,
+) -> String {
+    if let Some(ending) = typed_word.strip_prefix(term) {
+        let word = format!("{}{}", candidate, ending);
+        let terms = analyzer.analyze_with_language(&word, language);
+        if matches!(terms.as_slice(), [(analyzed, _)] if analyzed == candidate) {
+            return word;
+        }
+    }
+    candidate.to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index_builder::{Document, Index};
+
+    fn build_index(documents: &[(&str, &str)]) -> Index {
+        build_index_with_analyzer(documents, Analyzer::new())
+    }
+
+    fn build_index_with_analyzer(documents: &[(&str, &str)], analyzer: Analyzer) -> Index {
+        let mut index = Index::with_analyzer(analyzer);
+        for (path, text) in documents {
+            index.store_processed_text_in_index(&Document::new(path.to_string()), text);
+        }
+        index.calculate_idf();
+        index
+    }
+
+    #[test]
+    fn test_suggest_queries() {
+        let index = build_index(&[
+            ("notes.txt", "kubernetes cluster upgrade"),
+            ("more.txt", "kubernetes deployment upgrade"),
+            ("other.txt", "database migration"),
+        ]);
+
+        // The terms of the index are written with the ending of the misspelled word
+        assert_eq!(
+            suggest_queries("kubrnetes upgrade", &index, 3),
+            vec!["kubernetes upgrade"]
+        );
+        assert_eq!(
+            suggest_queries("+Kubrnetes AND -\"databse\"", &index, 3),
+            vec!["+kubernetes AND -\"database\""]
+        );
+        assert_eq!(
+            suggest_queries("kubrnetes~1 upgrad*", &index, 3),
+            vec!["kubernetes~1 upgrad*"]
+        );
+        assert_eq!(
+            suggest_queries("upgrding clusters", &index, 3),
+            vec!["upgrading clusters"]
+        );
+        // Other forms of the indexed words are found through stemming
+        assert!(suggest_queries("kubernetes upgrades", &index, 3).is_empty());
+        assert!(suggest_queries("xylophone", &index, 3).is_empty());
+    }
+
+    #[test]
+    fn test_common_terms_are_suggested_first() {
+        let analyzer = Analyzer::from_pipeline("lowercase,stop_words,ascii_fold").unwrap();
+        let index = build_index_with_analyzer(
+            &[
+                ("one.txt", "cluster"),
+                ("two.txt", "cluster"),
+                ("three.txt", "cluster"),
+                ("four.txt", "clusters"),
+            ],
+            analyzer,
+        );
+
+        assert_eq!(
+            suggest_queries("clusterz", &index, 3),
+            vec!["cluster", "clusters"]
+        );
+    }
+}